@@ -32,8 +32,13 @@ EOF
         wrk_output=$(wrk -t"$threads" -c"$conns" -d"${duration}s" --latency "$url" 2>&1)
     fi
     
-    # Parse output
-    echo "$wrk_output" | awk '
+    echo "$wrk_output" | _wrk_stats
+}
+
+# Parse wrk --latency output on stdin
+# Output: "requests errors rps min_ms p1_ms p50_ms p90_ms p99_ms max_ms avg_ms"
+_wrk_stats() {
+    awk '
     function to_ms(val) {
         if (val ~ /us$/) { gsub(/us$/, "", val); return val / 1000 }
         if (val ~ /ms$/) { gsub(/ms$/, "", val); return val + 0 }
@@ -90,6 +95,153 @@ EOF
     }'
 }
 
+# ─────────────────────────────────────────────────────────────────────────────
+# Pipelining
+# ─────────────────────────────────────────────────────────────────────────────
+
+# Run wrk until the given number of responses has arrived, sending `depth`
+# GET requests back to back on each connection before reading any of them.
+# A depth of 1 is plain sequential keep-alive. wrk records one latency sample
+# per batch, from the first byte written to the last response read
+# Args: $1=url $2=requests $3=depth $4=connections $5=threads $6=max_duration
+# Output: same as bench_wrk
+bench_pipeline() {
+    local url=$1 requests=$2 depth=$3 conns=${4:-10} threads=${5:-2} duration=${6:-60}
+    local lua_script=$(mktemp --suffix=.lua)
+
+    # Each thread stops itself once it has its share of the responses
+    cat > "$lua_script" <<'EOF'
+local limit, done, batch = 0, 0, ""
+
+function init(args)
+    limit = tonumber(args[1])
+    local reqs = {}
+    for i = 1, tonumber(args[2]) do reqs[i] = wrk.format() end
+    batch = table.concat(reqs)
+end
+
+function request() return batch end
+
+function response()
+    done = done + 1
+    if done >= limit then wrk.thread:stop() end
+end
+EOF
+
+    local per_thread=$(( (requests + threads - 1) / threads ))
+    wrk -t"$threads" -c"$conns" -d"${duration}s" --latency -s "$lua_script" "$url" \
+        -- "$per_thread" "$depth" 2>&1 | _wrk_stats
+    rm -f "$lua_script"
+}
+
+# ─────────────────────────────────────────────────────────────────────────────
+# Connections per Second
+# ─────────────────────────────────────────────────────────────────────────────
+
+# Run wrk with one request per connection, so every request pays for a TCP
+# handshake, an accept and a close on both sides
+# Args: $1=url $2=duration $3=connections $4=threads
+# Output: "connections conns_per_sec connect_errors other_errors p50_ms p99_ms"
+bench_conn_per_sec() {
+    local url=$1 duration=${2:-5} conns=${3:-10} threads=${4:-2}
+    
+    # wrk reconnects after every response that carries Connection: close,
+    # and servers echo that back when the client asks for it
+    wrk -t"$threads" -c"$conns" -d"${duration}s" --latency -H "Connection: close" "$url" 2>&1 | awk '
+    function to_ms(val) {
+        if (val ~ /us$/) { gsub(/us$/, "", val); return val / 1000 }
+        if (val ~ /ms$/) { gsub(/ms$/, "", val); return val + 0 }
+        if (val ~ /s$/)  { gsub(/s$/, "", val); return val * 1000 }
+        return val + 0
+    }
+    
+    BEGIN { reqs=0; cps=0; connect=0; other=0; p50=0; p99=0 }
+    
+    /unable to connect|Connection refused/ { exit }
+    
+    /50%/ { p50 = to_ms($2) }
+    /99%/ { p99 = to_ms($2) }
+    /requests in/ { reqs = $1 + 0 }
+    
+    # "Socket errors: connect 3, read 0, write 0, timeout 0"
+    /Socket errors/ {
+        for (i = 3; i < NF; i += 2) {
+            n = $(i + 1); gsub(/,/, "", n)
+            if ($i == "connect") connect += n; else other += n
+        }
+    }
+    /Non-2xx/ { other += $3 + 0 }
+    
+    /Requests\/sec:/ { cps = $2 + 0 }
+    
+    END { printf "%d %.2f %d %d %.2f %.2f\n", reqs, cps, connect, other, p50, p99 }'
+}
+
+# ─────────────────────────────────────────────────────────────────────────────
+# Slowloris
+# ─────────────────────────────────────────────────────────────────────────────
+
+# Hold one connection open by trickling header lines that never finish
+# Output: "closed <ms>" when the server hangs up, "open <ms>" if it never did,
+#         "failed 0" if the connection couldn't be made
+_slowloris_conn() {
+    local port=$1 duration=$2 interval=$3
+    trap '' PIPE
+    local start=$(date +%s%N)
+    local deadline=$(( start + duration * 1000000000 ))
+
+    exec 3<>"/dev/tcp/127.0.0.1/$port" 2>/dev/null || { echo "failed 0"; return; }
+    printf 'GET / HTTP/1.1\r\nHost: localhost\r\n' >&3 2>/dev/null
+
+    while (( $(date +%s%N) < deadline )); do
+        sleep "$interval"
+        # Readable means the server answered (e.g. 408) or closed on us
+        if read -r -t 0 -u 3 || ! printf 'X-Slow: 1\r\n' >&3 2>/dev/null; then
+            echo "closed $(( ($(date +%s%N) - start) / 1000000 ))"
+            exec 3<&-
+            return
+        fi
+    done
+    echo "open $(( ($(date +%s%N) - start) / 1000000 ))"
+    exec 3<&-
+}
+
+# Run a slowloris attack and probe the server with a normal request midway
+# Args: $1=port $2=connections $3=duration(s) $4=interval(s) $5=work_dir
+# Output: "opened failed closed still_open min_ms avg_ms max_ms probe_ms"
+#         (probe_ms is -1 if the probe got no answer within 2s)
+bench_slowloris() {
+    local port=$1 conns=$2 duration=$3 interval=$4 dir=$5
+    local -a pids=()
+
+    for ((i = 0; i < conns; i++)); do
+        _slowloris_conn "$port" "$duration" "$interval" >"$dir/slowloris_$i" &
+        pids+=($!)
+    done
+
+    # Give the trickle a head start, then see if real clients still get through
+    sleep $(( duration / 2 > 0 ? duration / 2 : 1 ))
+    local probe_start=$(date +%s%N) probe_ms=-1
+    if curl -sf -o /dev/null --max-time 2 "http://localhost:$port/" 2>/dev/null; then
+        probe_ms=$(( ($(date +%s%N) - probe_start) / 1000000 ))
+    fi
+
+    wait "${pids[@]}" 2>/dev/null
+    cat "$dir"/slowloris_* | awk -v conns="$conns" -v probe="$probe_ms" '
+        $1 == "failed" { failed++ }
+        $1 == "open"   { open++ }
+        $1 == "closed" {
+            closed++; sum += $2
+            if (min == "" || $2 < min) min = $2
+            if ($2 > max) max = $2
+        }
+        END {
+            avg = closed ? sum / closed : 0
+            printf "%d %d %d %d %d %d %d %d\n", conns - failed, failed, closed, open, min + 0, avg, max + 0, probe
+        }'
+    rm -f "$dir"/slowloris_*
+}
+
 # Warmup: single request to prime server
 bench_warmup() {
     local url=$1
@@ -60,6 +60,160 @@ fmt_num() {
     printf "%'d" "${1:-0}" 2>/dev/null || echo "${1:-0}"
 }
 
+# Percent change from $1 to $2, e.g. "+12.5%"
+fmt_pct() {
+    awk -v a="$1" -v b="$2" 'BEGIN { if (a > 0) printf "%+.1f%%", (b - a) * 100 / a; else print "n/a" }'
+}
+
+# ─────────────────────────────────────────────────────────────────────────────
+# Connections-per-Second Mode
+# ─────────────────────────────────────────────────────────────────────────────
+
+# Args: $1=port $2=path $3=connections $4=duration $5=threads
+run_conn_per_sec() {
+    local port=$1 path=$2 conns=$3 duration=$4 threads=$5
+    
+    info "\n🔌 Connections-per-Second Config"
+    info "   Connections: $conns | Threads: $threads | Duration: ${duration}s | Path: $path"
+    info "   One request per connection (Connection: close)"
+    
+    info "\n⏳ Warming up..."
+    bench_warmup "http://localhost:$port/"
+    sleep 0.5
+    
+    local result=$(bench_conn_per_sec "http://localhost:$port$path" "$duration" "$conns" "$threads")
+    read total cps connect_errs other_errs p50 p99 <<< "$result"
+    
+    header "🔌 Connection Results"
+    echo "   Connections completed: $(fmt_num $total)"
+    echo "   Connections/sec:       $(printf "%.1f" "$cps")"
+    echo "   Failed to connect:     $(fmt_num $connect_errs)"
+    echo "   Other errors:          $(fmt_num $other_errs) (read/write/timeout/non-2xx)"
+    echo "   Connect+request P50:   $(printf "%.2f" "$p50")ms"
+    echo "   Connect+request P99:   $(printf "%.2f" "$p99")ms"
+    
+    local res=$(bench_get_resources "$SERVER_PID")
+    read cpu mem <<< "$res"
+    echo "   Server CPU:     ${cpu}%"
+    echo "   Server Memory:  ${mem} MB"
+    
+    echo -e "\n${GREEN}✅ Connections-per-second test complete!${RESET}\n"
+}
+
+# ─────────────────────────────────────────────────────────────────────────────
+# Pipelining Mode
+# ─────────────────────────────────────────────────────────────────────────────
+
+# Args: $1=port $2=path $3=connections $4=duration $5=threads $6=requests $7=depth
+run_pipeline() {
+    local port=$1 path=$2 conns=$3 duration=$4 threads=$5 requests=$6 depth=$7
+    local url="http://localhost:$port$path"
+
+    info "\n📦 Pipelining Config"
+    info "   Connections: $conns | Threads: $threads | Requests: $(fmt_num $requests) per run | Path: $path"
+    info "   Sequential keep-alive vs $depth pipelined requests per batch, each run cut off after ${duration}s"
+
+    info "\n⏳ Warming up..."
+    bench_warmup "http://localhost:$port/"
+    sleep 0.5
+
+    local seq pip
+    seq=$(bench_pipeline "$url" "$requests" 1 "$conns" "$threads" "$duration")
+    pip=$(bench_pipeline "$url" "$requests" "$depth" "$conns" "$threads" "$duration")
+
+    header "📦 Pipelining Results"
+    print_table_header
+    local name result
+    for name in sequential pipelined; do
+        [[ "$name" == "sequential" ]] && result=$seq || result=$pip
+        read reqs errs rps minl p1 p50 p90 p99 maxl avg <<< "$result"
+        print_table_row "$name" "$(fmt_num ${reqs%.*})" "${errs%.*}" \
+            "$(printf "%.1f" "$rps")" \
+            "$(printf "%.2f" "$minl")" \
+            "$(printf "%.2f" "$p1")" \
+            "$(printf "%.2f" "$p50")" \
+            "$(printf "%.2f" "$p90")" \
+            "$(printf "%.2f" "$p99")" \
+            "$(printf "%.2f" "$maxl")" \
+            "$(printf "%.2f" "$avg")"
+    done
+    print_table_footer
+
+    read seq_reqs seq_errs seq_rps _ _ seq_p50 _ seq_p99 _ _ <<< "$seq"
+    read pip_reqs pip_errs pip_rps _ _ pip_p50 _ pip_p99 _ _ <<< "$pip"
+
+    echo "   Throughput:  $(printf "%.1f" "$seq_rps") → $(printf "%.1f" "$pip_rps") req/s ($(fmt_pct "$seq_rps" "$pip_rps"))"
+    echo "   Batch P50:   $(printf "%.2f" "$seq_p50") → $(printf "%.2f" "$pip_p50")ms ($(fmt_pct "$seq_p50" "$pip_p50"))"
+    echo "   Batch P99:   $(printf "%.2f" "$seq_p99") → $(printf "%.2f" "$pip_p99")ms ($(fmt_pct "$seq_p99" "$pip_p99"))"
+    for name in sequential pipelined; do
+        [[ "$name" == "sequential" ]] && result=$seq_reqs || result=$pip_reqs
+        if (( ${result%.*} < requests )); then
+            echo -e "   ${YELLOW}The $name run hit the ${duration}s cut-off after $(fmt_num ${result%.*}) requests; raise -d${RESET}"
+        fi
+    done
+    if (( ${seq_errs%.*} + ${pip_errs%.*} > 0 )); then
+        echo -e "   ${YELLOW}Errors usually mean the server closed connections instead of keeping them alive${RESET}"
+    fi
+
+    local res=$(bench_get_resources "$SERVER_PID")
+    read cpu mem <<< "$res"
+    echo "   Server CPU:     ${cpu}%"
+    echo "   Server Memory:  ${mem} MB"
+
+    echo -e "\n${GREEN}✅ Pipelining test complete!${RESET}\n"
+}
+
+# ─────────────────────────────────────────────────────────────────────────────
+# Slowloris Mode
+# ─────────────────────────────────────────────────────────────────────────────
+
+# Args: $1=port $2=connections $3=duration $4=interval
+run_slowloris() {
+    local port=$1 conns=$2 duration=$3 interval=$4
+    
+    info "\n🐌 Slowloris Config"
+    info "   Connections: $conns | Duration: ${duration}s | One header line every ${interval}s"
+    
+    local result=$(bench_slowloris "$port" "$conns" "$duration" "$interval" "$TEMP_DIR")
+    read opened failed closed still_open min_ms avg_ms max_ms probe_ms <<< "$result"
+    
+    header "🐌 Slowloris Results"
+    echo "   Connections opened:   $opened (failed to connect: $failed)"
+    echo "   Closed by server:     $closed"
+    echo "   Still open at end:    $still_open"
+    if [[ $closed -gt 0 ]]; then
+        echo "   Time until closed:    min ${min_ms}ms / avg ${avg_ms}ms / max ${max_ms}ms"
+    fi
+    if [[ $probe_ms -ge 0 ]]; then
+        echo "   Probe during attack:  ${probe_ms}ms"
+    else
+        echo "   Probe during attack:  no response within 2s"
+    fi
+    
+    # Sum it up: did the server cut slow clients off, and could others still get in?
+    local verdict
+    if [[ $closed -eq $opened && $opened -gt 0 ]]; then
+        verdict="all slow connections cut off"
+    elif [[ $closed -gt 0 ]]; then
+        verdict="some slow connections cut off, $still_open held until the client gave up"
+    else
+        verdict="no slow connection was cut off within ${duration}s"
+    fi
+    if [[ $probe_ms -ge 0 ]]; then
+        verdict="$verdict; normal requests still served"
+    else
+        verdict="$verdict; normal requests starved"
+    fi
+    echo -e "\n   Defense: $verdict"
+    
+    local res=$(bench_get_resources "$SERVER_PID")
+    read cpu mem <<< "$res"
+    echo "   Server CPU:     ${cpu}%"
+    echo "   Server Memory:  ${mem} MB"
+    
+    echo -e "\n${GREEN}✅ Slowloris test complete!${RESET}\n"
+}
+
 # ─────────────────────────────────────────────────────────────────────────────
 # Main
 # ─────────────────────────────────────────────────────────────────────────────
@@ -78,6 +232,20 @@ Options:
   -t, --threads      wrk threads (default: auto-calculated)
   --cpu              CPU cores for server (default: 1)
   --mem              Memory limit for server (default: 1G)
+  --mode MODE        requests: reuse connections where the server allows (default)
+                     conn-per-sec: open a new connection for every request and
+                     report connections/sec; needs a GET endpoint (default: root)
+                     pipeline: send the same number of requests sequentially, then
+                     pipelined, on kept-alive connections and compare the two;
+                     needs a GET endpoint (default: root)
+  --requests N       Requests per run in pipeline mode (default: 100000)
+  --pipeline-depth N Requests sent per batch in pipeline mode (default: 16)
+  --output FORMAT    text (default) or json: print the results as JSON on stdout, with
+                     the progress report on stderr (requests mode only)
+  --slowloris-test   Instead of wrk, hold -c connections open with trickled headers
+                     for -d seconds and report how the server defends itself
+  --slowloris-interval  Seconds between trickled header lines (default: 1)
+  --server-args      Extra flags passed to the server (Rust only), e.g. "--ignore-expect"
   -h, --help         Show this help
 
 Examples:
@@ -85,13 +253,20 @@ Examples:
   ./benchmark.sh -l go -c 200 -d 10           # Go, 200 connections, 10s
   ./benchmark.sh -l py --cpu 2 --mem 2G       # Python, 2 cores, 2GB RAM
   ./benchmark.sh -l rust -e root -c 100       # Rust, root endpoint only
+  ./benchmark.sh -l rust -e post --server-args "--ignore-expect"
+  ./benchmark.sh -l go --mode conn-per-sec -c 100
+  ./benchmark.sh -l rust --mode pipeline -c 8 --pipeline-depth 32
+  ./benchmark.sh -l go -e root --output json > go.json
+  ./benchmark.sh -l go --slowloris-test -c 100 -d 10
 EOF
     exit 0
 }
 
 main() {
     local lang="" endpoint="all" conns=50 duration=5 threads=""
-    local cpu_cores="1" mem_limit="1G"
+    local cpu_cores="1" mem_limit="1G" server_args=""
+    local slowloris=false slowloris_interval=1 mode="requests" output="text"
+    local requests=100000 depth=16
     
     # Parse args
     while [[ $# -gt 0 ]]; do
@@ -103,6 +278,13 @@ main() {
             -t|--threads)     threads="$2"; shift 2 ;;
             --cpu)            cpu_cores="$2"; shift 2 ;;
             --mem)            mem_limit="$2"; shift 2 ;;
+            --server-args)    server_args="$2"; shift 2 ;;
+            --mode)           mode="$2"; shift 2 ;;
+            --output)         output="$2"; shift 2 ;;
+            --requests)       requests="$2"; shift 2 ;;
+            --pipeline-depth) depth="$2"; shift 2 ;;
+            --slowloris-test) slowloris=true; shift ;;
+            --slowloris-interval) slowloris_interval="$2"; shift 2 ;;
             -h|--help)        usage ;;
             *)                shift ;;
         esac
@@ -111,6 +293,41 @@ main() {
     # Validate
     [[ -z "$lang" ]] && { err "Missing -l/--lang"; usage; }
     server_is_valid_lang "$lang" || { err "Invalid language: $lang"; exit 1; }
+    case $mode in
+        requests|conn-per-sec|pipeline) ;;
+        *) err "Invalid mode: $mode (expected requests, conn-per-sec or pipeline)"; exit 1 ;;
+    esac
+    [[ "$requests" =~ ^[1-9][0-9]*$ ]] || { err "Invalid --requests: $requests"; exit 1; }
+    [[ "$depth" =~ ^[1-9][0-9]*$ ]] || { err "Invalid --pipeline-depth: $depth"; exit 1; }
+    case $output in
+        text) ;;
+        json)
+            if [[ "$mode" != "requests" ]] || $slowloris; then
+                err "--output json is only supported for --mode requests"; exit 1
+            fi
+            # The report goes to stderr so stdout carries nothing but the JSON
+            exec 3>&1 1>&2
+            ;;
+        *) err "Invalid output format: $output (expected text or json)"; exit 1 ;;
+    esac
+    
+    # Connection churn and pipelining are measured on a single GET endpoint
+    local cps_path="/"
+    if [[ "$mode" != "requests" && "$endpoint" != "all" ]]; then
+        local cps_def=$(server_get_endpoint "$endpoint")
+        [[ -z "$cps_def" ]] && { err "Invalid endpoint: $endpoint"; exit 1; }
+        [[ "$(server_parse_endpoint "$cps_def" "method")" != "GET" ]] && {
+            err "--mode $mode needs a GET endpoint, not $endpoint"; exit 1; }
+        cps_path=$(server_parse_endpoint "$cps_def" "path")
+    fi
+    
+    # The Rust server closes after every response unless told otherwise, and a
+    # kept-alive connection holds a worker, so give every connection its own
+    if [[ "$mode" == "pipeline" && "$lang" == "rust" ]]; then
+        [[ " $server_args " == *" --keep-alive "* ]] || server_args="--keep-alive 5 $server_args"
+        [[ " $server_args " == *" --workers"* ]] || server_args="--workers $conns $server_args"
+        server_args="${server_args% }"
+    fi
     
     # Auto-calculate threads (1 per 50 connections, max 4)
     if [[ -z "$threads" ]]; then
@@ -155,7 +372,7 @@ main() {
         info "   Port: $port | CPU/RAM limits: N/A (systemd-run not found)"
     fi
     
-    SERVER_PID=$(server_start "$lang" "$TEMP_DIR/server.log" "$cpu_quota" "$mem_limit")
+    SERVER_PID=$(server_start "$lang" "$TEMP_DIR/server.log" "$cpu_quota" "$mem_limit" "$server_args")
     
     if [[ -z "$SERVER_PID" ]]; then
         err "Failed to start server"
@@ -172,6 +389,21 @@ main() {
     SERVER_PID=$(bench_find_pid_by_port "$port")
     ok "Server running (PID: $SERVER_PID)"
     
+    if $slowloris; then
+        run_slowloris "$port" "$conns" "$duration" "$slowloris_interval"
+        return
+    fi
+    
+    if [[ "$mode" == "conn-per-sec" ]]; then
+        run_conn_per_sec "$port" "$cps_path" "$conns" "$duration" "$threads"
+        return
+    fi
+    
+    if [[ "$mode" == "pipeline" ]]; then
+        run_pipeline "$port" "$cps_path" "$conns" "$duration" "$threads" "$requests" "$depth"
+        return
+    fi
+    
     # Config summary
     info "\n📊 Benchmark Config"
     info "   Connections: $conns | Threads: $threads | Duration: ${duration}s/endpoint"
@@ -184,6 +416,7 @@ main() {
     
     # Results storage
     local total_reqs=0 total_errs=0 total_rps=0
+    local -a json_rows=()
     
     # Run benchmarks
     header "📈 Results"
@@ -216,6 +449,9 @@ main() {
             "$(printf "%.2f" "$p99")" \
             "$(printf "%.2f" "$maxl")" \
             "$(printf "%.2f" "$avg")"
+        
+        json_rows+=("$(printf '{"name":"%s","requests":%d,"errors":%d,"rps":%.1f,"latency_ms":{"min":%.2f,"p1":%.2f,"p50":%.2f,"p90":%.2f,"p99":%.2f,"max":%.2f,"avg":%.2f}}' \
+            "$ep_name" "${reqs%.*}" "${errs%.*}" "$rps" "$minl" "$p1" "$p50" "$p90" "$p99" "$maxl" "$avg")")
     done
     
     print_table_footer
@@ -232,6 +468,14 @@ main() {
     echo "   Server Memory:  ${mem} MB"
     
     echo -e "\n${GREEN}✅ Benchmark complete!${RESET}\n"
+    
+    if [[ "$output" == "json" ]]; then
+        local rows
+        rows=$(IFS=,; echo "${json_rows[*]}")
+        printf '{"lang":"%s","server":"%s","connections":%d,"threads":%d,"duration_s":%d,"cpu":"%s","mem":"%s","server_args":"%s","endpoints":[%s],"server_cpu_pct":%s,"server_mem_mb":%s}\n' \
+            "$lang" "$name" "$conns" "$threads" "$duration" "$cpu_cores" "$mem_limit" \
+            "${server_args//\"/\\\"}" "$rows" "${cpu:-0}" "${mem:-0}" >&3
+    fi
 }
 
 main "$@"
@@ -0,0 +1,84 @@
+#!/bin/bash
+# ═══════════════════════════════════════════════════════════════════════════════
+# compare_report.sh - Side-by-side report from benchmark.sh --output json runs
+# ═══════════════════════════════════════════════════════════════════════════════
+#
+# Usage: ./compare_report.sh BASELINE.json OTHER.json [MORE.json...]
+#
+# Prints a Markdown report: the runs being compared, then one table per
+# endpoint with throughput and latency, each as a percent change against the
+# first file. Needs jq.
+#
+# Examples:
+#   ./benchmark.sh -l go -e root --output json > go.json
+#   ./benchmark.sh -l rust -e root --output json > rust.json
+#   ./compare_report.sh go.json rust.json > report.md
+#
+# ═══════════════════════════════════════════════════════════════════════════════
+
+set -euo pipefail
+
+usage() {
+    sed -n '6,15p' "$0" | sed 's/^# \{0,1\}//'
+    exit "$1"
+}
+[[ "${1:-}" == "-h" || "${1:-}" == "--help" ]] && usage 0
+[[ $# -lt 2 ]] && usage 1
+command -v jq &>/dev/null || { echo "compare_report.sh needs jq" >&2; exit 1; }
+
+for file in "$@"; do
+    jq -e '.endpoints | type == "array"' "$file" &>/dev/null || {
+        echo "$file: not a benchmark.sh --output json result" >&2; exit 1; }
+done
+
+# Runs are labelled by file name, so name the files after what differs
+label() { basename "$1" .json; }
+
+echo "# Benchmark comparison"
+echo ""
+echo "Baseline: \`$(label "$1")\`. Changes are relative to it; for latency, negative is better."
+echo ""
+echo "| Run | Server | Connections | Threads | Duration | CPU | Server args |"
+echo "|-----|--------|------------:|--------:|---------:|----:|-------------|"
+for file in "$@"; do
+    jq -r --arg run "$(label "$file")" \
+        '"| \($run) | \(.server) | \(.connections) | \(.threads) | \(.duration_s)s | \(.cpu) | \(if .server_args == "" then "—" else "`\(.server_args)`" end) |"' "$file"
+done
+
+# One TSV row per run and endpoint, in file order, then grouped by endpoint
+for file in "$@"; do
+    jq -r --arg run "$(label "$file")" \
+        '.endpoints[] | [.name, $run, .rps, .latency_ms.p50, .latency_ms.p99, .errors] | @tsv' "$file"
+done | awk -F'\t' -v baseline="$(label "$1")" '
+    function change(value, base) {
+        if (base == "" || base == 0) return "n/a"
+        return sprintf("%+.1f%%", (value - base) / base * 100)
+    }
+    function num(n,   s) {  # 142389.0 -> 142,389.0
+        s = sprintf("%.1f", n)
+        while (s ~ /^[0-9]+[0-9][0-9][0-9][.,]/) sub(/[0-9][0-9][0-9][.,]/, ",&", s)
+        return s
+    }
+    !($1 in count) { order[++endpoints] = $1 }
+    {
+        i = ++count[$1]
+        run[$1, i] = $2; rps[$1, i] = $3; p50[$1, i] = $4; p99[$1, i] = $5; errs[$1, i] = $6
+        if ($2 == baseline) { base_rps[$1] = $3; base_p50[$1] = $4; base_p99[$1] = $5 }
+    }
+    END {
+        for (e = 1; e <= endpoints; e++) {
+            ep = order[e]
+            printf "\n## %s\n\n", ep
+            print "| Run | Req/s | Δ | P50 (ms) | Δ | P99 (ms) | Δ | Errors |"
+            print "|-----|------:|--:|---------:|--:|---------:|--:|-------:|"
+            for (i = 1; i <= count[ep]; i++) {
+                is_base = (run[ep, i] == baseline)
+                printf "| %s | %s | %s | %.2f | %s | %.2f | %s | %d |\n", run[ep, i],
+                    num(rps[ep, i]), is_base ? "—" : change(rps[ep, i], base_rps[ep]),
+                    p50[ep, i], is_base ? "—" : change(p50[ep, i], base_p50[ep]),
+                    p99[ep, i], is_base ? "—" : change(p99[ep, i], base_p99[ep]),
+                    errs[ep, i]
+            }
+        }
+    }
+'
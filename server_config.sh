@@ -88,10 +88,10 @@ _RUST_BINARY=""
 _CPP_BINARY=""
 
 # Start server with resource limits
-# Args: $1=lang $2=log_file $3=cpu_quota(%) $4=memory_max
+# Args: $1=lang $2=log_file $3=cpu_quota(%) $4=memory_max $5=server_args (Rust only)
 # Returns: PID
 server_start() {
-    local lang=$1 log_file=$2 cpu_quota=${3:-100} memory_max=${4:-1G}
+    local lang=$1 log_file=$2 cpu_quota=${3:-100} memory_max=${4:-1G} server_args=${5:-}
     local port=$(server_get_port "$lang")
     local core=$(server_get_core "$lang")
     local pid=""
@@ -143,14 +143,14 @@ server_start() {
             fi
             [[ -z "$rustc_bin" ]] && rustc_bin=$(which rustc 2>/dev/null || echo "")
             
-            # Try direct rustc call with bypass
-            if [[ -n "$rustc_bin" ]] && "$rustc_bin" -O "$SERVER_DIR/server.rs" -o "$_RUST_BINARY" 2>>"$log_file" 2>/dev/null; then
-                $run_cmd "$_RUST_BINARY" >>"$log_file" 2>&1 &
+            # Try direct rustc call with bypass; RUSTFLAGS (e.g. "--cfg count_allocs") is passed through
+            if [[ -n "$rustc_bin" ]] && "$rustc_bin" -O ${RUSTFLAGS:-} "$SERVER_DIR/server.rs" -o "$_RUST_BINARY" 2>>"$log_file" 2>/dev/null; then
+                $run_cmd "$_RUST_BINARY" $server_args >>"$log_file" 2>&1 &
                 pid=$!
             elif command -v rustc &>/dev/null; then
                 # Last resort: try rustc anyway (may fail)
-                if RUSTUP_TOOLCHAIN=stable rustc -O "$SERVER_DIR/server.rs" -o "$_RUST_BINARY" 2>>"$log_file" 2>/dev/null; then
-                    $run_cmd "$_RUST_BINARY" >>"$log_file" 2>&1 &
+                if RUSTUP_TOOLCHAIN=stable rustc -O ${RUSTFLAGS:-} "$SERVER_DIR/server.rs" -o "$_RUST_BINARY" 2>>"$log_file" 2>/dev/null; then
+                    $run_cmd "$_RUST_BINARY" $server_args >>"$log_file" 2>&1 &
                     pid=$!
                 fi
             fi
@@ -26,6 +26,18 @@ const server = http.createServer((req, res) => {
       res.end(JSON.stringify({ route: path, body: JSON.parse(body || '{}') }));
     });
   }
+  else if (req.method === 'GET' && path === '/longpoll') {
+    // Parked requests cost a timer, not a thread - compare with the Rust pool
+    // Same rule as the Rust server: an integer is clamped to 0-30, anything else means 5
+    const requested = /^[+-]?\d+$/.test(query.timeout) ? Number(query.timeout) : NaN;
+    const secs = Number.isNaN(requested) ? 5 : Math.max(0, Math.min(requested, 30));
+    const started = Date.now();
+    const timer = setTimeout(() => {
+      res.setHeader('Content-Type', 'application/json');
+      res.end(JSON.stringify({ route: path, timeout: secs, waited_ms: Date.now() - started }));
+    }, secs * 1000);
+    res.on('close', () => clearTimeout(timer));
+  }
   else {
     res.statusCode = 404;
     res.end('Not Found');
@@ -1,56 +1,1290 @@
-use std::io::{BufRead, BufReader, Read, Write};
-use std::net::{TcpListener, TcpStream};
+use std::cell::{Cell, RefCell};
+use std::fmt::Write as _;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::ops::Range;
+use std::collections::hash_map::RandomState;
 use std::collections::HashMap;
-use std::sync::{mpsc, Arc, Mutex};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex, OnceLock, RwLock};
 use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const USAGE: &str = "\
+Usage: server [OPTIONS]
+
+Options:
+  --config FILE      Read settings from a TOML file; flags given here override it
+  --prefork N        Fork N server processes sharing the port via SO_REUSEPORT (Linux)
+  --workers N        Worker threads in the pool (default: 8)
+  --workers-per-core N
+                     Pool size as a multiple of available cores instead of --workers
+  --dispatch MODE    How connections reach workers: shared (one queue for all), round-robin,
+                     least-loaded or random (a queue per worker) (default: shared)
+  --recycle-after N  Replace a worker thread with a fresh one once it has served N requests
+                     (checked between connections; default: never)
+  --warmup N         Before accepting, run N requests through every route in-process,
+                     without a socket, and report any that fail (default: off)
+  --ignore-expect    Don't answer `Expect: 100-continue`; read the body immediately
+  --max-body BYTES   Reject larger request bodies with 413 (default: 1048576)
+  --max-uri BYTES    Reject longer decoded path+query with 414 (default: 2048)
+  --max-header-line BYTES
+                     Reject a longer header line with 431, request line with 414 (default: 8192)
+  --on-bad-request MODE
+                     What a malformed request gets: respond400, reset (RST via SO_LINGER 0)
+                     or close (FIN, no response) (default: respond400)
+  --body-read-chunk-size BYTES
+                     Most /upload-rate and /upload-file reads per call while streaming
+                     a body (default: 16384)
+  --upload-dir DIR   Where /upload-file saves bodies (default: the system temp directory)
+  --upload-ttl SECS  Keep uploaded files this long before deleting them (default: delete
+                     each one as soon as it's been answered)
+  --budget PATH=MS[,PATH=MS...]
+                     Shed requests for PATH with 503 once they've queued longer than MS
+  --adaptive-concurrency
+                     Cap how many requests are served at once, lowering the cap while
+                     latency is over --latency-target and raising it while under; requests
+                     past it get 503
+  --latency-target MS
+                     Latency --adaptive-concurrency aims for, time queued for a worker
+                     included (default: 50)
+  --read-timeout SECS
+                     Give up on a client that sends nothing for this long (default: never)
+  --keep-alive SECS  Keep connections open between requests, closing after SECS idle
+                     (default: close after every response)
+  --max-requests N   Close a keep-alive connection after its Nth response (default: no limit)
+  --max-bytes-per-connection BYTES
+                     Cut a connection off once its responses reach BYTES in total, mid-body
+                     if need be, and log it (default: no limit)
+  --shutdown-timeout SECS
+                     On SIGTERM or SIGINT, let in-flight and queued connections finish for
+                     at most this long before exiting anyway (default: 10)
+  --accept-backoff-ms MS
+                     First pause when accept runs out of file descriptors; doubles on
+                     each further failure (default: 5)
+  --accept-backoff-max-ms MS
+                     Cap for that pause (default: 1000)
+  --linger SECS|off  SO_LINGER for accepted sockets; 0 resets on close (default: OS)
+  --notsent-lowat BYTES
+                     TCP_NOTSENT_LOWAT for accepted sockets: how much unsent data the
+                     kernel queues before a write blocks (Linux and macOS; default: OS)
+  --centralized-writer
+                     Write every response from one dedicated thread instead of its worker
+  --response-buffer-flush-threshold BYTES
+                     Collect a chunked stream's chunks until this many bytes are waiting,
+                     then send them in one write (default: 0, a write per chunk)
+  --response-buffer-flush-ms MS
+                     Also send whatever is waiting once its oldest chunk is this old
+                     (default: only the threshold and the end of the stream flush)
+  --dump-requests FILE
+                     Append every raw request (head and body) to FILE for replay
+  --tee-bodies DIR   Save sampled request and response bodies to files in DIR
+  --tee-percent P    Share of requests --tee-bodies samples (default: 1)
+  --tee-max-bytes BYTES
+                     Keep at most this much of each teed body (default: 65536)
+  --server-timing    Add a Server-Timing header with parse/route/serialize durations
+  --date-refresh-ms MS
+                     How often the cached Date header is refreshed; it never lags the
+                     clock by more than this (default: 1000)
+  --security-headers Add X-Content-Type-Options, X-Frame-Options and Referrer-Policy to
+                     every response
+  --received-header  Add X-Request-Received: epoch nanoseconds when the request's first
+                     bytes were read
+  --drop-percent P   Hang up on this share of requests once read, without responding
+                     (default: 0; /metrics and /healthz are never dropped)
+  --schema FIELD:TYPE[,FIELD:TYPE...]
+                     Require these top-level fields in POST /something bodies, else 422;
+                     TYPE is string, number, boolean, object, array or null
+  --latency-buckets MS[,MS...]
+                     Upper bounds of the request_duration_seconds histogram in /metrics
+                     (default: 0.1,0.25,0.5,1,2.5,5,10,25,50,100,250,500,1000)
+  --access-log       Log one line per request to stdout
+  --connection-log   Log one line per connection to stdout when it closes: requests, bytes
+                     in and out, lifetime, and why it ended
+  --trust-proxy      Take the client IP from Forwarded / X-Forwarded-For
+  --log-time FORMAT  Access log timestamps: iso8601, epoch or none (default: iso8601)
+  --log-format FORMAT
+                     Access log layout: default, common (Apache CLF) or combined (CLF
+                     plus Referer and User-Agent); the last two carry their own timestamp
+  --static DIR       Serve files from DIR under /static/ (file.gz sidecars for gzip clients)
+  --default-content-type TYPE
+                     Content-Type for static files with an unknown extension
+                     (default: application/octet-stream)
+  --digest-trailer   Add a SHA-256 Digest to whole static files: a trailer on a chunked
+                     stream if the client sent TE: trailers, otherwise a header
+  --no-content-length
+                     Omit Content-Length; the body ends when the connection closes
+  --enable-connect   Answer CONNECT by tunnelling to the requested host:port (otherwise 405)
+  --connect-echo     Answer CONNECT with a tunnel that echoes the client's bytes back
+  --enable-upgrade   Answer `Upgrade: raw-echo` with 101 Switching Protocols, then echo
+                     the client's bytes back on the same connection
+  --debug-endpoints  Enable the /debug/ routes: pool introspection and deliberately broken responses
+  --admin-token TOKEN
+                     Enable /admin/routes for switching routes off and on at runtime, for
+                     requests with `Authorization: Bearer TOKEN`
+  --no-metrics       Drop the built-in /metrics route (falls through to 404)
+  --no-health        Drop the built-in /healthz route (falls through to 404)
+  --readiness-threshold N
+                     Have /healthz answer 503 while more than N connections are waiting
+                     for a worker (default: always 200)
+  -h, --help         Show this help
+";
+
+// Largest chunk a streamed static file is sent in
+const STREAM_CHUNK: usize = 64 * 1024;
+
+// How long a CONNECT tunnel may take to reach its target
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Long-poll limits: requests are held for at most this long
+const LONGPOLL_DEFAULT_SECS: u64 = 5;
+const LONGPOLL_MAX_SECS: u64 = 30;
+const LONGPOLL_TICK: Duration = Duration::from_millis(50);
+
+// /debug/dribble: one body byte per interval, for at most this many bytes
+const DRIBBLE_DEFAULT_MS: u64 = 1000;
+const DRIBBLE_MAX_MS: u64 = 60_000;
+const DRIBBLE_DEFAULT_BYTES: u64 = 3600;
+const DRIBBLE_MAX_BYTES: u64 = 86_400;
+
+// /stream: how many chunks of how many bytes, and the pause between them
+const STREAM_DEFAULT_CHUNKS: u64 = 100;
+const STREAM_MAX_CHUNKS: u64 = 100_000;
+const STREAM_DEFAULT_SIZE: u64 = 16;
+const STREAM_MAX_INTERVAL_MS: u64 = 10_000;
+
+// --adaptive-concurrency adjusts its cap once per window, toward this
+// latency unless --latency-target says otherwise
+const ADAPTIVE_WINDOW: Duration = Duration::from_millis(100);
+const ADAPTIVE_DEFAULT_TARGET: Duration = Duration::from_millis(50);
+
+// The HTTP/2 connection preface reads as a header-only "PRI * HTTP/2.0"
+// request, followed by this tail (RFC 9113 §3.4)
+const H2_PREFACE_HEAD: &str = "PRI * HTTP/2.0\r\n\r\n";
+const H2_PREFACE_TAIL: &[u8] = b"SM\r\n\r\n";
+
+// Chunked bodies with deliberately broken framing, served by
+// /debug/bad-chunked?variant=NAME
+const BAD_CHUNKED: &[(&str, &[u8])] = &[
+    ("short-chunk", b"a\r\nhello\r\n0\r\n\r\n"),  // Declares 10 bytes, sends 5
+    ("long-chunk", b"3\r\nhello\r\n0\r\n\r\n"),   // Declares 3, sends 5
+    ("bad-size", b"zz\r\nhello\r\n0\r\n\r\n"),     // Size isn't hex
+    ("huge-size", b"10000000000000000\r\nhello\r\n0\r\n\r\n"),  // Overflows 64 bits
+    ("no-data-crlf", b"5\r\nhello5\r\nworld\r\n0\r\n\r\n"),  // Data runs into the next size line
+    ("no-last-chunk", b"5\r\nhello\r\n"),             // Closes without the 0-size chunk
+    ("no-final-crlf", b"5\r\nhello\r\n0\r\n"),      // Last chunk but no closing blank line
+];
+
+// Content-Length headers that don't frame BAD_LENGTH_BODY, served by
+// /debug/bad-length?variant=NAME
+const BAD_LENGTH: &[(&str, &str)] = &[
+    ("too-large", "Content-Length: 10\r\n"),  // The body ends early
+    ("too-small", "Content-Length: 3\r\n"),   // Bytes left over after the body
+    ("conflicting", "Content-Length: 5\r\nContent-Length: 6\r\n"),  // Copies disagree
+    ("not-a-number", "Content-Length: five\r\n"),
+];
+const BAD_LENGTH_BODY: &[u8] = b"hello";
+
+// /barrier/wait: how long a waiter stays parked without a release
+const BARRIER_DEFAULT_SECS: u64 = 10;
+const BARRIER_MAX_SECS: u64 = 30;
+
+// Waiters parked on /barrier/wait. Each release bumps the generation, which
+// is how a waiter tells a real release from a spurious wakeup.
+struct BarrierState {
+    generation: u64,
+    waiting: usize,
+    released_at: Option<Instant>,
+}
+
+static BARRIER: Mutex<BarrierState> =
+    Mutex::new(BarrierState { generation: 0, waiting: 0, released_at: None });
+static BARRIER_RELEASED: Condvar = Condvar::new();
+
+// /compute caps; each takes very roughly a second of CPU at the top end
+const COMPUTE_MAX_FIB: u64 = 2_000_000_000;
+const COMPUTE_MAX_PRIMES: u64 = 5_000_000;
+
+// /json-tree caps. The node limit is what actually bounds the work, since
+// breadth^depth grows much faster than either cap suggests.
+const JSON_TREE_MAX_DEPTH: u32 = 8;
+const JSON_TREE_MAX_BREADTH: u32 = 16;
+const JSON_TREE_MAX_NODES: u64 = 100_000;
+
+// --security-headers: the set production servers commonly send. HSTS would
+// belong here too, but it's only meaningful over TLS, which this server lacks.
+const SECURITY_HEADERS: &str = "X-Content-Type-Options: nosniff\r\n\
+                                X-Frame-Options: DENY\r\n\
+                                Referrer-Policy: no-referrer\r\n";
+
+// Statuses with their own counter; anything else is counted by class (4xx etc.)
+const TRACKED_STATUSES: [u16; 16] = [200, 206, 400, 401, 404, 405, 406, 408, 413, 414, 416, 422, 431, 500, 502, 503];
+
+static STATUS_COUNTS: [AtomicU64; TRACKED_STATUSES.len()] =
+    [const { AtomicU64::new(0) }; TRACKED_STATUSES.len()];
+static STATUS_CLASS_COUNTS: [AtomicU64; 5] = [const { AtomicU64::new(0) }; 5];
+
+const PORT: u16 = 3003;
+
+// Content types for static files, by lowercase extension; anything missing
+// gets --default-content-type
+const MIME_TYPES: &[(&str, &str)] = &[
+    ("html", "text/html"),
+    ("htm", "text/html"),
+    ("css", "text/css"),
+    ("js", "application/javascript"),
+    ("mjs", "application/javascript"),
+    ("json", "application/json"),
+    ("map", "application/json"),
+    ("txt", "text/plain"),
+    ("csv", "text/csv"),
+    ("xml", "application/xml"),
+    ("wasm", "application/wasm"),
+    ("pdf", "application/pdf"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("svg", "image/svg+xml"),
+    ("ico", "image/x-icon"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+    ("mp3", "audio/mpeg"),
+];
+
+// Pool size when neither --workers nor --workers-per-core is given
+const DEFAULT_WORKERS: usize = 8;
+
+// A prefork child that dies sooner than this is restarted after a pause
+// instead of immediately, so a crash on startup can't spin the supervisor
+const CHILD_MIN_LIFETIME: Duration = Duration::from_secs(1);
+
+// Runtime options, parsed once from the command line
+struct Config {
+    prefork: Option<usize>,
+    workers: Option<usize>,
+    workers_per_core: Option<usize>,
+    dispatch: Dispatch,
+    recycle_after: Option<u64>,  // Requests per worker thread; None keeps threads for good
+    warmup: Option<u64>,  // Requests per WARMUP_ROUTES entry before accepting
+    ignore_expect: bool,
+    max_body: usize,
+    max_uri: usize,  // Measured after percent-decoding
+    max_header_line: usize,  // Including the line ending
+    on_bad_request: BadRequest,
+    body_read_chunk: usize,
+    upload_dir: PathBuf,
+    upload_ttl: Option<Duration>,  // None deletes each upload once answered
+    metrics: bool,
+    health: bool,
+    readiness_threshold: Option<usize>,  // Queued connections; None is always healthy
+    debug_endpoints: bool,
+    admin_token: Option<String>,  // None drops /admin/routes
+    connect: Option<ConnectMode>,  // None refuses CONNECT with 405
+    upgrade: bool,  // Off ignores Upgrade headers
+    read_timeout: Option<Duration>,
+    linger: Option<Linger>,  // None leaves the OS default alone
+    notsent_lowat: Option<u32>,  // Likewise
+    centralized_writer: bool,
+    flush_threshold: usize,  // Bytes of chunked output collected per write
+    flush_after: Option<Duration>,  // Longest a collected chunk waits, if capped
+    keep_alive: Option<Duration>,  // Idle timeout; None closes after each response
+    max_requests: Option<u64>,  // Per keep-alive connection
+    max_bytes_per_connection: Option<u64>,  // Response bytes, heads included
+    shutdown_timeout: Duration,
+    accept_backoff: Duration,  // First pause after accept fails for lack of resources
+    accept_backoff_max: Duration,
+    access_log: bool,
+    connection_log: bool,
+    log_time: LogTime,
+    log_format: LogFormat,
+    trust_proxy: bool,
+    static_dir: Option<PathBuf>,
+    default_content_type: String,
+    digest_trailer: bool,
+    content_length: bool,  // Off means close-delimited bodies (HTTP/1.0 style)
+    dump_requests: Option<PathBuf>,
+    tee_dir: Option<PathBuf>,
+    tee_percent: f64,
+    tee_max_bytes: usize,
+    budgets: Vec<Budget>,
+    adaptive_concurrency: bool,
+    latency_target: Option<Duration>,  // None uses ADAPTIVE_DEFAULT_TARGET
+    schema: Vec<FieldRule>,
+    latency_buckets: Vec<f64>,  // Upper bounds in seconds, ascending; +Inf is implied
+    server_timing: bool,
+    security_headers: bool,
+    date_refresh: Duration,
+    received_header: bool,
+    drop_percent: f64,
+}
+
+// Longest a request for `path` may wait for a worker before it's shed
+struct Budget {
+    path: String,
+    limit: Duration,
+}
+
+impl FromStr for Budget {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Budget, String> {
+        let (path, ms) = s.split_once('=').ok_or("expected PATH=MS")?;
+        let ms: u64 = ms.parse().map_err(|_| "expected milliseconds")?;
+        if !path.starts_with('/') {
+            return Err("the path must start with /".to_string());
+        }
+        Ok(Budget { path: path.to_string(), limit: Duration::from_millis(ms) })
+    }
+}
+
+// A top-level field --schema requires in a POST /something body
+struct FieldRule {
+    name: String,
+    kind: JsonType,
+}
+
+impl FromStr for FieldRule {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<FieldRule, String> {
+        let (name, kind) = s.split_once(':').ok_or("expected FIELD:TYPE")?;
+        if name.is_empty() {
+            return Err("the field needs a name".to_string());
+        }
+        Ok(FieldRule { name: name.to_string(), kind: kind.parse()? })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum JsonType {
+    String,
+    Number,
+    Boolean,
+    Object,
+    Array,
+    Null,
+}
+
+impl JsonType {
+    fn name(self) -> &'static str {
+        match self {
+            JsonType::String => "string",
+            JsonType::Number => "number",
+            JsonType::Boolean => "boolean",
+            JsonType::Object => "object",
+            JsonType::Array => "array",
+            JsonType::Null => "null",
+        }
+    }
+}
+
+impl FromStr for JsonType {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<JsonType, String> {
+        match s {
+            "string" => Ok(JsonType::String),
+            "number" => Ok(JsonType::Number),
+            "boolean" => Ok(JsonType::Boolean),
+            "object" => Ok(JsonType::Object),
+            "array" => Ok(JsonType::Array),
+            "null" => Ok(JsonType::Null),
+            other => Err(format!("unknown type: {}", other)),
+        }
+    }
+}
+
+// Which queue the accept loop hands a new connection to
+#[derive(Clone, Copy, PartialEq)]
+enum Dispatch {
+    Shared,       // One queue; whichever worker is free takes the next connection
+    RoundRobin,   // The rest give each worker its own queue
+    LeastLoaded,  // Fewest connections queued or in progress; ties go to the lowest index
+    Random,
+}
+
+impl FromStr for Dispatch {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Dispatch, String> {
+        match s {
+            "shared" => Ok(Dispatch::Shared),
+            "round-robin" => Ok(Dispatch::RoundRobin),
+            "least-loaded" => Ok(Dispatch::LeastLoaded),
+            "random" => Ok(Dispatch::Random),
+            other => Err(format!("unknown dispatch mode: {}", other)),
+        }
+    }
+}
+
+// What a CONNECT tunnel leads to
+#[derive(Clone, Copy)]
+enum ConnectMode {
+    Tunnel,  // The host:port in the request
+    Echo,    // Back to the client, for testing without an upstream
+}
+
+// Timestamp style for access log lines
+#[derive(Clone, Copy)]
+enum LogTime {
+    Iso8601,  // 2024-01-31T12:00:00.123Z, millisecond precision, UTC
+    Epoch,    // 1706702400.123
+    None,
+}
+
+impl FromStr for LogTime {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<LogTime, String> {
+        match s {
+            "iso8601" => Ok(LogTime::Iso8601),
+            "epoch" => Ok(LogTime::Epoch),
+            "none" => Ok(LogTime::None),
+            other => Err(format!("unknown log time format: {}", other)),
+        }
+    }
+}
+
+// Layout of access log lines
+#[derive(Clone, Copy, PartialEq)]
+enum LogFormat {
+    Default,   // <time> <client> "<method> <target>" <status> <bytes> <micros>us
+    Common,    // Apache's common log format
+    Combined,  // Common plus "Referer" "User-Agent"
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<LogFormat, String> {
+        match s {
+            "default" => Ok(LogFormat::Default),
+            "common" => Ok(LogFormat::Common),
+            "combined" => Ok(LogFormat::Combined),
+            other => Err(format!("unknown log format: {}", other)),
+        }
+    }
+}
+
+// How a request that fails to parse as HTTP is turned away
+#[derive(Clone, Copy, PartialEq)]
+enum BadRequest {
+    Respond400,
+    Reset,  // RST, by dropping the socket with a zero linger
+    Close,  // FIN and nothing else
+}
+
+impl FromStr for BadRequest {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<BadRequest, String> {
+        match s {
+            "respond400" => Ok(BadRequest::Respond400),
+            "reset" => Ok(BadRequest::Reset),
+            "close" => Ok(BadRequest::Close),
+            other => Err(format!("unknown bad request mode: {}", other)),
+        }
+    }
+}
+
+// SO_LINGER setting for accepted sockets
+#[derive(Clone, Copy)]
+enum Linger {
+    Off,
+    Secs(u32),
+}
+
+impl FromStr for Linger {
+    type Err = std::num::ParseIntError;
+    
+    fn from_str(s: &str) -> Result<Linger, Self::Err> {
+        match s {
+            "off" => Ok(Linger::Off),
+            secs => secs.parse().map(Linger::Secs),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            prefork: None,
+            workers: None,
+            workers_per_core: None,
+            dispatch: Dispatch::Shared,
+            recycle_after: None,
+            warmup: None,
+            ignore_expect: false,
+            max_body: 1024 * 1024,
+            max_uri: 2048,
+            max_header_line: 8192,
+            on_bad_request: BadRequest::Respond400,
+            body_read_chunk: 16 * 1024,
+            upload_dir: std::env::temp_dir(),
+            upload_ttl: None,
+            metrics: true,
+            health: true,
+            readiness_threshold: None,
+            debug_endpoints: false,
+            admin_token: None,
+            connect: None,
+            upgrade: false,
+            read_timeout: None,
+            linger: None,
+            notsent_lowat: None,
+            centralized_writer: false,
+            flush_threshold: 0,
+            flush_after: None,
+            keep_alive: None,
+            max_requests: None,
+            max_bytes_per_connection: None,
+            shutdown_timeout: Duration::from_secs(10),
+            accept_backoff: Duration::from_millis(5),
+            accept_backoff_max: Duration::from_millis(1000),
+            access_log: false,
+            connection_log: false,
+            log_time: LogTime::Iso8601,
+            log_format: LogFormat::Default,
+            trust_proxy: false,
+            static_dir: None,
+            default_content_type: "application/octet-stream".to_string(),
+            digest_trailer: false,
+            content_length: true,
+            dump_requests: None,
+            tee_dir: None,
+            tee_percent: 1.0,
+            tee_max_bytes: 65536,
+            budgets: Vec::new(),
+            adaptive_concurrency: false,
+            latency_target: None,
+            schema: Vec::new(),
+            latency_buckets: [0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0]
+                .iter()
+                .map(|ms| ms / 1000.0)
+                .collect(),
+            server_timing: false,
+            security_headers: false,
+            date_refresh: Duration::from_secs(1),
+            received_header: false,
+            drop_percent: 0.0,
+        }
+    }
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+static LISTENER: OnceLock<TcpListener> = OnceLock::new();
+static DUMP_FILE: OnceLock<Mutex<fs::File>> = OnceLock::new();
+
+// Requests shed per --budget entry, in the same order as config().budgets
+static SHED_COUNTS: OnceLock<Box<[AtomicU64]>> = OnceLock::new();
+
+fn shed_counts() -> &'static [AtomicU64] {
+    SHED_COUNTS.get_or_init(|| config().budgets.iter().map(|_| AtomicU64::new(0)).collect())
+}
+
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+// --adaptive-concurrency: requests being served, the cap on them, and the
+// current window's samples. The controller adjusts the cap once per window.
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+static CONCURRENCY_LIMIT: AtomicUsize = AtomicUsize::new(0);
+static CONCURRENCY_REJECTED: AtomicU64 = AtomicU64::new(0);
+static WINDOW_PEAK: AtomicUsize = AtomicUsize::new(0);
+static WINDOW_SAMPLES: AtomicU64 = AtomicU64::new(0);
+static WINDOW_LATENCY_US: AtomicU64 = AtomicU64::new(0);
+static CONTROLLER: OnceLock<Mutex<Controller>> = OnceLock::new();
+
+// request_duration_seconds: one count per --latency-buckets bound plus +Inf,
+// not cumulative (render_metrics adds them up), and the total time
+static LATENCY_COUNTS: OnceLock<Box<[AtomicU64]>> = OnceLock::new();
+static LATENCY_SUM_NANOS: AtomicU64 = AtomicU64::new(0);
+
+fn latency_counts() -> &'static [AtomicU64] {
+    LATENCY_COUNTS.get_or_init(|| (0..=config().latency_buckets.len()).map(|_| AtomicU64::new(0)).collect())
+}
+
+fn record_latency(elapsed: Duration) {
+    let bucket = config().latency_buckets.partition_point(|&le| le < elapsed.as_secs_f64());
+    latency_counts()[bucket].fetch_add(1, Ordering::Relaxed);
+    LATENCY_SUM_NANOS.fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+}
+
+// Numbers /upload-file's files within this process
+static UPLOAD_SEQ: AtomicU64 = AtomicU64::new(0);
+
+// Prefix of every file /upload-file saves; the --upload-ttl sweep touches nothing else
+const UPLOAD_PREFIX: &str = "rs-upload-";
+
+// --tee-bodies: the index every sample is listed in, and the count that
+// numbers them within this process
+static TEE_INDEX: OnceLock<Mutex<fs::File>> = OnceLock::new();
+static TEE_SEQ: AtomicU64 = AtomicU64::new(0);
+
+fn config() -> &'static Config {
+    CONFIG.get().expect("config is set before the pool starts")
+}
+
+// How a --config key turns into flags
+enum FileKey {
+    Value,   // key = "x" or key = 5  ->  --key x
+    Switch,  // key = true            ->  --key
+    Toggle,  // key = false           ->  --no-key
+}
+
+// Every key a --config file may set; each is its flag's name with _ for -
+const CONFIG_KEYS: &[(&str, FileKey)] = &[
+    ("prefork", FileKey::Value),
+    ("workers", FileKey::Value),
+    ("workers_per_core", FileKey::Value),
+    ("dispatch", FileKey::Value),
+    ("recycle_after", FileKey::Value),
+    ("warmup", FileKey::Value),
+    ("ignore_expect", FileKey::Switch),
+    ("max_body", FileKey::Value),
+    ("max_uri", FileKey::Value),
+    ("max_header_line", FileKey::Value),
+    ("on_bad_request", FileKey::Value),
+    ("body_read_chunk_size", FileKey::Value),
+    ("upload_dir", FileKey::Value),
+    ("upload_ttl", FileKey::Value),
+    ("budget", FileKey::Value),
+    ("adaptive_concurrency", FileKey::Switch),
+    ("latency_target", FileKey::Value),
+    ("schema", FileKey::Value),
+    ("read_timeout", FileKey::Value),
+    ("keep_alive", FileKey::Value),
+    ("max_requests", FileKey::Value),
+    ("max_bytes_per_connection", FileKey::Value),
+    ("shutdown_timeout", FileKey::Value),
+    ("accept_backoff_ms", FileKey::Value),
+    ("accept_backoff_max_ms", FileKey::Value),
+    ("linger", FileKey::Value),
+    ("notsent_lowat", FileKey::Value),
+    ("centralized_writer", FileKey::Switch),
+    ("response_buffer_flush_threshold", FileKey::Value),
+    ("response_buffer_flush_ms", FileKey::Value),
+    ("dump_requests", FileKey::Value),
+    ("tee_bodies", FileKey::Value),
+    ("tee_percent", FileKey::Value),
+    ("tee_max_bytes", FileKey::Value),
+    ("server_timing", FileKey::Switch),
+    ("security_headers", FileKey::Switch),
+    ("date_refresh_ms", FileKey::Value),
+    ("received_header", FileKey::Switch),
+    ("drop_percent", FileKey::Value),
+    ("latency_buckets", FileKey::Value),
+    ("access_log", FileKey::Switch),
+    ("connection_log", FileKey::Switch),
+    ("log_time", FileKey::Value),
+    ("log_format", FileKey::Value),
+    ("trust_proxy", FileKey::Switch),
+    ("static", FileKey::Value),
+    ("default_content_type", FileKey::Value),
+    ("digest_trailer", FileKey::Switch),
+    ("content_length", FileKey::Toggle),
+    ("metrics", FileKey::Toggle),
+    ("health", FileKey::Toggle),
+    ("readiness_threshold", FileKey::Value),
+    ("enable_connect", FileKey::Switch),
+    ("connect_echo", FileKey::Switch),
+    ("enable_upgrade", FileKey::Switch),
+    ("debug_endpoints", FileKey::Switch),
+    ("admin_token", FileKey::Value),
+];
+
+impl Config {
+    fn from_args() -> Config {
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        let mut config = Config::default();
+        
+        // The file is applied first wherever --config appears, so the
+        // command line always wins
+        if let Some(i) = args.iter().position(|arg| arg == "--config") {
+            let path = args.get(i + 1).unwrap_or_else(|| usage_error("Missing value for --config"));
+            config.apply(config_file_args(path).into_iter());
+            // Either pool flag on the command line replaces the file's pool size
+            if args.iter().any(|arg| arg == "--workers" || arg == "--workers-per-core") {
+                config.workers = None;
+                config.workers_per_core = None;
+            }
+        }
+        config.apply(args.into_iter());
+        
+        match (config.workers, config.workers_per_core) {
+            (Some(_), Some(_)) => usage_error("--workers and --workers-per-core are mutually exclusive"),
+            (Some(0), _) | (_, Some(0)) => usage_error("The pool needs at least one worker"),
+            _ => {}
+        }
+        match config.prefork {
+            Some(0) => usage_error("--prefork needs at least one process"),
+            Some(_) if !cfg!(target_os = "linux") => usage_error("--prefork is only supported on Linux"),
+            _ => {}
+        }
+        if config.keep_alive == Some(Duration::ZERO) {
+            usage_error("--keep-alive needs an idle timeout of at least 1 second");
+        }
+        if config.date_refresh.is_zero() {
+            usage_error("--date-refresh-ms must be at least 1");
+        }
+        if config.recycle_after == Some(0) {
+            usage_error("--recycle-after must be at least 1");
+        }
+        if config.warmup == Some(0) {
+            usage_error("--warmup must be at least 1");
+        }
+        if config.body_read_chunk == 0 {
+            usage_error("--body-read-chunk-size must be at least 1");
+        }
+        if config.flush_after == Some(Duration::ZERO) {
+            usage_error("--response-buffer-flush-ms must be at least 1");
+        }
+        if config.flush_after.is_some() && config.flush_threshold == 0 {
+            usage_error("--response-buffer-flush-ms needs --response-buffer-flush-threshold");
+        }
+        if config.upload_ttl == Some(Duration::ZERO) {
+            usage_error("--upload-ttl must be at least 1 second");
+        }
+        if config.max_requests == Some(0) {
+            usage_error("--max-requests must be at least 1");
+        }
+        if config.latency_target == Some(Duration::ZERO) {
+            usage_error("--latency-target must be at least 1");
+        }
+        if config.latency_target.is_some() && !config.adaptive_concurrency {
+            usage_error("--latency-target needs --adaptive-concurrency");
+        }
+        if config.max_bytes_per_connection == Some(0) {
+            usage_error("--max-bytes-per-connection must be at least 1");
+        }
+        if config.accept_backoff.is_zero() {
+            usage_error("--accept-backoff-ms must be at least 1");
+        }
+        if config.accept_backoff_max < config.accept_backoff {
+            usage_error("--accept-backoff-max-ms can't be below --accept-backoff-ms");
+        }
+        if !(0.0..=100.0).contains(&config.drop_percent) {
+            usage_error("--drop-percent must be between 0 and 100");
+        }
+        if !(0.0..=100.0).contains(&config.tee_percent) {
+            usage_error("--tee-percent must be between 0 and 100");
+        }
+        if config.admin_token.as_ref().is_some_and(|t| t.is_empty() || !t.bytes().all(|b| b.is_ascii_graphic())) {
+            usage_error("--admin-token must be printable ASCII without spaces");
+        }
+        
+        config
+    }
+    
+    fn apply(&mut self, mut args: impl Iterator<Item = String>) {
+        let config = self;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--config" => {
+                    args.next();  // Already loaded by from_args
+                }
+                "--prefork" => config.prefork = Some(flag_value(&mut args, &arg)),
+                "--workers" => config.workers = Some(flag_value(&mut args, &arg)),
+                "--workers-per-core" => config.workers_per_core = Some(flag_value(&mut args, &arg)),
+                "--dispatch" => config.dispatch = flag_value(&mut args, &arg),
+                "--recycle-after" => config.recycle_after = Some(flag_value(&mut args, &arg)),
+                "--warmup" => config.warmup = Some(flag_value(&mut args, &arg)),
+                "--ignore-expect" => config.ignore_expect = true,
+                "--max-body" => config.max_body = flag_value(&mut args, &arg),
+                "--max-uri" => config.max_uri = flag_value(&mut args, &arg),
+                "--max-header-line" => config.max_header_line = flag_value(&mut args, &arg),
+                "--on-bad-request" => config.on_bad_request = flag_value(&mut args, &arg),
+                "--body-read-chunk-size" => config.body_read_chunk = flag_value(&mut args, &arg),
+                "--upload-dir" => config.upload_dir = flag_value(&mut args, &arg),
+                "--upload-ttl" => {
+                    config.upload_ttl = Some(Duration::from_secs(flag_value(&mut args, &arg)));
+                }
+                "--no-content-length" => config.content_length = false,
+                "--enable-connect" => config.connect = Some(ConnectMode::Tunnel),
+                "--connect-echo" => config.connect = Some(ConnectMode::Echo),
+                "--enable-upgrade" => config.upgrade = true,
+                "--debug-endpoints" => config.debug_endpoints = true,
+                "--admin-token" => config.admin_token = Some(flag_value(&mut args, &arg)),
+                "--no-metrics" => config.metrics = false,
+                "--no-health" => config.health = false,
+                "--readiness-threshold" => config.readiness_threshold = Some(flag_value(&mut args, &arg)),
+                "--budget" => {
+                    let list: String = flag_value(&mut args, &arg);
+                    for item in list.split(',') {
+                        match item.parse::<Budget>() {
+                            Ok(budget) => {
+                                config.budgets.retain(|b| b.path != budget.path);
+                                config.budgets.push(budget);
+                            }
+                            Err(e) => usage_error(&format!("Invalid --budget {}: {}", item, e)),
+                        }
+                    }
+                }
+                "--adaptive-concurrency" => config.adaptive_concurrency = true,
+                "--latency-target" => {
+                    config.latency_target = Some(Duration::from_millis(flag_value(&mut args, &arg)));
+                }
+                "--schema" => {
+                    let list: String = flag_value(&mut args, &arg);
+                    for item in list.split(',') {
+                        match item.parse::<FieldRule>() {
+                            Ok(rule) => {
+                                config.schema.retain(|r| r.name != rule.name);
+                                config.schema.push(rule);
+                            }
+                            Err(e) => usage_error(&format!("Invalid --schema {}: {}", item, e)),
+                        }
+                    }
+                }
+                "--latency-buckets" => {
+                    let list: String = flag_value(&mut args, &arg);
+                    config.latency_buckets.clear();
+                    for item in list.split(',') {
+                        match item.parse::<f64>() {
+                            Ok(ms) if ms > 0.0 && ms.is_finite() => config.latency_buckets.push(ms / 1000.0),
+                            _ => usage_error(&format!("Invalid --latency-buckets {}: expected milliseconds", item)),
+                        }
+                    }
+                    if config.latency_buckets.windows(2).any(|pair| pair[0] >= pair[1]) {
+                        usage_error("--latency-buckets must be in ascending order");
+                    }
+                }
+                "--read-timeout" => {
+                    config.read_timeout = Some(Duration::from_secs(flag_value(&mut args, &arg)));
+                }
+                "--keep-alive" => {
+                    config.keep_alive = Some(Duration::from_secs(flag_value(&mut args, &arg)));
+                }
+                "--max-requests" => config.max_requests = Some(flag_value(&mut args, &arg)),
+                "--max-bytes-per-connection" => config.max_bytes_per_connection = Some(flag_value(&mut args, &arg)),
+                "--shutdown-timeout" => {
+                    config.shutdown_timeout = Duration::from_secs(flag_value(&mut args, &arg));
+                }
+                "--accept-backoff-ms" => {
+                    config.accept_backoff = Duration::from_millis(flag_value(&mut args, &arg));
+                }
+                "--accept-backoff-max-ms" => {
+                    config.accept_backoff_max = Duration::from_millis(flag_value(&mut args, &arg));
+                }
+                "--linger" => config.linger = Some(flag_value(&mut args, &arg)),
+                "--notsent-lowat" => config.notsent_lowat = Some(flag_value(&mut args, &arg)),
+                "--centralized-writer" => config.centralized_writer = true,
+                "--response-buffer-flush-threshold" => config.flush_threshold = flag_value(&mut args, &arg),
+                "--response-buffer-flush-ms" => {
+                    config.flush_after = Some(Duration::from_millis(flag_value(&mut args, &arg)));
+                }
+                "--dump-requests" => config.dump_requests = Some(flag_value(&mut args, &arg)),
+                "--tee-bodies" => config.tee_dir = Some(flag_value(&mut args, &arg)),
+                "--tee-percent" => config.tee_percent = flag_value(&mut args, &arg),
+                "--tee-max-bytes" => config.tee_max_bytes = flag_value(&mut args, &arg),
+                "--server-timing" => config.server_timing = true,
+                "--security-headers" => config.security_headers = true,
+                "--date-refresh-ms" => config.date_refresh = Duration::from_millis(flag_value(&mut args, &arg)),
+                "--received-header" => config.received_header = true,
+                "--drop-percent" => config.drop_percent = flag_value(&mut args, &arg),
+                "--access-log" => config.access_log = true,
+                "--connection-log" => config.connection_log = true,
+                "--log-time" => config.log_time = flag_value(&mut args, &arg),
+                "--log-format" => config.log_format = flag_value(&mut args, &arg),
+                "--trust-proxy" => config.trust_proxy = true,
+                "--static" => config.static_dir = Some(flag_value(&mut args, &arg)),
+                "--default-content-type" => config.default_content_type = flag_value(&mut args, &arg),
+                "--digest-trailer" => config.digest_trailer = true,
+                "-h" | "--help" => {
+                    print!("{}", USAGE);
+                    process::exit(0);
+                }
+                other => usage_error(&format!("Unknown option: {}", other)),
+            }
+        }
+    }
+    
+    fn pool_size(&self) -> usize {
+        match (self.workers, self.workers_per_core) {
+            (Some(n), _) => n,
+            (None, Some(ratio)) => {
+                let cores = thread::available_parallelism().map_or(1, |n| n.get());
+                cores * ratio
+            }
+            (None, None) => DEFAULT_WORKERS,
+        }
+    }
+}
+
+// Parses the value following a flag, exiting with usage on a bad or missing value
+fn flag_value<T: FromStr>(args: &mut impl Iterator<Item = String>, flag: &str) -> T {
+    match args.next().map(|v| v.parse()) {
+        Some(Ok(value)) => value,
+        _ => usage_error(&format!("Invalid or missing value for {}", flag)),
+    }
+}
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}\n\n{}", message, USAGE);
+    process::exit(2);
+}
+
+// Reads a --config file and returns the flags it stands for. Only the flat
+// part of TOML is needed: `key = value` lines with a string, integer, float
+// or boolean value, plus # comments. Tables and arrays are rejected.
+fn config_file_args(path: &str) -> Vec<String> {
+    let text = fs::read_to_string(path)
+        .unwrap_or_else(|e| usage_error(&format!("Can't read config file {}: {}", path, e)));
+    let mut args = Vec::new();
+    let mut seen: Vec<&str> = Vec::new();
+    
+    for (i, line) in text.lines().enumerate() {
+        let line_no = i + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line.starts_with('[') {
+            config_error(path, line_no, "tables aren't supported; put every key at the top level");
+        }
+        
+        let (key, value) = line
+            .split_once('=')
+            .unwrap_or_else(|| config_error(path, line_no, "expected `key = value`"));
+        let key = key.trim();
+        let kind = match CONFIG_KEYS.iter().find(|(known, _)| *known == key) {
+            Some((_, kind)) => kind,
+            None => config_error(path, line_no, &format!("unknown key `{}`", key)),
+        };
+        if seen.contains(&key) {
+            config_error(path, line_no, &format!("`{}` is set twice", key));
+        }
+        seen.push(key);
+        
+        let value = parse_toml_value(value.trim())
+            .unwrap_or_else(|| config_error(path, line_no, "invalid value"));
+        let flag = format!("--{}", key.replace('_', "-"));
+        match (kind, value) {
+            (FileKey::Value, TomlValue::Text(value)) => {
+                args.push(flag);
+                args.push(value);
+            }
+            (FileKey::Switch, TomlValue::Bool(on)) => {
+                if on {
+                    args.push(flag);
+                }
+            }
+            (FileKey::Toggle, TomlValue::Bool(on)) => {
+                if !on {
+                    args.push(format!("--no-{}", &flag[2..]));
+                }
+            }
+            (FileKey::Value, TomlValue::Bool(_)) => {
+                config_error(path, line_no, &format!("`{}` takes a string or number", key))
+            }
+            (_, TomlValue::Text(_)) => config_error(path, line_no, &format!("`{}` takes true or false", key)),
+        }
+    }
+    args
+}
+
+fn config_error(path: &str, line: usize, message: &str) -> ! {
+    eprintln!("{}:{}: {}", path, line, message);
+    process::exit(2);
+}
+
+// Integers are kept as text; the flag parser gives them their real type
+enum TomlValue {
+    Text(String),
+    Bool(bool),
+}
+
+// One value plus an optional trailing comment; None if it isn't valid
+fn parse_toml_value(s: &str) -> Option<TomlValue> {
+    let (value, rest) = match s.chars().next()? {
+        '"' => {
+            // Basic string with the common escapes
+            let mut out = String::new();
+            let mut chars = s[1..].char_indices();
+            loop {
+                match chars.next()? {
+                    (i, '"') => break (TomlValue::Text(out), &s[i + 2..]),
+                    (_, '\\') => out.push(match chars.next()?.1 {
+                        'n' => '\n',
+                        't' => '\t',
+                        '"' => '"',
+                        '\\' => '\\',
+                        _ => return None,
+                    }),
+                    (_, c) => out.push(c),
+                }
+            }
+        }
+        '\'' => {
+            // Literal string: no escapes
+            let end = s[1..].find('\'')? + 1;
+            (TomlValue::Text(s[1..end].to_string()), &s[end + 1..])
+        }
+        _ => {
+            let end = s.find(|c: char| c.is_whitespace() || c == '#').unwrap_or(s.len());
+            let value = match &s[..end] {
+                "true" => TomlValue::Bool(true),
+                "false" => TomlValue::Bool(false),
+                // Integers, and floats written with a decimal point
+                number
+                    if number.split('.').count() <= 2
+                        && number.split('.').all(|part| {
+                            !part.is_empty() && part.chars().all(|c| c.is_ascii_digit() || c == '_')
+                        }) =>
+                {
+                    TomlValue::Text(number.replace('_', ""))
+                }
+                _ => return None,
+            };
+            (value, &s[end..])
+        }
+    };
+    
+    let rest = rest.trim_start();
+    if rest.is_empty() || rest.starts_with('#') { Some(value) } else { None }
+}
+
+// Per-worker counters, indexed like the pool's threads
+struct WorkerStats {
+    load: AtomicUsize,  // Queued or in progress; only kept with per-worker queues
+    served: AtomicU64,  // Connections
+    requests: AtomicU64,
+    state: AtomicUsize,  // WORKER_IDLE, WORKER_READING, WORKER_EXITED or WORKER_BUSY + a ROUTES index
+    waiting: Mutex<u64>,  // sys::socket_handle of a connection waiting for a request, else 0
+}
+
+static WORKER_STATS: OnceLock<Box<[WorkerStats]>> = OnceLock::new();
+
+// Connections handed to the pool that no worker has picked up yet, across
+// every queue
+static POOL_QUEUED: AtomicUsize = AtomicUsize::new(0);
+
+// Worker threads running, --recycle-after replacements included
+static LIVE_WORKERS: AtomicUsize = AtomicUsize::new(0);
+
+// Set once SIGTERM or SIGINT arrives, and once the accept loop has stopped
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+static ACCEPT_STOPPED: AtomicBool = AtomicBool::new(false);
+
+// How often a draining server hangs up on connections waiting for a request
+const SHUTDOWN_TICK: Duration = Duration::from_millis(100);
+
+const WORKER_IDLE: usize = 0;
+const WORKER_READING: usize = 1;  // Has a connection, waiting for a request on it
+const WORKER_EXITED: usize = 2;  // Its thread is gone, after a handler panicked
+const WORKER_BUSY: usize = 3;
+
+// Routes as /debug/workers names them; anything unmatched is "other"
+const ROUTES: &[&str] = &[
+    "/", "/something", "/upload-rate", "/upload-file", "/compute", "/json-tree", "/cookies", "/cookies/set", "/static/", "/metrics", "/healthz",
+    "/stream", "/debug/bad-chunked", "/debug/bad-length", "/debug/dribble", "/debug/sysstat", "/debug/workers", "/debug/panic", "/barrier/wait", "/barrier/release", "/longpoll",
+    "/admin/routes", "other",
+];
+
+// Routes /admin/routes has switched off, by ROUTES index
+static ROUTE_DISABLED: [AtomicBool; ROUTES.len()] = [const { AtomicBool::new(false) }; ROUTES.len()];
+
+thread_local! {
+    // Index into WORKER_STATS for pool threads
+    static WORKER_ID: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+fn route_index(path: &str) -> usize {
+    let name = if path.starts_with("/static/") { "/static/" } else { path };
+    ROUTES.iter().position(|route| *route == name).unwrap_or(ROUTES.len() - 1)
+}
+
+// The calling worker's counters; None off the pool
+fn current_worker() -> Option<&'static WorkerStats> {
+    WORKER_STATS.get()?.get(WORKER_ID.with(Cell::get)?)
+}
+
+// Marks a worker exited when a handler panics and unwinds its thread. A
+// thread ending any other way is shutting down or has been recycled.
+struct ExitGuard(&'static WorkerStats);
+
+impl Drop for ExitGuard {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.0.state.store(WORKER_EXITED, Ordering::Relaxed);
+        }
+        if let Ok(mut waiting) = self.0.waiting.lock() {
+            *waiting = 0;
+        }
+        LIVE_WORKERS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Worker threads --recycle-after has replaced
+static RECYCLED: AtomicU64 = AtomicU64::new(0);
+
+// Connections a worker started on after another worker had started one
+// accepted later, and the highest Job::seq started so far
+static DISPATCH_OUT_OF_ORDER: AtomicU64 = AtomicU64::new(0);
+static DISPATCH_STARTED: AtomicU64 = AtomicU64::new(0);
+
+// An accepted connection waiting for a worker
+struct Job {
+    stream: TcpStream,
+    queued_at: Instant,
+    seq: u64,  // Accept order, from 1
+}
+
+type JobQueue = Arc<Mutex<mpsc::Receiver<Job>>>;
+
+// ThreadPool::execute found no worker left to take the connection, which it
+// hands back so the caller can still answer it
+struct PoolClosed(TcpStream);
 
 // Thread pool for handling connections
 struct ThreadPool {
     workers: Vec<thread::JoinHandle<()>>,
-    sender: Option<mpsc::Sender<TcpStream>>,
+    senders: Vec<mpsc::Sender<Job>>,
+    dispatch: Dispatch,
+    next: Cell<usize>,  // Round-robin position
+    accepted: Cell<u64>,  // Connections queued so far, for Job::seq
 }
 
 impl ThreadPool {
-    fn new(size: usize) -> ThreadPool {
-        let (sender, receiver) = mpsc::channel();
-        let receiver = Arc::new(Mutex::new(receiver));
+    fn new(size: usize, dispatch: Dispatch) -> ThreadPool {
+        let stats = WORKER_STATS.get_or_init(|| {
+            (0..size)
+                .map(|_| WorkerStats {
+                    load: AtomicUsize::new(0),
+                    served: AtomicU64::new(0),
+                    requests: AtomicU64::new(0),
+                    state: AtomicUsize::new(WORKER_IDLE),
+                    waiting: Mutex::new(0),
+                })
+                .collect()
+        });
+        let queues = if dispatch == Dispatch::Shared { 1 } else { size };
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..queues)
+            .map(|_| {
+                let (sender, receiver) = mpsc::channel::<Job>();
+                (sender, Arc::new(Mutex::new(receiver)))
+            })
+            .unzip();
         
         let mut workers = Vec::with_capacity(size);
         
-        for _ in 0..size {
-            let receiver = Arc::clone(&receiver);
-            let worker = thread::spawn(move || loop {
-                let stream = {
-                    let receiver = receiver.lock().unwrap();
-                    receiver.recv()
-                };
-                
-                match stream {
-                    Ok(stream) => handle_client(stream),
-                    Err(_) => break,  // Channel closed, exit worker
-                }
-            });
-            workers.push(worker);
+        for (id, stats) in stats.iter().enumerate() {
+            // Only the shared queue's lock is ever contended
+            let receiver = Arc::clone(&receivers[id % queues]);
+            workers.push(spawn_worker(id, stats, receiver, dispatch));
         }
         
         ThreadPool {
             workers,
-            sender: Some(sender),
+            senders,
+            dispatch,
+            next: Cell::new(0),
+            accepted: Cell::new(0),
         }
     }
     
-    fn execute(&self, stream: TcpStream) {
-        if let Some(ref sender) = self.sender {
-            sender.send(stream).unwrap_or_else(|_| {
-                eprintln!("Failed to send stream to worker");
-            });
+    // A worker whose handler panicked has dropped its queue. With per-worker
+    // queues the connection goes to the next live one instead, so one lost
+    // worker only costs capacity; it's an error only once every worker is gone.
+    fn execute(&self, stream: TcpStream) -> Result<(), PoolClosed> {
+        let stats = WORKER_STATS.get().expect("stats are set up with the pool");
+        let first = match self.dispatch {
+            Dispatch::Shared => 0,
+            Dispatch::RoundRobin => {
+                let queue = self.next.get();
+                self.next.set((queue + 1) % self.senders.len());
+                queue
+            }
+            Dispatch::LeastLoaded => (0..self.senders.len())
+                .min_by_key(|&i| stats[i].load.load(Ordering::Relaxed))
+                .unwrap_or(0),
+            Dispatch::Random => (random_u64() % self.senders.len() as u64) as usize,
+        };
+        
+        self.accepted.set(self.accepted.get() + 1);
+        let mut job = Job { stream, queued_at: Instant::now(), seq: self.accepted.get() };
+        POOL_QUEUED.fetch_add(1, Ordering::Relaxed);
+        for i in 0..self.senders.len() {
+            let queue = (first + i) % self.senders.len();
+            if self.dispatch != Dispatch::Shared {
+                stats[queue].load.fetch_add(1, Ordering::Relaxed);
+            }
+            match self.senders[queue].send(job) {
+                Ok(()) => return Ok(()),
+                Err(mpsc::SendError(returned)) => {
+                    if self.dispatch != Dispatch::Shared {
+                        stats[queue].load.fetch_sub(1, Ordering::Relaxed);
+                    }
+                    job = returned;
+                }
+            }
         }
+        POOL_QUEUED.fetch_sub(1, Ordering::Relaxed);
+        Err(PoolClosed(job.stream))
     }
 }
 
+// With --recycle-after, a worker that has served its share starts its own
+// replacement on the same queue and counters, then exits. The new thread
+// begins with fresh thread-locals and whatever the allocator gives it. Only
+// the original threads are joined on shutdown; replacements exit on their
+// own once the queue closes, and LIVE_WORKERS says when they have.
+fn spawn_worker(id: usize, stats: &'static WorkerStats, receiver: JobQueue, dispatch: Dispatch) -> thread::JoinHandle<()> {
+    LIVE_WORKERS.fetch_add(1, Ordering::SeqCst);
+    thread::spawn(move || {
+        WORKER_ID.with(|worker| worker.set(Some(id)));
+        let _exit = ExitGuard(stats);
+        let requests_before = stats.requests.load(Ordering::Relaxed);
+        loop {
+            let job = {
+                let receiver = receiver.lock().unwrap();
+                receiver.recv()
+            };
+            
+            match job {
+                Ok(job) => {
+                    // Checked with the lock released, where the connection
+                    // really starts: the channel hands jobs out in order, but
+                    // two workers leaving the lock can still swap places, and
+                    // per-worker queues let a later connection overtake one
+                    // stuck behind a busy worker
+                    if DISPATCH_STARTED.fetch_max(job.seq, Ordering::SeqCst) > job.seq {
+                        DISPATCH_OUT_OF_ORDER.fetch_add(1, Ordering::Relaxed);
+                    }
+                    POOL_QUEUED.fetch_sub(1, Ordering::Relaxed);
+                    stats.state.store(WORKER_READING, Ordering::Relaxed);
+                    handle_client(job.stream, job.queued_at.elapsed());
+                    stats.state.store(WORKER_IDLE, Ordering::Relaxed);
+                }
+                Err(_) => break,  // Channel closed, exit worker
+            }
+            stats.served.fetch_add(1, Ordering::Relaxed);
+            if dispatch != Dispatch::Shared {
+                stats.load.fetch_sub(1, Ordering::Relaxed);
+            }
+            
+            let served = stats.requests.load(Ordering::Relaxed) - requests_before;
+            if config().recycle_after.is_some_and(|limit| served >= limit) {
+                RECYCLED.fetch_add(1, Ordering::Relaxed);
+                spawn_worker(id, stats, receiver, dispatch);
+                return;
+            }
+        }
+    })
+}
+
 impl Drop for ThreadPool {
     fn drop(&mut self) {
-        drop(self.sender.take());
+        self.senders.clear();
         for worker in self.workers.drain(..) {
             let _ = worker.join();
         }
@@ -58,61 +1292,645 @@ impl Drop for ThreadPool {
 }
 
 fn main() {
-    let listener = TcpListener::bind("0.0.0.0:3003").unwrap();
-    listener.set_nonblocking(false).unwrap();
-    println!("Rust server running on :3003");
+    CONFIG.get_or_init(Config::from_args);
     
-    // Create thread pool with fixed number of worker threads
-    // Using 8 threads as a good default (can handle many concurrent connections)
-    let pool = ThreadPool::new(8);
+    // Opened before any fork, so prefork children append to the same file
+    if let Some(path) = &config().dump_requests {
+        match fs::OpenOptions::new().create(true).append(true).open(path) {
+            Ok(file) => DUMP_FILE.get_or_init(|| Mutex::new(file)),
+            Err(e) => usage_error(&format!("Can't open {}: {}", path.display(), e)),
+        };
+    }
+    if let Some(dir) = &config().tee_dir {
+        let index = fs::create_dir_all(dir)
+            .and_then(|_| fs::OpenOptions::new().create(true).append(true).open(dir.join("index")));
+        match index {
+            Ok(file) => TEE_INDEX.get_or_init(|| Mutex::new(file)),
+            Err(e) => usage_error(&format!("Can't use {}: {}", dir.display(), e)),
+        };
+    }
     
-    for stream in listener.incoming().flatten() {
-        pool.execute(stream);
+    match config().prefork {
+        Some(children) => supervise(children),
+        None => serve(),
     }
 }
 
-fn handle_client(stream: TcpStream) {
-    // Set TCP options for performance
-    stream.set_nodelay(true).ok();
+fn serve() {
+    // Kept in a static so /metrics can inspect its accept queue. Prefork
+    // children each bind their own socket and let the kernel spread connections.
+    let listener = LISTENER.get_or_init(|| match config().prefork {
+        Some(_) => sys::bind_reuseport(PORT).unwrap(),
+        None => TcpListener::bind(("0.0.0.0", PORT)).unwrap(),
+    });
+    listener.set_nonblocking(false).unwrap();
     
-    let mut reader = BufReader::new(&stream);
-    let mut request_line = String::new();
+    // Before any thread starts, so every one inherits the mask
+    sys::block_shutdown_signals();
     
-    if reader.read_line(&mut request_line).is_err() || request_line.is_empty() {
-        return;
+    // Fixed number of worker threads; 8 by default handles many concurrent
+    // connections, or scale with the machine via --workers-per-core
+    let workers = config().pool_size();
+    thread::spawn(|| refresh_date(config().date_refresh));
+    if let Some(ttl) = config().upload_ttl {
+        thread::spawn(move || sweep_uploads(ttl));
     }
-
-    let parts: Vec<&str> = request_line.trim().split_whitespace().collect();
-    if parts.len() < 2 {
-        return;
+    if config().centralized_writer {
+        let (jobs, queue) = mpsc::channel();
+        WRITER.get_or_init(|| jobs);
+        thread::spawn(move || run_writer(queue));
+    }
+    if config().adaptive_concurrency {
+        CONCURRENCY_LIMIT.store(workers, Ordering::Relaxed);
+        CONTROLLER.get_or_init(|| {
+            Mutex::new(Controller { limit: workers as f64, max: workers, window_start: Instant::now() })
+        });
+    }
+    let pool = ThreadPool::new(workers, config().dispatch);
+    thread::spawn(await_shutdown);
+    if let Some(rounds) = config().warmup {
+        warm_up(rounds);
+    }
+    match config().prefork {
+        Some(_) => println!("Rust server running on :{} ({} workers, pid {})", PORT, workers, process::id()),
+        None => println!("Rust server running on :{} ({} workers)", PORT, workers),
     }
     
-    let (method, full_path) = (parts[0], parts[1]);
-    let (path, query_string) = full_path.split_once('?').unwrap_or((full_path, ""));
-
-    // Read headers
-    let mut content_length: usize = 0;
+    // Running out of file descriptors leaves the connection in the accept
+    // queue, so retrying at once would just spin. Back off until some close.
+    let mut backoff = config().accept_backoff;
+    let mut failures = 0u64;
     loop {
-        let mut line = String::new();
-        if reader.read_line(&mut line).is_err() || line.trim().is_empty() {
-            break;
-        }
-        if let Some((k, v)) = line.trim().split_once(": ") {
-            if k.eq_ignore_ascii_case("content-length") {
-                content_length = v.parse().unwrap_or(0);
-            }
-        }
+        match listener.accept() {
+            Ok((stream, _)) => {
+                if SHUTTING_DOWN.load(Ordering::SeqCst) {
+                    refuse_draining(stream);
+                    break;
+                }
+                if failures > 0 {
+                    eprintln!("accept recovered after {} failures", failures);
+                    failures = 0;
+                    backoff = config().accept_backoff;
+                }
+                // Nothing is left to serve this or any later connection, so
+                // answer this one and exit rather than accept more to drop
+                if let Err(PoolClosed(stream)) = pool.execute(stream) {
+                    let _ = make_response(503, "Service Unavailable", "text/plain").write_to(&stream);
+                    eprintln!("Every worker has exited; shutting down");
+                    process::exit(1);
+                }
+            }
+            Err(e) if sys::out_of_resources(&e) => {
+                failures += 1;
+                eprintln!("accept failed: {}; retrying in {}ms", e, backoff.as_millis());
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(config().accept_backoff_max);
+            }
+            // Anything else (ECONNABORTED and the like) is one connection's problem
+            Err(_) => {}
+        }
+    }
+    
+    // What's still in the backlog came too late to be served, but gets a
+    // 503 rather than a wait. After that the listener stops, so later
+    // connection attempts are refused.
+    ACCEPT_STOPPED.store(true, Ordering::SeqCst);
+    if listener.set_nonblocking(true).is_ok() {
+        while let Ok((stream, _)) = listener.accept() {
+            refuse_draining(stream);
+        }
+    }
+    sys::stop_listening(listener);
+    
+    // Closing the queues lets every worker finish the connections already
+    // queued for it, then exit. Dropping the pool joins the original threads;
+    // --recycle-after replacements aren't joinable, so they're counted out.
+    drop(pool);
+    while LIVE_WORKERS.load(Ordering::SeqCst) > 0 {
+        thread::sleep(Duration::from_millis(10));
+    }
+    eprintln!("Drained; exiting");
+}
+
+// Waits for SIGTERM or SIGINT, then drives the drain from the side. The
+// accept loop is woken with a connection of our own, since accept() only
+// returns for one. Workers waiting for a request on a connection are hung
+// up on: bytes that have already arrived can still be read, so a request
+// that made it in is served, but nobody waits out a keep-alive timeout.
+// If the drain overruns --shutdown-timeout, the process exits anyway.
+fn await_shutdown() {
+    let signal = sys::wait_shutdown_signal();
+    let deadline = Instant::now() + config().shutdown_timeout;
+    SHUTTING_DOWN.store(true, Ordering::SeqCst);
+    eprintln!(
+        "Caught signal {}; draining {} queued connections",
+        signal,
+        POOL_QUEUED.load(Ordering::Relaxed)
+    );
+    
+    let stats = WORKER_STATS.get().expect("stats are set up with the pool");
+    let wake = SocketAddr::from(([127, 0, 0, 1], PORT));
+    loop {
+        // With --prefork this may land on another child's listener, so it's
+        // repeated until this one's accept loop has seen one
+        if !ACCEPT_STOPPED.load(Ordering::SeqCst) {
+            let _ = TcpStream::connect_timeout(&wake, SHUTDOWN_TICK);
+        }
+        for worker in stats.iter() {
+            // Held across the shutdown: the worker clears the handle under
+            // this lock before it can close the socket, so the descriptor
+            // can't have been closed and reused for something else
+            let socket = worker.waiting.lock().unwrap();
+            if *socket != 0 {
+                sys::shutdown_read(*socket);
+            }
+        }
+        
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            eprintln!("Still draining after --shutdown-timeout; exiting with requests in flight");
+            process::exit(1);
+        }
+        thread::sleep(SHUTDOWN_TICK.min(left));
+    }
+}
+
+// Answers a connection that arrived once shutdown had begun. Whatever of its
+// request has arrived is read first, so the close doesn't reset the 503 away.
+fn refuse_draining(stream: TcpStream) {
+    let _ = make_response(503, "Service Unavailable", "text/plain").write_to(&stream);
+    let _ = stream.shutdown(Shutdown::Write);
+    if stream.set_nonblocking(true).is_ok() {
+        let _ = io::copy(&mut (&stream).take(64 * 1024), &mut io::sink());
+    }
+}
+
+// Prefork parent: forks the children, then waits on them forever and replaces
+// any that exit. It stays single-threaded, so forking from it is safe.
+fn supervise(children: usize) -> ! {
+    let mut started: HashMap<i32, Instant> = HashMap::new();
+    println!("Prefork supervisor (pid {}) starting {} processes", process::id(), children);
+    
+    for _ in 0..children {
+        spawn_child(&mut started);
+    }
+    
+    loop {
+        let (pid, how) = match sys::wait_child() {
+            Ok(exited) => exited,
+            Err(e) => {
+                eprintln!("waitpid failed: {}", e);
+                thread::sleep(CHILD_MIN_LIFETIME);
+                continue;
+            }
+        };
+        let lifetime = started.remove(&pid).map_or(Duration::ZERO, |at| at.elapsed());
+        eprintln!("Server process {} {} after {:.1}s; restarting", pid, how, lifetime.as_secs_f64());
+        
+        if lifetime < CHILD_MIN_LIFETIME {
+            thread::sleep(CHILD_MIN_LIFETIME);
+        }
+        spawn_child(&mut started);
+    }
+}
+
+fn spawn_child(started: &mut HashMap<i32, Instant>) {
+    match sys::fork_process() {
+        Ok(None) => {
+            sys::exit_with_parent();
+            serve();
+            process::exit(0);
+        }
+        Ok(Some(pid)) => {
+            started.insert(pid, Instant::now());
+        }
+        Err(e) => eprintln!("fork failed: {}", e),
+    }
+}
+
+// What --warmup requests, in order: every route that answers straight away
+// without extra flags. Routes that park (/longpoll, /barrier/wait), write
+// files (/upload-file), write to the socket as they go (/stream) or only
+// exist behind a flag are left out.
+const WARMUP_ROUTES: &[(&str, &str, &str)] = &[
+    ("GET", "/", ""),
+    ("GET", "/something?name=warmup&value=1", ""),
+    ("GET", "/something?name=warmup&json=true", ""),
+    ("POST", "/something", r#"{"warmup":true}"#),
+    ("POST", "/upload-rate", "warmup"),
+    ("GET", "/compute?fib=20", ""),
+    ("GET", "/json-tree?depth=2&breadth=4", ""),
+    ("GET", "/cookies", ""),
+    ("GET", "/metrics", ""),
+    ("GET", "/healthz", ""),
+];
+
+// Runs each WARMUP_ROUTES request `rounds` times without a connection. No
+// socket is opened, so clients that connect meanwhile wait in the listener's
+// backlog. Since handle_request never sees these requests, they aren't
+// logged, dumped, teed, dropped or counted. Anything other than a 2xx is
+// reported, once per route.
+fn warm_up(rounds: u64) {
+    let started = Instant::now();
+    let routes: Vec<_> = WARMUP_ROUTES
+        .iter()
+        .filter(|(_, target, _)| match *target {
+            "/metrics" => config().metrics,
+            "/healthz" => config().health,
+            _ => true,
+        })
+        .collect();
+    let mut out = Vec::new();
+    for (method, target, body) in &routes {
+        let raw = format!(
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
+            method, target, body.len(), body
+        );
+        let mut failures = 0;
+        let mut last_code = 0;
+        for _ in 0..rounds {
+            let code = warmup_request(raw.as_bytes(), &mut out);
+            if !(200..300).contains(&code) {
+                failures += 1;
+                last_code = code;
+            }
+        }
+        if failures > 0 {
+            eprintln!("Warmup: {} {} failed {} of {} times ({})", method, target, failures, rounds, last_code);
+        }
+    }
+    println!(
+        "Warmed up {} routes, {} requests each, in {:.1}ms",
+        routes.len(), rounds, started.elapsed().as_secs_f64() * 1000.0
+    );
+}
+
+// One request through the same parse, route and serialize steps as a
+// client's, read from `raw` and answered into `out`. Returns the status.
+fn warmup_request(raw: &[u8], out: &mut Vec<u8>) -> u16 {
+    let mut reader = BufReader::new(ClientStream {
+        source: Source::Memory(raw),
+        recorded: None,
+        tee: None,
+        received: None,
+        arrived: None,
+        read_total: 0,
+        requests: 0,
+        written: 0,
+    });
+    let request = match read_request(&mut reader) {
+        Ok(request) => request,
+        Err(ReadError::Invalid(code)) => return code,  // e.g. 414 under a small --max-uri
+        Err(_) => return 400,  // Not expected: the whole request is already there
+    };
+    // handle_request turns these away before routing
+    let response = if request.content_length > config().max_body {
+        make_response(413, "Payload Too Large", "text/plain")
+    } else {
+        route(&request, &mut reader, None)
+    };
+    out.clear();
+    out.extend_from_slice(response.head().as_bytes());
+    out.extend_from_slice(&response.body);
+    response.code
+}
+
+fn handle_client(stream: TcpStream, queue_delay: Duration) {
+    // Set TCP options for performance
+    stream.set_nodelay(true).ok();
+    if let Some(linger) = config().linger {
+        sys::set_linger(&stream, linger).ok();
+    }
+    if let Some(bytes) = config().notsent_lowat {
+        sys::set_notsent_lowat(&stream, bytes).ok();
+    }
+    if config().read_timeout.is_some() {
+        stream.set_read_timeout(config().read_timeout).ok();
+    }
+    
+    CONN_SENT.with(|sent| sent.set(0));
+    CONN_CUT.with(|cut| cut.set(false));
+    
+    let mut reader = BufReader::new(ClientStream {
+        source: Source::Socket(&stream),
+        recorded: DUMP_FILE.get().map(|_| Vec::new()),
+        tee: None,
+        received: None,
+        arrived: None,
+        read_total: 0,
+        requests: 0,
+        written: 0,
+    });
+    let opened = Instant::now();
+    let mut queue_delay = queue_delay;
+    let end = loop {
+        if let Err(end) = handle_request(&stream, &mut reader, queue_delay) {
+            break end;
+        }
+        queue_delay = Duration::ZERO;  // Only the first request waited for a worker
+        if let Some(worker) = current_worker() {
+            worker.state.store(WORKER_READING, Ordering::Relaxed);
+        }
+        
+        // Between requests only the idle timeout applies
+        reader.get_mut().received = None;
+        reader.get_mut().arrived = None;
+        stream.set_read_timeout(config().keep_alive).ok();
+        let idle = await_request(&mut reader).map(|buf| buf.is_empty());
+        stream.set_read_timeout(config().read_timeout).ok();
+        match idle {
+            Ok(false) => {}
+            Err(ref e) if is_timeout(e) => break ConnectionEnd::Timeout,
+            _ => break ConnectionEnd::ClientClose,
+        }
+    };
+    if config().connection_log {
+        log_connection(&stream, reader.get_ref(), opened, end);
+    }
+}
+
+// Why a connection closed, for --connection-log
+#[derive(Clone, Copy)]
+enum ConnectionEnd {
+    ClientClose,  // EOF or a reset where a request would start
+    Timeout,  // --keep-alive or --read-timeout ran out
+    MaxRequests,
+    MaxBytes,  // --max-bytes-per-connection cut a response short
+    ServerClose,  // The last response said Connection: close, or the route hung up
+    WriteError,
+}
+
+impl ConnectionEnd {
+    fn name(self) -> &'static str {
+        match self {
+            ConnectionEnd::ClientClose => "client-close",
+            ConnectionEnd::Timeout => "timeout",
+            ConnectionEnd::MaxRequests => "max-requests",
+            ConnectionEnd::MaxBytes => "max-bytes",
+            ConnectionEnd::ServerClose => "server-close",
+            ConnectionEnd::WriteError => "write-error",
+        }
+    }
+}
+
+// Reads and answers one request. Returns Ok if the connection can carry
+// another, i.e. keep-alive is on, both sides want it and the request's body
+// has been read to its end, and otherwise why it can't.
+fn handle_request(
+    stream: &TcpStream,
+    reader: &mut BufReader<ClientStream>,
+    queue_delay: Duration,
+) -> Result<(), ConnectionEnd> {
+    let entered = Instant::now();
+    let allocs_before = allocs::snapshot();
+    // A head already buffered was in before this call; otherwise the
+    // request starts with its first bytes, not with the wait for them
+    let buffered = !reader.buffer().is_empty();
+    let request = match read_request(reader) {
+        Ok(request) => request,
+        Err(ReadError::Closed) => return Err(ConnectionEnd::ClientClose),
+        Err(ReadError::Idle) => return Err(ConnectionEnd::Timeout),
+        // The limits (408, 414, 431) are always answered; only requests
+        // that aren't valid HTTP follow --on-bad-request
+        Err(ReadError::Invalid(400)) if config().on_bad_request != BadRequest::Respond400 => {
+            if config().on_bad_request == BadRequest::Reset {
+                sys::set_linger(stream, Linger::Secs(0)).ok();
+            } else {
+                let _ = stream.shutdown(Shutdown::Write);
+                drain_before_close(reader, stream);  // Unread bytes at close would mean a reset
+            }
+            return Err(ConnectionEnd::ServerClose);
+        }
+        Err(ReadError::Invalid(code)) => {
+            if let Ok(n) = make_response(code, status_text(code), "text/plain").write_to(stream) {
+                reader.get_mut().written += n;
+            }
+            // The rest of an oversized head may still be arriving
+            let _ = stream.shutdown(Shutdown::Write);
+            drain_before_close(reader, stream);
+            return Err(if code == 408 { ConnectionEnd::Timeout } else { ConnectionEnd::ServerClose });
+        }
+    };
+    reader.get_mut().requests += 1;
+    
+    let parsed = Instant::now();
+    // The clock for the latency histogram, the access log and --server-timing
+    let started = match reader.get_ref().arrived {
+        Some(arrived) if !buffered => arrived,
+        _ => entered,
+    };
+    if let Some(worker) = current_worker() {
+        worker.requests.fetch_add(1, Ordering::Relaxed);
+        worker.state.store(WORKER_BUSY + route_index(request.path()), Ordering::Relaxed);
+    }
+    let body_start = consumed(reader);
+    let body_end = body_start + request.content_length as u64;
+    
+    // A client with prior knowledge of h2c skips HTTP/1.1 altogether
+    if request.head == H2_PREFACE_HEAD {
+        refuse_h2(reader, stream);
+        return Err(ConnectionEnd::ServerClose);
+    }
+    
+    // Past its route's budget the client has probably given up already, so
+    // answer at once rather than spend a worker on a stale request
+    let over_budget = config()
+        .budgets
+        .iter()
+        .position(|b| b.path == request.path() && queue_delay > b.limit);
+    if let Some(i) = over_budget {
+        shed_counts()[i].fetch_add(1, Ordering::Relaxed);
+        return Err(shed(stream, reader, &request, started));
+    }
+    
+    // Held until the response is out, which is when its latency is taken.
+    // The observability routes don't count, so a run can still be measured.
+    let _slot = if config().adaptive_concurrency && !matches!(request.path(), "/metrics" | "/healthz") {
+        match ConcurrencySlot::acquire(entered.checked_sub(queue_delay).unwrap_or(entered)) {
+            Some(slot) => Some(slot),
+            None => return Err(shed(stream, reader, &request, started)),
+        }
+    } else {
+        None
+    };
+    
+    // Play an unreliable server: read the whole request, then hang up. The
+    // observability routes are spared so a run can still be measured.
+    if config().drop_percent > 0.0
+        && !matches!(request.path(), "/metrics" | "/healthz")
+        && random_percent() < config().drop_percent
+    {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+        if request.content_length <= config().max_body {
+            let _ = io::copy(&mut reader.by_ref().take(request.content_length as u64), &mut io::sink());
+        }
+        dump_request(reader.get_ref(), &request);
+        return Err(ConnectionEnd::ServerClose);
+    }
+    
+    let (method, path, query_string) = (request.method(), request.path(), request.query());
+    let content_length = request.content_length;
+
+    // Refuse an oversized body up front rather than reading and discarding it.
+    // The rest of the body may still be in flight, so the connection is done.
+    if content_length > config().max_body {
+        let mut response = make_response(413, "Payload Too Large", "text/plain");
+        response.version = response_version(&request);
+        if let Ok(n) = response.write_to(stream) {
+            reader.get_mut().written += n;
+        }
+        let _ = stream.shutdown(Shutdown::Write);
+        log_access(stream, &request, &response, started);
+        dump_request(reader.get_ref(), &request);
+        return Err(ConnectionEnd::ServerClose);
     }
 
-    let response = match (method, path) {
+    // Sampled before routing, so the tee sees the body as the route reads it
+    if TEE_INDEX.get().is_some() && random_percent() < config().tee_percent {
+        let buffered = reader.buffer();
+        let keep = buffered.len().min(content_length).min(config().tee_max_bytes);
+        let captured = buffered[..keep].to_vec();
+        reader.get_mut().tee = Some(captured);
+    }
+    
+    let mut chunked_read = false;
+    let mut response = match (method, path) {
+        _ if ROUTE_DISABLED[route_index(path)].load(Ordering::Relaxed) => {
+            make_response(503, "Service Unavailable", "text/plain")
+        }
+        
+        _ if wants_raw_echo(&request) => {
+            raw_echo(stream, reader);
+            return Err(ConnectionEnd::ServerClose);  // The echo ran until the client closed
+        }
+        
+        // Negotiated before the route runs, so a refused request has no
+        // effects: no upload saved, no route switched, no CPU burnt
+        _ if route_content_type(&request).is_some_and(|t| !accepts_type(&request, t)) => {
+            make_response(406, "Not Acceptable", "text/plain")
+        }
+        
+        ("CONNECT", _) => match config().connect {
+            Some(mode) => match connect(stream, reader, request.target(), mode) {
+                Some(response) => response,
+                None => return Err(ConnectionEnd::ServerClose),  // The tunnel ran until one side closed
+            },
+            None => {
+                let mut response = make_response(405, "Method Not Allowed", "text/plain");
+                response.headers.push(("Allow", "GET, POST".to_string()));
+                response
+            }
+        },
+        
+        ("GET", "/longpoll") => match longpoll(stream, query_string) {
+            Some(response) => response,
+            None => return Err(ConnectionEnd::ClientClose),  // Client went away while parked
+        },
+        
+        ("GET", "/stream") => stream_chunks(stream, &request, query_string)?,
+        
+        ("GET", "/debug/bad-chunked") if config().debug_endpoints => match bad_chunked(stream, &request, query_string) {
+            Some(response) => response,
+            None => return Err(ConnectionEnd::ServerClose),  // Sent by hand; the broken framing leaves nothing to reuse
+        },
+        
+        ("GET", "/debug/bad-length") if config().debug_endpoints => match bad_length(stream, &request, query_string) {
+            Some(response) => response,
+            None => return Err(ConnectionEnd::ServerClose),
+        },
+        
+        ("GET", "/debug/dribble") if config().debug_endpoints => dribble(stream, &request, query_string)?,
+        
+        _ => {
+            let response = route(&request, reader, Some(stream));
+            // Only a saved upload has read a chunked body through its last chunk
+            chunked_read = path == "/upload-file" && response.code == 200;
+            response
+        }
+    };
+    if config().server_timing {
+        response.timing = Some((parsed - started, parsed.elapsed()));
+    }
+    if config().received_header {
+        let received = reader.get_ref().received.unwrap_or_else(SystemTime::now);
+        let nanos = received.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+        response.headers.push(("X-Request-Received", nanos.to_string()));
+    }
+    // Everything from reading the head to here; writing the response isn't included
+    if let (Some(before), Some(now)) = (allocs_before, allocs::snapshot()) {
+        response.headers.push(("X-Allocations", (now.count - before.count).to_string()));
+        response.headers.push(("X-Allocated-Bytes", (now.bytes - before.bytes).to_string()));
+    }
+    // Without Content-Length the close is what ends the body
+    response.keep_alive = config().keep_alive.is_some()
+        && config().content_length
+        && wants_keep_alive(&request)
+        && !SHUTTING_DOWN.load(Ordering::Relaxed);
+    // Nothing else decodes chunked bodies, so an unread one can't be skipped
+    response.keep_alive &= !request.chunked || chunked_read;
+    let last_allowed = config().max_requests.is_some_and(|max| reader.get_ref().requests >= max);
+    let end = if last_allowed && response.keep_alive {
+        ConnectionEnd::MaxRequests
+    } else {
+        ConnectionEnd::ServerClose
+    };
+    response.keep_alive &= !last_allowed;
+    response.version = response_version(&request);
+    
+    match response.write_to(stream) {
+        Ok(n) => reader.get_mut().written += n,
+        Err(_) => return Err(write_end(ConnectionEnd::WriteError)),
+    }
+    if config().metrics {
+        record_latency(started.elapsed());
+    }
+    log_access(stream, &request, &response, started);
+    if request.chunked && !chunked_read {
+        let _ = stream.shutdown(Shutdown::Write);
+        drain_before_close(reader, stream);
+    }
+    if !response.keep_alive {
+        dump_request(reader.get_ref(), &request);
+        tee_bodies(reader.get_mut(), &request, &response);
+        return Err(end);
+    }
+    
+    // The next request starts right after this body, so whatever the route
+    // left unread is skipped, through the reader so nothing buffered is lost
+    let unread = body_end.saturating_sub(consumed(reader));
+    if unread > 0 && io::copy(&mut reader.by_ref().take(unread), &mut io::sink()).ok() != Some(unread) {
+        return Err(ConnectionEnd::ClientClose);
+    }
+    dump_request(reader.get_ref(), &request);
+    tee_bodies(reader.get_mut(), &request, &response);
+    let body_len = (consumed(reader) - body_start) as usize;
+    if let Some(recorded) = &mut reader.get_mut().recorded {
+        recorded.drain(..recorded.len().min(request.head.len() + body_len));
+    }
+    Ok(())
+}
+
+// The routes that answer with a Response alone, as opposed to the ones in
+// handle_request that write to the socket themselves. With no client, as
+// for --warmup, Expect: 100-continue goes unanswered.
+fn route(request: &Request, reader: &mut BufReader<ClientStream>, client: Option<&TcpStream>) -> Response {
+    let (method, path, query_string) = (request.method(), request.path(), request.query());
+    let content_length = request.content_length;
+    // A 1.0 client can't have meant it: 1xx responses are new in 1.1
+    let expect_continue = request.version() != "HTTP/1.0"
+        && request
+            .header("expect")
+            .is_some_and(|v| v.eq_ignore_ascii_case("100-continue"));
+    let ask_for_body = |has_body| match client {
+        Some(stream) if expect_continue => send_continue(stream, has_body),
+        _ => {}
+    };
+    
+    match (method, path) {
         ("GET", "/") => make_response(200, "Hello from Rust!", "text/plain"),
         
         ("GET", "/something") => {
-            let query: HashMap<_, _> = query_string
-                .split('&')
-                .filter(|s| !s.is_empty())
-                .filter_map(|p| p.split_once('='))
-                .collect();
+            let query = parse_query(query_string);
             
             if query.get("json") == Some(&"true") {
                 let pairs: Vec<String> = query.iter()
@@ -127,8 +1945,22 @@ fn handle_client(stream: TcpStream) {
         }
         
         ("POST", "/something") => {
-            let mut body = vec![0u8; content_length];
-            if content_length > 0 && reader.read_exact(&mut body).is_ok() {
+            ask_for_body(content_length > 0);
+            
+            // Bounded by Content-Length, so a pipelined request behind the
+            // body stays in the reader's buffer for the next parse
+            let mut body = Vec::with_capacity(content_length);
+            let read = content_length > 0
+                && reader.by_ref().take(content_length as u64).read_to_end(&mut body).is_ok()
+                && body.len() == content_length;
+            let invalid = if config().schema.is_empty() {
+                None
+            } else {
+                validate_schema(if read { &body } else { b"{}" })
+            };
+            if let Some(response) = invalid {
+                response
+            } else if read {
                 let body_str = String::from_utf8_lossy(&body);
                 let json = format!(r#"{{"route":"{}","body":{}}}"#, path, body_str);
                 make_response(200, &json, "application/json")
@@ -137,22 +1969,3239 @@ fn handle_client(stream: TcpStream) {
             }
         }
         
+        ("POST", "/upload-rate") => {
+            ask_for_body(content_length > 0);
+            upload_rate(reader, content_length)
+        }
+        
+        ("POST", "/upload-file") => {
+            ask_for_body(content_length > 0 || request.chunked);
+            upload_file(reader, request)
+        }
+        
+        ("GET", "/compute") => compute(query_string),
+        ("GET", "/json-tree") => json_tree(query_string),
+        ("GET", "/cookies") => echo_cookies(request),
+        ("GET", "/cookies/set") => set_cookies(query_string),
+        
+        ("GET", p) if p.starts_with("/static/") && config().static_dir.is_some() => {
+            serve_static(config().static_dir.as_ref().unwrap(), request)
+        }
+        
+        ("GET", "/metrics") if config().metrics => {
+            make_response(200, &render_metrics(), "text/plain; version=0.0.4")
+        }
+        
+        ("GET", "/healthz") if config().health => health(),
+        
+        ("GET", "/debug/sysstat") if config().debug_endpoints => make_response(200, &sysstat(), "application/json"),
+        
+        ("GET", "/debug/workers") if config().debug_endpoints => make_response(200, &debug_workers(), "application/json"),
+        
+        // Takes its worker down with it, to exercise the pool losing threads
+        ("GET", "/debug/panic") if config().debug_endpoints => panic!("/debug/panic requested"),
+        
+        ("GET" | "POST", "/admin/routes") if config().admin_token.is_some() => admin_routes(request),
+        
+        ("GET", "/barrier/wait") => barrier_wait(query_string),
+        ("POST", "/barrier/release") => barrier_release(),
+        
         _ => make_response(404, "Not Found", "text/plain"),
+    }
+}
+
+// Answers 503 straight away and closes, for a request turned away under load
+fn shed(stream: &TcpStream, reader: &mut BufReader<ClientStream>, request: &Request, started: Instant) -> ConnectionEnd {
+    let mut response = make_response(503, "Service Unavailable", "text/plain");
+    response.version = response_version(request);
+    if let Ok(n) = response.write_to(stream) {
+        reader.get_mut().written += n;
+    }
+    let _ = stream.shutdown(Shutdown::Write);  // Any body is left unread
+    log_access(stream, request, &response, started);
+    dump_request(reader.get_ref(), request);
+    ConnectionEnd::ServerClose
+}
+
+// The --adaptive-concurrency cap, kept fractional so small cuts add up
+struct Controller {
+    limit: f64,
+    max: usize,  // The pool size; no more than that can run at once anyway
+    window_start: Instant,
+}
+
+// One of the places under the --adaptive-concurrency cap, held while a
+// request is served. Dropping it records how long that took, counted from
+// when the connection was queued for a worker.
+struct ConcurrencySlot {
+    since: Instant,
+}
+
+impl ConcurrencySlot {
+    fn acquire(since: Instant) -> Option<ConcurrencySlot> {
+        let in_flight = IN_FLIGHT.fetch_add(1, Ordering::SeqCst) + 1;
+        if in_flight > CONCURRENCY_LIMIT.load(Ordering::Relaxed) {
+            IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+            CONCURRENCY_REJECTED.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        WINDOW_PEAK.fetch_max(in_flight, Ordering::Relaxed);
+        Some(ConcurrencySlot { since })
+    }
+}
+
+impl Drop for ConcurrencySlot {
+    fn drop(&mut self) {
+        IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+        WINDOW_SAMPLES.fetch_add(1, Ordering::Relaxed);
+        WINDOW_LATENCY_US.fetch_add(self.since.elapsed().as_micros() as u64, Ordering::Relaxed);
+        adjust_concurrency();
+    }
+}
+
+// Once per window, a gradient step. If the window's average latency is
+// over the target, the cap shrinks by target / average, at most halving it.
+// Under the target it grows by one, but only if the window filled the
+// cap: one that never reached it says nothing about whether more is safe.
+// Whichever request ends the window does the update; the rest skip the lock.
+fn adjust_concurrency() {
+    let Some(controller) = CONTROLLER.get() else {
+        return;
+    };
+    let Ok(mut controller) = controller.try_lock() else {
+        return;
     };
+    if controller.window_start.elapsed() < ADAPTIVE_WINDOW {
+        return;
+    }
+    controller.window_start = Instant::now();
+    let samples = WINDOW_SAMPLES.swap(0, Ordering::Relaxed);
+    let total_us = WINDOW_LATENCY_US.swap(0, Ordering::Relaxed);
+    let peak = WINDOW_PEAK.swap(IN_FLIGHT.load(Ordering::Relaxed), Ordering::Relaxed);
+    if samples == 0 {
+        return;
+    }
+    
+    let average = total_us as f64 / samples as f64;
+    let target = config().latency_target.unwrap_or(ADAPTIVE_DEFAULT_TARGET).as_micros() as f64;
+    if average > target {
+        controller.limit *= (target / average).max(0.5);
+    } else if peak >= controller.limit as usize {
+        controller.limit += 1.0;
+    }
+    controller.limit = controller.limit.clamp(1.0, controller.max as f64);
+    CONCURRENCY_LIMIT.store(controller.limit as usize, Ordering::Relaxed);
+}
 
-    let mut stream = stream;
-    let _ = stream.write_all(response.as_bytes());
-    let _ = stream.flush();
+// Connection is a comma-separated list and may be repeated. `close` wins
+// wherever it appears, even next to `keep-alive`: either side asking to close
+// is enough. Otherwise HTTP/1.1 persists by default and HTTP/1.0 only on request.
+fn wants_keep_alive(request: &Request) -> bool {
+    let tokens = request
+        .headers()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("connection"))
+        .flat_map(|(_, value)| value.split(','))
+        .map(str::trim);
+    let mut keep_alive = false;
+    for token in tokens {
+        if token.eq_ignore_ascii_case("close") {
+            return false;
+        }
+        keep_alive |= token.eq_ignore_ascii_case("keep-alive");
+    }
+    keep_alive || request.version() == "HTTP/1.1"
 }
 
-fn make_response(code: u16, body: &str, content_type: &str) -> String {
-    let status = match code {
-        200 => "OK",
-        404 => "Not Found",
-        _ => "Error",
+// An upgrade is only offered over HTTP/1.1 and has to be named in
+// Connection too (RFC 9110 §7.8). A request with a body is answered
+// normally instead, since the body would have to be read before switching.
+fn wants_raw_echo(request: &Request) -> bool {
+    let listed = |name: &str, token: &str| {
+        request
+            .headers()
+            .filter(|(header, _)| header.eq_ignore_ascii_case(name))
+            .flat_map(|(_, value)| value.split(','))
+            .any(|t| t.trim().eq_ignore_ascii_case(token))
     };
-    format!(
-        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
-        code, status, content_type, body.len(), body
-    )
+    config().upgrade
+        && request.version() == "HTTP/1.1"
+        && request.content_length == 0
+        && !request.chunked
+        && listed("upgrade", "raw-echo")
+        && listed("connection", "upgrade")
+}
+
+// The status line echoes HTTP/1.0 to a 1.0 client. Anything else, including
+// a head with no version at all, gets HTTP/1.1: a later 1.x still reads it,
+// and there's nothing newer to offer (h2 only gets as far as refuse_h2).
+fn response_version(request: &Request) -> &'static str {
+    if request.version() == "HTTP/1.0" {
+        "HTTP/1.0"
+    } else {
+        "HTTP/1.1"
+    }
+}
+
+// There's no HTTP/2 implementation here, so after a full preface the client
+// gets the least an h2 server must send, an empty SETTINGS frame, and then a
+// GOAWAY with HTTP_1_1_REQUIRED so it knows to retry over HTTP/1.1. That's
+// a clear refusal instead of a 404 the client can't parse.
+fn refuse_h2(reader: &mut BufReader<ClientStream>, mut stream: &TcpStream) {
+    let mut tail = [0u8; H2_PREFACE_TAIL.len()];
+    if reader.read_exact(&mut tail).is_err() || tail != H2_PREFACE_TAIL {
+        return;
+    }
+    
+    const SETTINGS: [u8; 9] = [0, 0, 0, 0x4, 0, 0, 0, 0, 0];
+    const GOAWAY: [u8; 17] = [
+        0, 0, 8, 0x7, 0, 0, 0, 0, 0,  // 8-byte payload, type GOAWAY, stream 0
+        0, 0, 0, 0,                   // Last stream id: none were processed
+        0, 0, 0, 0xd,                 // HTTP_1_1_REQUIRED
+    ];
+    let mut frames = SETTINGS.to_vec();
+    frames.extend_from_slice(&GOAWAY);
+    if stream.write_all(&frames).is_err() || stream.shutdown(Shutdown::Write).is_err() {
+        return;
+    }
+    
+    // The client has likely sent its own SETTINGS already
+    drain_before_close(reader, stream);
+}
+
+/// Closing with unread input resets the connection, which can destroy a
+/// response the client hasn't read yet, so discard what's in flight first.
+/// Bounded in time and size so a client that never stops can't hold a worker.
+fn drain_before_close(reader: &mut BufReader<ClientStream>, stream: &TcpStream) {
+    let _ = stream.set_read_timeout(Some(Duration::from_millis(200)));
+    let _ = io::copy(&mut reader.take(64 * 1024), &mut io::sink());
+}
+
+// The client socket as handle_client reads it. With --dump-requests it also
+// keeps a copy of every byte read, so the request can be dumped verbatim.
+struct ClientStream<'a> {
+    source: Source<'a>,
+    recorded: Option<Vec<u8>>,
+    tee: Option<Vec<u8>>,  // Body bytes kept for --tee-bodies, up to its cap
+    received: Option<SystemTime>,  // When the first bytes came in
+    arrived: Option<Instant>,  // The same moment, for timing
+    read_total: u64,
+    requests: u64,  // Parsed on this connection so far
+    written: u64,  // Response bytes sent, for --connection-log
+}
+
+// Where a ClientStream's bytes come from: a client, or a request --warmup
+// built in memory
+enum Source<'a> {
+    Socket(&'a TcpStream),
+    Memory(&'a [u8]),
+}
+
+impl Read for ClientStream<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = match &mut self.source {
+            Source::Socket(stream) => stream.read(buf)?,
+            Source::Memory(bytes) => bytes.read(buf)?,
+        };
+        self.read_total += n as u64;
+        if self.received.is_none() && n > 0 {
+            self.received = Some(SystemTime::now());
+            self.arrived = Some(Instant::now());
+        }
+        if let Some(recorded) = &mut self.recorded {
+            recorded.extend_from_slice(&buf[..n]);
+        }
+        if let Some(tee) = &mut self.tee {
+            let room = config().tee_max_bytes.saturating_sub(tee.len());
+            tee.extend_from_slice(&buf[..n.min(room)]);
+        }
+        Ok(n)
+    }
+}
+
+// Bytes handed on to the parser or a route, as opposed to read off the socket
+fn consumed(reader: &BufReader<ClientStream>) -> u64 {
+    reader.get_ref().read_total - reader.buffer().len() as u64
+}
+
+// Appends one record: "<unix_ms> <length>\n", exactly <length> raw bytes of
+// head and body, then "\n". The length is what makes binary bodies safe.
+// A body the route never read is cut at whatever arrived with the head.
+fn dump_request(client: &ClientStream, request: &Request) {
+    let (file, recorded) = match (DUMP_FILE.get(), &client.recorded) {
+        (Some(file), Some(recorded)) => (file, recorded),
+        _ => return,
+    };
+    // Anything past the declared body isn't part of this request
+    let raw = &recorded[..recorded.len().min(request.head.len() + request.content_length)];
+    
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let mut record = format!("{} {}\n", now.as_millis(), raw.len()).into_bytes();
+    record.extend_from_slice(raw);
+    record.push(b'\n');
+    // One write per record, so records from other workers never interleave
+    let _ = file.lock().unwrap().write_all(&record);
+}
+
+// Writes a sampled request's bodies to <id>.request and <id>.response, each
+// cut at --tee-max-bytes, and lists them in the index with their full sizes.
+// A streamed static file isn't in memory, so its .response stays empty.
+fn tee_bodies(client: &mut ClientStream, request: &Request, response: &Response) {
+    let (index, captured) = match (TEE_INDEX.get(), client.tee.take()) {
+        (Some(index), Some(captured)) => (index, captured),
+        _ => return,
+    };
+    let dir = config().tee_dir.as_ref().expect("index is only opened with a directory");
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    // Prefork children share the directory, hence the pid
+    let id = format!("{}-{}-{}", now.as_millis(), process::id(), TEE_SEQ.fetch_add(1, Ordering::Relaxed));
+    
+    // Bytes past the declared body belong to the next request
+    let request_body = &captured[..captured.len().min(request.content_length)];
+    let response_body = &response.body[..response.body.len().min(config().tee_max_bytes)];
+    let _ = fs::write(dir.join(format!("{}.request", id)), request_body);
+    let _ = fs::write(dir.join(format!("{}.response", id)), response_body);
+    
+    let line = format!(
+        "{} {} {} {} {} {}\n",
+        id, request.method(), request.target(), response.code, request.content_length, response.body_len()
+    );
+    let _ = index.lock().unwrap().write_all(line.as_bytes());
+}
+
+// Request line and headers, kept as one string and sliced on demand so a
+// request costs a single allocation however many headers it carries
+struct Request {
+    head: String,
+    method: Range<usize>,
+    target: Range<usize>,
+    path: Range<usize>,
+    query: Range<usize>,
+    version: Range<usize>,  // Empty if the request line has none
+    content_length: usize,
+    chunked: bool,  // Transfer-Encoding: chunked, so content_length is 0
+}
+
+impl Request {
+    // Lines may end in CRLF or a bare LF; a CR anywhere else is rejected
+    // since it could make peers disagree on where a header ends
+    fn parse(raw: Vec<u8>) -> Result<Request, ReadError> {
+        if has_bare_cr(&raw) {
+            return Err(ReadError::Invalid(400));
+        }
+        
+        // The slow path enforces this as it reads; a head that fit in the first
+        // buffer is checked here
+        if let Some(i) = raw.split(|&b| b == b'\n').position(|line| line.len() + 1 > config().max_header_line) {
+            return Err(ReadError::Invalid(line_too_long(i)));
+        }
+        
+        let head = String::from_utf8(raw).map_err(|_| ReadError::Invalid(400))?;
+        let line_end = head.find('\n').unwrap_or(head.len());
+        
+        let mut parts = head[..line_end].split_whitespace();
+        let (method, target) = match (parts.next(), parts.next()) {
+            (Some(method), Some(target)) => (method, target),
+            _ => return Err(ReadError::Invalid(400)),
+        };
+        let version = parts.next().map_or(line_end..line_end, |version| span(&head, version));
+        if decoded_len(target) > config().max_uri {
+            return Err(ReadError::Invalid(414));
+        }
+        let (method, target) = (span(&head, method), span(&head, target));
+        
+        let (path, query) = match head[target.clone()].find('?') {
+            Some(i) => (target.start..target.start + i, target.start + i + 1..target.end),
+            None => (target.clone(), target.end..target.end),
+        };
+        
+        let mut request = Request {
+            method,
+            target,
+            path,
+            query,
+            version,
+            content_length: 0,
+            chunked: false,
+            head,
+        };
+        // Where the body ends has to be beyond doubt, so a value that doesn't
+        // parse, or copies that disagree, get 400 rather than a guess
+        let mut content_length = None;
+        for (_, value) in request.headers().filter(|(name, _)| name.eq_ignore_ascii_case("content-length")) {
+            match (parse_content_length(value.as_bytes()), content_length) {
+                (Some(n), None) => content_length = Some(n),
+                (Some(n), Some(seen)) if n == seen => {}
+                _ => return Err(ReadError::Invalid(400)),
+            }
+        }
+        request.content_length = content_length.unwrap_or(0);
+        
+        // Chunked is the only transfer coding understood here, and with a
+        // Content-Length alongside, the two could frame the body differently
+        let codings: Vec<&str> = request
+            .headers()
+            .filter(|(name, _)| name.eq_ignore_ascii_case("transfer-encoding"))
+            .flat_map(|(_, value)| value.split(','))
+            .map(str::trim)
+            .collect();
+        match codings[..] {
+            [] => {}
+            [coding] if coding.eq_ignore_ascii_case("chunked") && content_length.is_none() => request.chunked = true,
+            _ => return Err(ReadError::Invalid(400)),
+        }
+        Ok(request)
+    }
+    
+    fn method(&self) -> &str {
+        &self.head[self.method.clone()]
+    }
+    
+    fn target(&self) -> &str {
+        &self.head[self.target.clone()]
+    }
+    
+    fn path(&self) -> &str {
+        &self.head[self.path.clone()]
+    }
+    
+    fn query(&self) -> &str {
+        &self.head[self.query.clone()]
+    }
+    
+    fn version(&self) -> &str {
+        &self.head[self.version.clone()]
+    }
+    
+    fn headers(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.head
+            .lines()
+            .skip(1)
+            .filter_map(|line| line.split_once(':'))
+            .map(|(k, v)| (k.trim(), v.trim()))
+    }
+    
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v)
+    }
+    
+    // Repeated headers form one list, so the last hop is in the last one
+    fn last_header(&self, name: &str) -> Option<&str> {
+        self.headers()
+            .filter(|(k, _)| k.eq_ignore_ascii_case(name))
+            .last()
+            .map(|(_, v)| v)
+    }
+    
+    // Name/value pairs from every Cookie header, separated by `;` (RFC 6265
+    // §5.4). A pair without `=` is skipped, quotes around a value are
+    // dropped, and of repeated names the first wins, as browsers send the
+    // most specific cookie first.
+    fn cookies(&self) -> HashMap<String, String> {
+        let mut cookies = HashMap::new();
+        let pairs = self
+            .headers()
+            .filter(|(name, _)| name.eq_ignore_ascii_case("cookie"))
+            .flat_map(|(_, value)| value.split(';'))
+            .filter_map(|pair| pair.split_once('='));
+        for (name, value) in pairs {
+            let name = name.trim();
+            if name.is_empty() {
+                continue;
+            }
+            let value = value.trim();
+            let value = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')).unwrap_or(value);
+            cookies.entry(name.to_string()).or_insert_with(|| value.to_string());
+        }
+        cookies
+    }
+}
+
+// Content-Length is 1*DIGIT (RFC 9110 §8.6): no sign, no whitespace inside,
+// no list. Leading zeros are legal, but not so many that the value is longer
+// than any usize could need.
+fn parse_content_length(digits: &[u8]) -> Option<usize> {
+    const MAX_DIGITS: usize = 20;  // u64::MAX has 20
+    if digits.is_empty() || digits.len() > MAX_DIGITS {
+        return None;
+    }
+    digits.iter().try_fold(0usize, |n, &b| {
+        if !b.is_ascii_digit() {
+            return None;
+        }
+        n.checked_mul(10)?.checked_add((b - b'0') as usize)
+    })
+}
+
+// Why no request came out of the connection
+enum ReadError {
+    Closed,        // Peer went away (or never sent anything)
+    Idle,          // Nothing arrived before --read-timeout
+    Invalid(u16),  // Malformed; answer with this status and close
+}
+
+// Reads the request line and headers. A small request arrives whole in the
+// reader's first fill, so it's parsed straight out of that buffer; only a
+// head split across segments falls back to line-at-a-time reads.
+fn read_request(reader: &mut BufReader<ClientStream>) -> Result<Request, ReadError> {
+    let buffered = match await_request(reader) {
+        Ok(buffered) if !buffered.is_empty() => buffered,
+        Err(ref e) if is_timeout(e) => return Err(ReadError::Idle),
+        _ => return Err(ReadError::Closed),
+    };
+    
+    if let Some(end) = find_head_end(buffered) {
+        let raw = buffered[..end].to_vec();
+        reader.consume(end);
+        return Request::parse(raw);
+    }
+    // CR-only line endings would otherwise leave us waiting for an LF forever
+    if has_bare_cr(buffered) {
+        return Err(ReadError::Invalid(400));
+    }
+    
+    let limit = config().max_header_line;
+    let mut raw = Vec::new();
+    let mut line = 0;
+    loop {
+        let start = raw.len();
+        // Read at most one line's worth, so a huge header is turned away as
+        // soon as it passes the limit instead of being buffered whole
+        match reader.by_ref().take(limit as u64).read_until(b'\n', &mut raw) {
+            // The head is incomplete here, so a timeout is worth telling the client about
+            Err(e) if is_timeout(&e) => return Err(ReadError::Invalid(408)),
+            Ok(0) | Err(_) => return Err(ReadError::Closed),
+            Ok(n) if n == limit && !raw.ends_with(b"\n") => return Err(ReadError::Invalid(line_too_long(line))),
+            Ok(_) => {}
+        }
+        if raw[start..] == b"\r\n"[..] || raw[start..] == b"\n"[..] {
+            return Request::parse(raw);
+        }
+        line += 1;
+    }
+}
+
+// fill_buf for the start of a request. Meanwhile the worker lists the
+// connection as waiting, so a draining server can hang up on it.
+fn await_request<'a>(reader: &'a mut BufReader<ClientStream>) -> io::Result<&'a [u8]> {
+    let (worker, stream) = match (current_worker(), &reader.get_ref().source) {
+        (Some(worker), Source::Socket(stream)) if reader.buffer().is_empty() => (worker, *stream),
+        _ => return reader.fill_buf(),
+    };
+    *worker.waiting.lock().unwrap() = sys::socket_handle(stream);
+    let filled = reader.fill_buf();
+    *worker.waiting.lock().unwrap() = 0;
+    filled
+}
+
+/// A request line over the limit is a too-long URI, anything later is a header
+fn line_too_long(line: usize) -> u16 {
+    if line == 0 { 414 } else { 431 }
+}
+
+// A CR followed by anything but LF; a CR at the very end may still get its LF
+fn has_bare_cr(buf: &[u8]) -> bool {
+    buf.windows(2).any(|w| w[0] == b'\r' && w[1] != b'\n')
+}
+
+// Length of `s` once percent-escapes are decoded; %XX counts as one byte,
+// and a % not followed by two hex digits counts as itself
+fn decoded_len(s: &str) -> usize {
+    let bytes = s.as_bytes();
+    let mut len = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes[i] == b'%'
+            && i + 2 < bytes.len()
+            && bytes[i + 1].is_ascii_hexdigit()
+            && bytes[i + 2].is_ascii_hexdigit();
+        i += if escaped { 3 } else { 1 };
+        len += 1;
+    }
+    len
+}
+
+// None if an escape decodes to invalid UTF-8; a stray % is kept as is
+fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match (bytes[i], bytes.get(i + 1..i + 3)) {
+            (b'%', Some(&[hi, lo])) if hi.is_ascii_hexdigit() && lo.is_ascii_hexdigit() => {
+                let hex = |b: u8| (b as char).to_digit(16).unwrap() as u8;
+                out.push(hex(hi) * 16 + hex(lo));
+                i += 3;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+// Byte range of `part` within `whole`, which it must be a slice of
+fn span(whole: &str, part: &str) -> Range<usize> {
+    let start = part.as_ptr() as usize - whole.as_ptr() as usize;
+    start..start + part.len()
+}
+
+// Offset just past the blank line ending the head, if it's in `buf`
+fn find_head_end(buf: &[u8]) -> Option<usize> {
+    let mut line_start = 0;
+    for (i, &b) in buf.iter().enumerate() {
+        if b == b'\n' {
+            let line = &buf[line_start..i];
+            if line.is_empty() || line == b"\r" {
+                return Some(i + 1);
+            }
+            line_start = i + 1;
+        }
+    }
+    None
+}
+
+// The client is waiting for permission to send the body
+fn send_continue(mut stream: &TcpStream, has_body: bool) {
+    if has_body && !config().ignore_expect {
+        let _ = stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n");
+    }
+}
+
+// Drains the body, timing from the first byte to the last. Bytes that came in
+// with the head are already buffered, so small uploads report a near-zero span.
+fn upload_rate(reader: &mut BufReader<ClientStream>, content_length: usize) -> Response {
+    // Reads of at least the reader's capacity skip its buffer and go
+    // straight to the socket; smaller ones are served out of it
+    let mut buf = vec![0u8; config().body_read_chunk.min(content_length.max(1))];
+    let mut received = 0;
+    let mut reads = 0;
+    let mut first_byte: Option<Instant> = None;
+    let mut last_byte = Instant::now();
+    
+    // Never reads past the body, whatever follows it on the connection
+    let mut body = reader.by_ref().take(content_length as u64);
+    while received < content_length {
+        match body.read(&mut buf) {
+            Ok(0) => break,  // Client gave up mid-body
+            Ok(n) => {
+                last_byte = Instant::now();
+                first_byte.get_or_insert(last_byte);
+                received += n;
+                reads += 1;
+            }
+            Err(e) if is_timeout(&e) => return make_response(408, "Request Timeout", "text/plain"),
+            Err(_) => break,
+        }
+    }
+    
+    if received < content_length {
+        return make_response(400, "Bad Request", "text/plain");
+    }
+    
+    let elapsed = first_byte.map_or(Duration::ZERO, |first| last_byte - first);
+    let secs = elapsed.as_secs_f64();
+    let rate = if secs > 0.0 { received as f64 / secs } else { 0.0 };
+    let json = format!(
+        r#"{{"route":"/upload-rate","bytes":{},"reads":{},"elapsed_ms":{:.3},"bytes_per_sec":{:.0}}}"#,
+        received, reads, secs * 1000.0, rate
+    );
+    make_response(200, &json, "application/json")
+}
+
+// Streams the body to a new file in --upload-dir, a buffer at a time, and
+// reports its size and name. The file is deleted once answered unless
+// --upload-ttl leaves it to the sweep; a failed upload is deleted at once.
+fn upload_file(reader: &mut BufReader<ClientStream>, request: &Request) -> Response {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    // Prefork children share the directory, hence the pid
+    let name = format!(
+        "{}{}-{}-{}", UPLOAD_PREFIX, now.as_millis(), process::id(), UPLOAD_SEQ.fetch_add(1, Ordering::Relaxed)
+    );
+    let path = config().upload_dir.join(&name);
+    let mut file = match fs::File::create(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Can't create {}: {}", path.display(), e);
+            return make_response(500, "Internal Server Error", "text/plain");
+        }
+    };
+    
+    let saved = if request.chunked {
+        save_body(&mut ChunkedBody::new(reader), &mut file)
+    } else {
+        let content_length = request.content_length;
+        match save_body(&mut reader.by_ref().take(content_length as u64), &mut file) {
+            Ok(n) if n < content_length => Err(400),  // Client gave up mid-body
+            saved => saved,
+        }
+    };
+    drop(file);
+    
+    match saved {
+        Ok(bytes) => {
+            if config().upload_ttl.is_none() {
+                let _ = fs::remove_file(&path);
+            }
+            let json = format!(r#"{{"route":"/upload-file","bytes":{},"file":"{}"}}"#, bytes, name);
+            make_response(200, &json, "application/json")
+        }
+        Err(code) => {
+            let _ = fs::remove_file(&path);
+            make_response(code, status_text(code), "text/plain")
+        }
+    }
+}
+
+// Copies `body` into `file` until it ends. Errors are the status to answer
+// with: 413 past --max-body, which only a chunked body can reach here.
+fn save_body(body: &mut impl Read, file: &mut fs::File) -> Result<usize, u16> {
+    let mut buf = vec![0u8; config().body_read_chunk];
+    let mut saved = 0;
+    loop {
+        let n = match body.read(&mut buf) {
+            Ok(0) => return Ok(saved),
+            Ok(n) => n,
+            Err(e) if is_timeout(&e) => return Err(408),
+            Err(_) => return Err(400),
+        };
+        saved += n;
+        if saved > config().max_body {
+            return Err(413);
+        }
+        if let Err(e) = file.write_all(&buf[..n]) {
+            eprintln!("Upload write failed: {}", e);
+            return Err(500);
+        }
+    }
+}
+
+// A chunked request body, decoded: reads return chunk data and end after the
+// last chunk and its trailers, which are skipped. Lines may end in a bare LF
+// like the head's, and anything malformed, or a close before the last chunk,
+// is an InvalidData or UnexpectedEof error.
+struct ChunkedBody<R> {
+    reader: R,
+    remaining: usize,  // Of the current chunk's data
+    done: bool,
+}
+
+impl<R: BufRead> ChunkedBody<R> {
+    fn new(reader: R) -> Self {
+        ChunkedBody { reader, remaining: 0, done: false }
+    }
+    
+    // One line, without its line ending; capped at --max-header-line
+    fn read_line(&mut self) -> io::Result<Vec<u8>> {
+        let limit = config().max_header_line as u64;
+        let mut line = Vec::new();
+        self.reader.by_ref().take(limit).read_until(b'\n', &mut line)?;
+        if line.pop() != Some(b'\n') {
+            let kind = if line.len() as u64 == limit { io::ErrorKind::InvalidData } else { io::ErrorKind::UnexpectedEof };
+            return Err(io::Error::new(kind, "unterminated chunk line"));
+        }
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        Ok(line)
+    }
+    
+    // The size line: hex digits, then optional extensions, which are ignored
+    fn read_size(&mut self) -> io::Result<usize> {
+        let line = self.read_line()?;
+        let digits = line.iter().take_while(|b| b.is_ascii_hexdigit()).count();
+        let rest = &line[digits..];
+        let size = std::str::from_utf8(&line[..digits]).ok().and_then(|hex| usize::from_str_radix(hex, 16).ok());
+        match size {
+            Some(size) if rest.is_empty() || rest[0] == b';' || rest[0] == b' ' || rest[0] == b'\t' => Ok(size),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, "bad chunk size")),
+        }
+    }
+}
+
+impl<R: BufRead> Read for ChunkedBody<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 && !self.done {
+            self.remaining = self.read_size()?;
+            if self.remaining == 0 {
+                while !self.read_line()?.is_empty() {}  // Trailer fields
+                self.done = true;
+            }
+        }
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+        
+        let want = buf.len().min(self.remaining);
+        let n = self.reader.read(&mut buf[..want])?;
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.remaining -= n;
+        if self.remaining == 0 && !self.read_line()?.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "chunk longer than its size"));
+        }
+        Ok(n)
+    }
+}
+
+// With --upload-ttl: once a second, delete uploads older than the TTL, so
+// each lives between TTL and TTL + 1s
+fn sweep_uploads(ttl: Duration) {
+    loop {
+        thread::sleep(Duration::from_secs(1));
+        let entries = match fs::read_dir(&config().upload_dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            let expired = entry.metadata().and_then(|m| m.modified()).is_ok_and(|modified| {
+                modified.elapsed().is_ok_and(|age| age >= ttl)
+            });
+            if expired && entry.file_name().to_string_lossy().starts_with(UPLOAD_PREFIX) {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+}
+
+// Deterministic CPU work: ?fib=N or ?primes=N, exactly one of them
+fn compute(query_string: &str) -> Response {
+    let query = parse_query(query_string);
+    let (kind, n, max) = match (query.get("fib"), query.get("primes")) {
+        (Some(n), None) => ("fib", n, COMPUTE_MAX_FIB),
+        (None, Some(n)) => ("primes", n, COMPUTE_MAX_PRIMES),
+        _ => return make_response(400, "Bad Request", "text/plain"),
+    };
+    let n = match n.parse::<u64>() {
+        Ok(n) if n <= max => n,
+        _ => return make_response(400, "Bad Request", "text/plain"),
+    };
+    
+    let start = Instant::now();
+    let result = match kind {
+        "fib" => fibonacci(n),
+        _ => count_primes_below(n),
+    };
+    let json = format!(
+        r#"{{"route":"/compute","{}":{},"result":{},"compute_us":{}}}"#,
+        kind, n, result, start.elapsed().as_micros()
+    );
+    make_response(200, &json, "application/json")
+}
+
+// Iterative, so the work is linear in n. Exact up to fib(93); past that it
+// keeps going modulo 2^64 so large n still costs CPU instead of overflowing.
+fn fibonacci(n: u64) -> u64 {
+    let (mut a, mut b) = (0u64, 1u64);
+    for _ in 0..n {
+        let next = a.wrapping_add(b);
+        a = b;
+        b = next;
+    }
+    a
+}
+
+// Trial division rather than a sieve, so it needs no memory proportional to n
+fn count_primes_below(n: u64) -> u64 {
+    let is_prime = |k: u64| {
+        k == 2
+            || (!k.is_multiple_of(2)
+                && (3..).step_by(2).take_while(|d| d * d <= k).all(|d| !k.is_multiple_of(d)))
+    };
+    (2..n).filter(|&k| is_prime(k)).count() as u64
+}
+
+// Always ok without --readiness-threshold. With it, a backlog past the
+// threshold fails the check so a load balancer sends traffic elsewhere
+// until the pool catches up. The check itself has already left the queue,
+// so the count is what's still waiting behind it.
+fn health() -> Response {
+    let queued = POOL_QUEUED.load(Ordering::Relaxed);
+    match config().readiness_threshold {
+        Some(threshold) if queued > threshold => {
+            let body = format!("overloaded: {} connections waiting for a worker", queued);
+            let mut response = make_response(503, &body, "text/plain");
+            response.headers.push(("Retry-After", "1".to_string()));
+            response
+        }
+        _ => make_response(200, "ok", "text/plain"),
+    }
+}
+
+// Checks a body against --schema: None if it passes, else 400 for a body that
+// isn't JSON at all, or 422 listing every field that's missing or mistyped
+fn validate_schema(body: &[u8]) -> Option<Response> {
+    let mut scanner = JsonScanner { bytes: body, pos: 0 };
+    let members = match scanner.top_level() {
+        Some(members) => members,
+        None => return Some(make_response(400, "Bad Request", "text/plain")),
+    };
+    
+    let mut errors = Vec::new();
+    let Some(members) = members else {
+        return Some(make_response(422, r#"{"errors":[{"field":"","error":"expected an object"}]}"#, "application/json"));
+    };
+    for rule in &config().schema {
+        // Of duplicate keys the last wins, as in most JSON parsers
+        let error = match members.iter().rev().find(|(name, _)| *name == rule.name) {
+            None => "missing".to_string(),
+            Some((_, kind)) if *kind == rule.kind => continue,
+            Some((_, kind)) => format!("expected {}, got {}", rule.kind.name(), kind.name()),
+        };
+        let mut json = String::from(r#"{"field":"#);
+        write_json_string(&rule.name, &mut json);
+        let _ = write!(json, r#","error":"{}"}}"#, error);
+        errors.push(json);
+    }
+    if errors.is_empty() {
+        return None;
+    }
+    let json = format!(r#"{{"errors":[{}]}}"#, errors.join(","));
+    Some(make_response(422, &json, "application/json"))
+}
+
+// Just enough of a JSON parser for --schema: checks the syntax of a whole
+// document (RFC 8259) but only keeps the top-level member names and types
+struct JsonScanner<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl JsonScanner<'_> {
+    // Deeper documents are refused rather than risk the recursion
+    const MAX_DEPTH: usize = 64;
+    
+    // None for invalid JSON; Some(None) for valid JSON that isn't an object
+    fn top_level(&mut self) -> Option<Option<Vec<(String, JsonType)>>> {
+        self.skip_whitespace();
+        let members = if self.peek() == Some(b'{') {
+            let mut members = Vec::new();
+            self.object(0, Some(&mut members))?;
+            Some(members)
+        } else {
+            self.value(0)?;
+            None
+        };
+        self.skip_whitespace();
+        if self.pos == self.bytes.len() { Some(members) } else { None }
+    }
+    
+    fn value(&mut self, depth: usize) -> Option<JsonType> {
+        if depth > JsonScanner::MAX_DEPTH {
+            return None;
+        }
+        self.skip_whitespace();
+        match self.peek()? {
+            b'{' => self.object(depth, None).map(|_| JsonType::Object),
+            b'[' => {
+                self.pos += 1;
+                self.skip_whitespace();
+                if self.eat(b']') {
+                    return Some(JsonType::Array);
+                }
+                loop {
+                    self.value(depth + 1)?;
+                    self.skip_whitespace();
+                    if self.eat(b']') {
+                        return Some(JsonType::Array);
+                    }
+                    if !self.eat(b',') {
+                        return None;
+                    }
+                }
+            }
+            b'"' => self.string().map(|_| JsonType::String),
+            b't' => self.literal(b"true").map(|_| JsonType::Boolean),
+            b'f' => self.literal(b"false").map(|_| JsonType::Boolean),
+            b'n' => self.literal(b"null").map(|_| JsonType::Null),
+            _ => self.number().map(|_| JsonType::Number),
+        }
+    }
+    
+    // Collects member names and types into `members` when given one
+    fn object(&mut self, depth: usize, mut members: Option<&mut Vec<(String, JsonType)>>) -> Option<()> {
+        self.pos += 1;  // The {
+        self.skip_whitespace();
+        if self.eat(b'}') {
+            return Some(());
+        }
+        loop {
+            self.skip_whitespace();
+            let name = self.string()?;
+            self.skip_whitespace();
+            if !self.eat(b':') {
+                return None;
+            }
+            let kind = self.value(depth + 1)?;
+            if let Some(members) = members.as_mut() {
+                members.push((name, kind));
+            }
+            self.skip_whitespace();
+            if self.eat(b'}') {
+                return Some(());
+            }
+            if !self.eat(b',') {
+                return None;
+            }
+        }
+    }
+    
+    fn string(&mut self) -> Option<String> {
+        if !self.eat(b'"') {
+            return None;
+        }
+        let mut out = Vec::new();
+        loop {
+            let b = self.next()?;
+            match b {
+                b'"' => return String::from_utf8(out).ok(),
+                b'\\' => match self.next()? {
+                    b'"' => out.push(b'"'),
+                    b'\\' => out.push(b'\\'),
+                    b'/' => out.push(b'/'),
+                    b'b' => out.push(8),
+                    b'f' => out.push(12),
+                    b'n' => out.push(b'\n'),
+                    b'r' => out.push(b'\r'),
+                    b't' => out.push(b'\t'),
+                    b'u' => {
+                        let hex = self.bytes.get(self.pos..self.pos + 4)?;
+                        let code = u32::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?;
+                        self.pos += 4;
+                        // A lone surrogate half has no char of its own
+                        let c = char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER);
+                        out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                    }
+                    _ => return None,
+                },
+                0..=0x1f => return None,
+                b => out.push(b),
+            }
+        }
+    }
+    
+    // -?(0|[1-9][0-9]*)(\.[0-9]+)?([eE][+-]?[0-9]+)?
+    fn number(&mut self) -> Option<()> {
+        self.eat(b'-');
+        match self.next()? {
+            b'0' => {}
+            b'1'..=b'9' => self.digits(),
+            _ => return None,
+        }
+        if self.eat(b'.') {
+            self.next().filter(u8::is_ascii_digit)?;
+            self.digits();
+        }
+        if self.eat(b'e') || self.eat(b'E') {
+            if !self.eat(b'+') {
+                self.eat(b'-');
+            }
+            self.next().filter(u8::is_ascii_digit)?;
+            self.digits();
+        }
+        Some(())
+    }
+    
+    fn digits(&mut self) {
+        while self.peek().is_some_and(|b| b.is_ascii_digit()) {
+            self.pos += 1;
+        }
+    }
+    
+    fn literal(&mut self, word: &[u8]) -> Option<()> {
+        if self.bytes[self.pos..].starts_with(word) {
+            self.pos += word.len();
+            Some(())
+        } else {
+            None
+        }
+    }
+    
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.pos += 1;
+        }
+    }
+    
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.pos).copied()
+    }
+    
+    fn next(&mut self) -> Option<u8> {
+        let b = self.peek()?;
+        self.pos += 1;
+        Some(b)
+    }
+    
+    fn eat(&mut self, b: u8) -> bool {
+        let matched = self.peek() == Some(b);
+        if matched {
+            self.pos += 1;
+        }
+        matched
+    }
+}
+
+// The request's cookies as a JSON object, sorted by name
+fn echo_cookies(request: &Request) -> Response {
+    let mut cookies: Vec<_> = request.cookies().into_iter().collect();
+    cookies.sort();
+    let mut json = String::from(r#"{"cookies":{"#);
+    for (i, (name, value)) in cookies.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        write_json_string(name, &mut json);
+        json.push(':');
+        write_json_string(value, &mut json);
+    }
+    json.push_str("}}");
+    make_response(200, &json, "application/json")
+}
+
+// One Set-Cookie per query parameter, scoped to the whole site. Names must
+// be tokens and values cookie-octets (RFC 6265 §4.1.1), since the query is
+// copied into the header as-is.
+fn set_cookies(query_string: &str) -> Response {
+    let is_token = |s: &str| {
+        !s.is_empty() && s.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+    };
+    let is_value = |s: &str| {
+        s.bytes().all(|b| matches!(b, 0x21 | 0x23..=0x2b | 0x2d..=0x3a | 0x3c..=0x5b | 0x5d..=0x7e))
+    };
+    
+    // Kept in query order, which is the order the headers go out in
+    let pairs: Vec<(&str, &str)> = query_string
+        .split('&')
+        .filter(|s| !s.is_empty())
+        .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+        .collect();
+    if pairs.is_empty() || !pairs.iter().all(|(name, value)| is_token(name) && is_value(value)) {
+        return make_response(400, "Bad Request", "text/plain");
+    }
+    
+    let mut json = String::from(r#"{"set":["#);
+    let mut response = make_response(200, "", "application/json");
+    for (i, (name, value)) in pairs.iter().enumerate() {
+        if i > 0 {
+            json.push(',');
+        }
+        write_json_string(name, &mut json);
+        response.headers.push(("Set-Cookie", format!("{}={}; Path=/", name, value)));
+    }
+    json.push_str("]}");
+    response.body = json.into_bytes();
+    response
+}
+
+// ?depth=N&breadth=M[&seed=S]: an object nested N deep with M members at
+// every level, leaves drawn from a generator seeded with S (default 1). The
+// tree is built in full and then serialized, so both costs are measured.
+fn json_tree(query_string: &str) -> Response {
+    let query = parse_query(query_string);
+    let number = |name: &str, default: Option<u64>| match query.get(name) {
+        Some(value) => value.parse::<u64>().ok(),
+        None => default,
+    };
+    let (depth, breadth, seed) = match (number("depth", None), number("breadth", None), number("seed", Some(1))) {
+        (Some(depth), Some(breadth), Some(seed))
+            if (1..=JSON_TREE_MAX_DEPTH as u64).contains(&depth)
+                && (1..=JSON_TREE_MAX_BREADTH as u64).contains(&breadth) =>
+        {
+            (depth as u32, breadth, seed)
+        }
+        _ => return make_response(400, "Bad Request", "text/plain"),
+    };
+    let nodes: u64 = (1..=depth).map(|level| breadth.pow(level)).sum();
+    if nodes > JSON_TREE_MAX_NODES {
+        return make_response(400, "Bad Request", "text/plain");
+    }
+    
+    // xorshift64 needs a nonzero state; SplitMix-style scrambling keeps
+    // nearby seeds from starting out alike
+    let mut state = (seed ^ 0x9e37_79b9_7f4a_7c15).wrapping_mul(0xbf58_476d_1ce4_e5b9) | 1;
+    let tree = JsonValue::tree(depth, breadth as usize, &mut state);
+    let mut json = String::with_capacity(nodes as usize * 16);
+    tree.write(&mut json);
+    make_response(200, &json, "application/json")
+}
+
+enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(i64),
+    Text(String),
+    Object(Vec<(String, JsonValue)>),
+}
+
+impl JsonValue {
+    fn tree(depth: u32, breadth: usize, state: &mut u64) -> JsonValue {
+        let members = (0..breadth)
+            .map(|i| {
+                let value = if depth > 1 { JsonValue::tree(depth - 1, breadth, state) } else { JsonValue::leaf(state) };
+                (format!("k{}", i), value)
+            })
+            .collect();
+        JsonValue::Object(members)
+    }
+    
+    fn leaf(state: &mut u64) -> JsonValue {
+        let mut next = || {
+            *state ^= *state << 13;
+            *state ^= *state >> 7;
+            *state ^= *state << 17;
+            *state
+        };
+        match next() % 4 {
+            0 => JsonValue::Null,
+            1 => JsonValue::Bool(next() & 1 == 1),
+            2 => JsonValue::Number(next() as i64 >> 16),
+            _ => {
+                let letters = next();
+                JsonValue::Text((0..8).map(|i| (b'a' + (letters >> (i * 5) & 31) as u8 % 26) as char).collect())
+            }
+        }
+    }
+    
+    fn write(&self, out: &mut String) {
+        match self {
+            JsonValue::Null => out.push_str("null"),
+            JsonValue::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+            JsonValue::Number(n) => {
+                let _ = write!(out, "{}", n);
+            }
+            JsonValue::Text(text) => write_json_string(text, out),
+            JsonValue::Object(members) => {
+                out.push('{');
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    write_json_string(key, out);
+                    out.push(':');
+                    value.write(out);
+                }
+                out.push('}');
+            }
+        }
+    }
+}
+
+fn write_json_string(text: &str, out: &mut String) {
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+// GET /static/<path> from --static DIR. When file.gz sits next to the file
+// and the client accepts gzip, the sidecar goes out as-is with
+// Content-Encoding: gzip (nginx's gzip_static); otherwise the plain file.
+// Nothing is compressed on the fly.
+fn serve_static(dir: &Path, request: &Request) -> Response {
+    let relative = match percent_decode(&request.path()["/static/".len()..]) {
+        Some(relative) => relative,
+        None => return make_response(400, "Bad Request", "text/plain"),
+    };
+    // Only plain names, so the path can't climb out of (or replace) `dir`
+    let safe = relative.split('/').all(|part| {
+        !part.is_empty() && part != "." && part != ".." && !part.contains(['\\', '\0'])
+    });
+    if !safe {
+        return make_response(400, "Bad Request", "text/plain");
+    }
+    
+    let path = dir.join(&relative);
+    let mut sidecar = path.clone().into_os_string();
+    sidecar.push(".gz");
+    let sidecar = PathBuf::from(sidecar);
+    let has_sidecar = sidecar.is_file();
+    let gzip = has_sidecar && accepts_encoding(request, "gzip");
+    
+    let file = if gzip { &sidecar } else { &path };
+    let meta = match fs::metadata(file) {
+        Ok(meta) if meta.is_file() => meta,
+        _ => return make_response(404, "Not Found", "text/plain"),
+    };
+    
+    // Size and mtime are what changes when the file does. The gzip copy has
+    // its own, so the two representations never share a validator.
+    let len = meta.len() as usize;
+    let modified = meta.modified().ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok()).unwrap_or_default();
+    let etag = format!("\"{:x}-{:x}\"", len, modified.as_nanos());
+    let last_modified = http_date(modified);
+    
+    let range = match request.header("range") {
+        // A stale If-Range validator means the client's partial copy is of an
+        // older file, so it gets the whole new one instead of a piece
+        Some(range) if if_range_matches(request, &etag, &last_modified) => byte_range(range, len),
+        _ => ByteRange::Whole,
+    };
+    if let ByteRange::Unsatisfiable = range {
+        let mut response = make_response(416, "Range Not Satisfiable", "text/plain");
+        response.headers.push(("Content-Range", format!("bytes */{}", len)));
+        return response;
+    }
+    // Only a whole file is streamed, and only to a client that can take the
+    // trailer; a range is small enough to buffer
+    let streamed = match range {
+        ByteRange::Whole if config().digest_trailer && accepts_trailers(request) => match fs::File::open(file) {
+            Ok(opened) => Some(opened),
+            Err(_) => return make_response(404, "Not Found", "text/plain"),
+        },
+        _ => None,
+    };
+    let body = if streamed.is_some() {
+        Vec::new()
+    } else {
+        match (fs::read(file), &range) {
+            (Ok(body), ByteRange::Part(part)) if body.len() >= part.end => body[part.clone()].to_vec(),
+            (Ok(body), ByteRange::Whole) => body,
+            _ => return make_response(404, "Not Found", "text/plain"),
+        }
+    };
+    let code = if let ByteRange::Part(_) = range { 206 } else { 200 };
+    let mut headers = vec![
+        ("Accept-Ranges", "bytes".to_string()),
+        ("ETag", etag),
+        ("Last-Modified", last_modified),
+    ];
+    // A client that won't take trailers gets the same digest up front instead
+    if config().digest_trailer && streamed.is_none() && code == 200 {
+        let mut digest = Sha256::new();
+        digest.update(&body);
+        headers.push(("Digest", format!("sha-256={}", base64(&digest.finish()))));
+    }
+    
+    // Typed by the original name, not the .gz
+    let ext = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+    let mut response = Response {
+        code,
+        content_type: mime_from_extension(ext),
+        headers,
+        body,
+        file: streamed,
+        timing: None,
+        keep_alive: false,
+        version: "HTTP/1.1",
+    };
+    if let ByteRange::Part(part) = range {
+        response.headers.push(("Content-Range", format!("bytes {}-{}/{}", part.start, part.end - 1, len)));
+    }
+    if gzip {
+        response.headers.push(("Content-Encoding", "gzip".to_string()));
+    }
+    if has_sidecar {
+        // Caches must not hand the gzipped copy to clients that can't take it
+        response.headers.push(("Vary", "Accept-Encoding".to_string()));
+    }
+    response
+}
+
+fn mime_from_extension(ext: &str) -> &'static str {
+    MIME_TYPES
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(ext))
+        .map_or(&config().default_content_type, |(_, mime)| mime)
+}
+
+// What a Range header asks of a body of `len` bytes
+enum ByteRange {
+    Whole,  // No usable range: several ranges, another unit or bad syntax
+    Part(Range<usize>),
+    Unsatisfiable,
+}
+
+// Handles one `bytes=first-last`, `bytes=first-` or `bytes=-suffix` range.
+// Anything else may be ignored (RFC 9110 §14.2), so it falls back to the
+// whole body rather than an error; multipart responses aren't supported.
+fn byte_range(header: &str, len: usize) -> ByteRange {
+    let spec = match header.split_once('=') {
+        Some((unit, spec)) if unit.trim().eq_ignore_ascii_case("bytes") && !spec.contains(',') => spec.trim(),
+        _ => return ByteRange::Whole,
+    };
+    let (first, last) = match spec.split_once('-') {
+        Some(bounds) => bounds,
+        None => return ByteRange::Whole,
+    };
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    
+    let (start, end) = match (digits(first), digits(last)) {
+        // The last N bytes
+        (false, true) if first.is_empty() => match last.parse::<usize>() {
+            Ok(0) => return ByteRange::Unsatisfiable,
+            Ok(n) => (len.saturating_sub(n), len),
+            Err(_) => (0, len),  // More digits than usize: a suffix longer than any file
+        },
+        (true, false) if last.is_empty() => match first.parse::<usize>() {
+            Ok(start) => (start, len),
+            Err(_) => return ByteRange::Unsatisfiable,
+        },
+        (true, true) => match (first.parse::<usize>(), last.parse::<usize>()) {
+            (Ok(start), Ok(last)) if last >= start => (start, last.saturating_add(1).min(len)),
+            (Ok(_), Ok(_)) => return ByteRange::Whole,
+            (Ok(start), Err(_)) => (start, len),
+            (Err(_), _) => return ByteRange::Unsatisfiable,
+        },
+        _ => return ByteRange::Whole,
+    };
+    if start >= len {
+        return ByteRange::Unsatisfiable;
+    }
+    ByteRange::Part(start..end)
+}
+
+// If-Range holds either an entity tag, compared strongly, or the date that
+// was sent as Last-Modified. The date has to come back exactly: its only
+// source is this server, and any other value can't prove the copy is current.
+fn if_range_matches(request: &Request, etag: &str, last_modified: &str) -> bool {
+    match request.header("if-range") {
+        None => true,
+        Some(tag) if tag.starts_with('"') || tag.starts_with("W/") => tag == etag,
+        Some(date) => date == last_modified,
+    }
+}
+
+// Whether Accept-Encoding allows `coding`, either by name or through `*`.
+// A q=0 weight refuses it, and naming it outright overrides the wildcard.
+fn accepts_encoding(request: &Request, coding: &str) -> bool {
+    let header = match request.header("accept-encoding") {
+        Some(header) => header,
+        None => return false,
+    };
+    
+    let mut wildcard = false;
+    for (name, refused) in weighted_items(header) {
+        if name.eq_ignore_ascii_case(coding) {
+            return !refused;
+        }
+        if name == "*" {
+            wildcard = !refused;
+        }
+    }
+    wildcard
+}
+
+// The one content type each route succeeds with, so Accept can be checked
+// before the handler runs. Every route has a single representation, and its
+// error responses go out whatever Accept says. None for what isn't
+// negotiated: unknown routes, those off behind a flag, and CONNECT.
+fn route_content_type(request: &Request) -> Option<&'static str> {
+    let debug = config().debug_endpoints;
+    let content_type = match (request.method(), request.path()) {
+        ("GET", "/something") if parse_query(request.query()).get("json") == Some(&"true") => "application/json",
+        ("GET", "/" | "/something" | "/stream") => "text/plain",
+        ("GET", "/healthz") if config().health => "text/plain",
+        ("GET", "/debug/bad-chunked" | "/debug/bad-length" | "/debug/dribble") if debug => "text/plain",
+        ("GET", "/metrics") if config().metrics => "text/plain; version=0.0.4",
+        ("POST", "/something" | "/upload-rate" | "/upload-file" | "/barrier/release") => "application/json",
+        ("GET", "/compute" | "/json-tree" | "/cookies" | "/cookies/set" | "/barrier/wait" | "/longpoll") => {
+            "application/json"
+        }
+        ("GET", "/debug/sysstat" | "/debug/workers") if debug => "application/json",
+        ("GET" | "POST", "/admin/routes") if config().admin_token.is_some() => "application/json",
+        // Typed by name, as serve_static does
+        ("GET", p) if p.starts_with("/static/") && config().static_dir.is_some() => {
+            let name = percent_decode(p)?;
+            mime_from_extension(Path::new(&name).extension().and_then(|ext| ext.to_str()).unwrap_or(""))
+        }
+        _ => return None,
+    };
+    Some(content_type)
+}
+
+// Whether Accept allows `content_type` (its parameters aside). Without the
+// header anything goes. Otherwise the most specific matching range decides,
+// type/subtype over type/* over */*, so `*/*, text/plain;q=0` refuses only
+// text/plain. Nothing matching means refused.
+fn accepts_type(request: &Request, content_type: &str) -> bool {
+    let header = match request.header("accept") {
+        Some(header) => header,
+        None => return true,
+    };
+    let essence = content_type.split(';').next().unwrap_or("").trim();
+    let main_type = essence.split('/').next().unwrap_or("");
+    
+    let mut best: Option<(u8, bool)> = None;  // Specificity and refusal
+    for (range, refused) in weighted_items(header) {
+        let specificity = if range.eq_ignore_ascii_case(essence) {
+            2
+        } else if range.strip_suffix("/*").is_some_and(|t| t.eq_ignore_ascii_case(main_type)) {
+            1
+        } else if range == "*/*" {
+            0
+        } else {
+            continue;
+        };
+        if best.is_none_or(|(seen, _)| specificity > seen) {
+            best = Some((specificity, refused));
+        }
+    }
+    best.is_some_and(|(_, refused)| !refused)
+}
+
+// A comma-separated list of items with optional `;` parameters, such as
+// Accept or Accept-Encoding, as (item, refused) pairs. Only the weight
+// matters here, and only whether it's q=0.
+fn weighted_items(header: &str) -> impl Iterator<Item = (&str, bool)> {
+    header.split(',').filter(|item| !item.trim().is_empty()).map(|item| {
+        let mut params = item.split(';');
+        let name = params.next().unwrap_or("").trim();
+        let refused = params.any(|param| {
+            param.trim().strip_prefix("q=").and_then(|q| q.trim().parse::<f32>().ok()) == Some(0.0)
+        });
+        (name, refused)
+    })
+}
+
+// RFC 9110 §10.1.4: `trailers` in TE says the client won't discard trailer
+// fields. It's a bare keyword among transfer codings, so it takes no q-value.
+fn accepts_trailers(request: &Request) -> bool {
+    request
+        .headers()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("te"))
+        .flat_map(|(_, value)| value.split(','))
+        .any(|item| item.trim().eq_ignore_ascii_case("trailers"))
+}
+
+// set_read_timeout surfaces as WouldBlock on Unix and TimedOut on Windows
+fn is_timeout(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
+
+fn parse_query(query_string: &str) -> HashMap<&str, &str> {
+    query_string
+        .split('&')
+        .filter(|s| !s.is_empty())
+        .filter_map(|p| p.split_once('='))
+        .collect()
+}
+
+// Holds the worker for up to `timeout` seconds, waking every tick to check
+// whether the client is still there. Returns None if it disconnected.
+fn longpoll(stream: &TcpStream, query_string: &str) -> Option<Response> {
+    // Any integer is clamped into range, anything else gets the default;
+    // server.js reads the parameter the same way
+    let secs = parse_query(query_string)
+        .get("timeout")
+        .and_then(|v| v.parse::<i64>().ok())
+        .map_or(LONGPOLL_DEFAULT_SECS, |n| n.clamp(0, LONGPOLL_MAX_SECS as i64) as u64);
+    let timeout = Duration::from_secs(secs);
+    let start = Instant::now();
+    
+    while start.elapsed() < timeout {
+        if client_disconnected(stream) {
+            return None;
+        }
+        thread::sleep(LONGPOLL_TICK.min(timeout.saturating_sub(start.elapsed())));
+    }
+    
+    let json = format!(
+        r#"{{"route":"/longpoll","timeout":{},"waited_ms":{}}}"#,
+        secs, start.elapsed().as_millis()
+    );
+    Some(make_response(200, &json, "application/json"))
+}
+
+// Opens the tunnel and relays bytes both ways until either side closes,
+// then closes the other. Bytes the client sent right behind the request are
+// forwarded first. Returns an error response if there's no tunnel to open.
+fn connect(stream: &TcpStream, reader: &mut BufReader<ClientStream>, target: &str, mode: ConnectMode) -> Option<Response> {
+    // Authority form only: host:port, with brackets around an IPv6 host
+    let port_ok = target.rsplit_once(':').is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok());
+    if !port_ok {
+        return Some(make_response(400, "Bad Request", "text/plain"));
+    }
+    let upstream = match mode {
+        ConnectMode::Echo => None,
+        ConnectMode::Tunnel => {
+            let addr = target.to_socket_addrs().ok().and_then(|mut addrs| addrs.next());
+            match addr.map(|addr| TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)) {
+                Some(Ok(upstream)) => Some(upstream),
+                _ => return Some(make_response(502, "Bad Gateway", "text/plain")),
+            }
+        }
+    };
+    
+    record_status(200);
+    // Tunnelled bytes aren't requests, and --dump-requests mustn't buffer them all
+    reader.get_mut().recorded = None;
+    let mut client = stream;
+    if client.write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n").is_err() {
+        return None;
+    }
+    match upstream {
+        None => {
+            let _ = io::copy(reader, &mut client);
+        }
+        Some(upstream) => {
+            upstream.set_nodelay(true).ok();
+            let (mut from_upstream, mut to_client) = match (upstream.try_clone(), stream.try_clone()) {
+                (Ok(from), Ok(to)) => (from, to),
+                _ => return None,
+            };
+            let downstream = thread::spawn(move || {
+                let _ = io::copy(&mut from_upstream, &mut to_client);
+                let _ = to_client.shutdown(Shutdown::Write);
+            });
+            let mut to_upstream = &upstream;
+            let _ = io::copy(reader, &mut to_upstream);
+            let _ = upstream.shutdown(Shutdown::Write);
+            let _ = downstream.join();
+        }
+    }
+    let _ = client.shutdown(Shutdown::Write);
+    None
+}
+
+// After the 101 the connection carries raw bytes, each sent straight back,
+// until the client closes. Bytes that came in behind the head are echoed first.
+fn raw_echo(stream: &TcpStream, reader: &mut BufReader<ClientStream>) {
+    record_status(101);
+    // Like a CONNECT tunnel, what follows isn't requests
+    reader.get_mut().recorded = None;
+    let mut client = stream;
+    if client.write_all(b"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: raw-echo\r\n\r\n").is_err() {
+        return;
+    }
+    let _ = io::copy(reader, &mut client);
+    let _ = client.shutdown(Shutdown::Write);
+}
+
+// Writes one of the BAD_CHUNKED bodies after an otherwise normal chunked head,
+// then closes. Returns a 400 listing the variants if none matched. Chunked
+// framing can't be sent to an HTTP/1.0 client, so it gets the same bytes as
+// a body the close ends.
+fn bad_chunked(stream: &TcpStream, request: &Request, query_string: &str) -> Option<Response> {
+    let wanted = parse_query(query_string).get("variant").copied().unwrap_or("");
+    let body = match BAD_CHUNKED.iter().find(|(name, _)| *name == wanted) {
+        Some((_, body)) => body,
+        None => {
+            let names: Vec<&str> = BAD_CHUNKED.iter().map(|(name, _)| *name).collect();
+            return Some(make_response(400, &format!("variant must be one of: {}", names.join(", ")), "text/plain"));
+        }
+    };
+    
+    record_status(200);
+    let version = response_version(request);
+    let framing = if version == "HTTP/1.1" { "Transfer-Encoding: chunked\r\n" } else { "" };
+    let mut out = format!("{} 200 OK\r\nContent-Type: text/plain\r\n{}Connection: close\r\n\r\n", version, framing).into_bytes();
+    out.extend_from_slice(body);
+    let _ = send(stream, &out);
+    let _ = stream.shutdown(Shutdown::Write);
+    None
+}
+
+// Writes BAD_LENGTH_BODY under one of the BAD_LENGTH headers, then closes.
+// Returns a 400 listing the variants if none matched.
+fn bad_length(stream: &TcpStream, request: &Request, query_string: &str) -> Option<Response> {
+    let wanted = parse_query(query_string).get("variant").copied().unwrap_or("");
+    let header = match BAD_LENGTH.iter().find(|(name, _)| *name == wanted) {
+        Some((_, header)) => header,
+        None => {
+            let names: Vec<&str> = BAD_LENGTH.iter().map(|(name, _)| *name).collect();
+            return Some(make_response(400, &format!("variant must be one of: {}", names.join(", ")), "text/plain"));
+        }
+    };
+    
+    record_status(200);
+    let mut out = format!(
+        "{} 200 OK\r\nContent-Type: text/plain\r\n{}Connection: close\r\n\r\n",
+        response_version(request), header
+    ).into_bytes();
+    out.extend_from_slice(BAD_LENGTH_BODY);
+    let _ = send(stream, &out);
+    let _ = stream.shutdown(Shutdown::Write);
+    None
+}
+
+// Sends the head at once, then the body one byte per interval_ms, so the
+// client's read timeout fires long before the end. Between bytes the worker
+// watches for the client hanging up, and stops as soon as it does.
+fn dribble(stream: &TcpStream, request: &Request, query_string: &str) -> Result<Response, ConnectionEnd> {
+    let query = parse_query(query_string);
+    let param = |name, default, max| match query.get(name) {
+        None => Some(default),
+        Some(v) => v.parse::<u64>().ok().filter(|&n| n >= 1 && n <= max),
+    };
+    let interval = param("interval_ms", DRIBBLE_DEFAULT_MS, DRIBBLE_MAX_MS);
+    let bytes = param("bytes", DRIBBLE_DEFAULT_BYTES, DRIBBLE_MAX_BYTES);
+    let (interval, bytes) = match (interval, bytes) {
+        (Some(ms), Some(bytes)) => (Duration::from_millis(ms), bytes),
+        _ => return Ok(make_response(400, "Bad Request", "text/plain")),
+    };
+    
+    record_status(200);
+    let head = format!(
+        "{} 200 OK\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response_version(request), bytes
+    );
+    send(stream, head.as_bytes()).map_err(|_| write_end(ConnectionEnd::WriteError))?;
+    for _ in 0..bytes {
+        let next = Instant::now() + interval;
+        while let Some(left) = next.checked_duration_since(Instant::now()).filter(|left| !left.is_zero()) {
+            if client_disconnected(stream) {
+                return Err(ConnectionEnd::ClientClose);
+            }
+            thread::sleep(LONGPOLL_TICK.min(left));
+        }
+        send(stream, b".").map_err(|_| write_end(ConnectionEnd::ClientClose))?;
+    }
+    let _ = stream.shutdown(Shutdown::Write);
+    Err(ConnectionEnd::ServerClose)
+}
+
+// A chunked body of `chunks` chunks of `size` dots, `interval_ms` apart: the
+// chatty stream --response-buffer-flush-threshold is for. The pause between
+// chunks is also when an overdue buffer goes out and a hang-up is noticed.
+// An HTTP/1.0 client can't read chunked framing, so it gets the same dots,
+// buffered the same way, in a body the close ends.
+fn stream_chunks(stream: &TcpStream, request: &Request, query_string: &str) -> Result<Response, ConnectionEnd> {
+    let query = parse_query(query_string);
+    let param = |name, default, min, max| match query.get(name) {
+        None => Some(default),
+        Some(v) => v.parse::<u64>().ok().filter(|&n| n >= min && n <= max),
+    };
+    let chunks = param("chunks", STREAM_DEFAULT_CHUNKS, 1, STREAM_MAX_CHUNKS);
+    let size = param("size", STREAM_DEFAULT_SIZE, 1, STREAM_CHUNK as u64);
+    let interval = param("interval_ms", 0, 0, STREAM_MAX_INTERVAL_MS);
+    let (chunks, size, interval) = match (chunks, size, interval) {
+        (Some(chunks), Some(size), Some(ms)) => (chunks, size as usize, Duration::from_millis(ms)),
+        _ => return Ok(make_response(400, "Bad Request", "text/plain")),
+    };
+    
+    record_status(200);
+    let version = response_version(request);
+    let framed = version == "HTTP/1.1";
+    let head = format!(
+        "{} 200 OK\r\nContent-Type: text/plain\r\n{}Connection: close\r\n\r\n",
+        version, if framed { "Transfer-Encoding: chunked\r\n" } else { "" }
+    );
+    send(stream, head.as_bytes()).map_err(|_| write_end(ConnectionEnd::WriteError))?;
+    let data = vec![b'.'; size];
+    let mut out = if framed { ChunkWriter::new(stream) } else { ChunkWriter::unframed(stream) };
+    for i in 0..chunks {
+        let next = Instant::now() + if i > 0 { interval } else { Duration::ZERO };
+        while let Some(left) = next.checked_duration_since(Instant::now()).filter(|left| !left.is_zero()) {
+            if client_disconnected(stream) {
+                return Err(ConnectionEnd::ClientClose);
+            }
+            let flush_in = out.deadline().map_or(left, |due| due.saturating_duration_since(Instant::now()));
+            thread::sleep(LONGPOLL_TICK.min(left).min(flush_in));
+            out.flush_if_due().map_err(|_| write_end(ConnectionEnd::ClientClose))?;
+        }
+        out.chunk(&data).map_err(|_| write_end(ConnectionEnd::ClientClose))?;
+    }
+    out.finish("").map_err(|_| write_end(ConnectionEnd::ClientClose))?;
+    let _ = stream.shutdown(Shutdown::Write);
+    Err(ConnectionEnd::ServerClose)
+}
+
+// System load and this process's footprint, each null where the platform
+// can't tell. Read fresh from /proc on every request, so a harness can poll it.
+fn sysstat() -> String {
+    fn or_null<T: std::fmt::Display>(value: Option<T>) -> String {
+        value.map_or_else(|| "null".to_string(), |v| v.to_string())
+    }
+    let load = sys::load_average().map(|[one, five, fifteen]| format!("[{:.2},{:.2},{:.2}]", one, five, fifteen));
+    format!(
+        r#"{{"load_average":{},"rss_bytes":{},"open_fds":{}}}"#,
+        or_null(load), or_null(sys::rss_bytes()), or_null(sys::open_fds())
+    )
+}
+
+// Every worker's state and counters. These are separate relaxed loads, not
+// one snapshot, so under load a worker may move on while it's being read.
+fn debug_workers() -> String {
+    let stats = WORKER_STATS.get().map_or(&[][..], |stats| &stats[..]);
+    let mut busy = 0;
+    let mut workers = Vec::with_capacity(stats.len());
+    for (id, worker) in stats.iter().enumerate() {
+        let mut json = format!(r#"{{"id":{},"#, id);
+        match worker.state.load(Ordering::Relaxed) {
+            WORKER_IDLE => json.push_str(r#""state":"idle","#),
+            WORKER_READING => json.push_str(r#""state":"reading","#),
+            WORKER_EXITED => json.push_str(r#""state":"exited","#),
+            state => {
+                busy += 1;
+                let _ = write!(json, r#""state":"busy","route":"{}","#, ROUTES[state - WORKER_BUSY]);
+            }
+        }
+        let _ = write!(
+            json,
+            r#""requests":{},"connections":{}}}"#,
+            worker.requests.load(Ordering::Relaxed),
+            worker.served.load(Ordering::Relaxed)
+        );
+        workers.push(json);
+    }
+    format!(r#"{{"busy":{},"total":{},"workers":[{}]}}"#, busy, stats.len(), workers.join(","))
+}
+
+// Lists which routes are on, after a POST has applied its `disable=` and
+// `enable=` lists. Route names are those of ROUTES, matched verbatim.
+// /admin/routes itself and the catch-all can't be switched off.
+fn admin_routes(request: &Request) -> Response {
+    let expected = config().admin_token.as_deref().unwrap_or_default();
+    let token = request.header("authorization").and_then(|v| v.strip_prefix("Bearer "));
+    if token != Some(expected) {
+        let mut response = make_response(401, "Unauthorized", "text/plain");
+        response.headers.push(("WWW-Authenticate", "Bearer".to_string()));
+        return response;
+    }
+    
+    if request.method() == "POST" {
+        let mut changes = Vec::new();
+        for (key, value) in parse_query(request.query()) {
+            let disable = match key {
+                "disable" => true,
+                "enable" => false,
+                _ => return make_response(400, &format!("Unknown parameter: {}", key), "text/plain"),
+            };
+            for name in value.split(',') {
+                match ROUTES.iter().position(|route| *route == name) {
+                    Some(i) if !matches!(name, "/admin/routes" | "other") => changes.push((i, disable)),
+                    _ => return make_response(400, &format!("Can't switch route: {}", name), "text/plain"),
+                }
+            }
+        }
+        // Checked in full first, so a bad name leaves every route as it was
+        for (i, disable) in changes {
+            ROUTE_DISABLED[i].store(disable, Ordering::Relaxed);
+        }
+    }
+    
+    let routes: Vec<String> = ROUTES[..ROUTES.len() - 1]
+        .iter()
+        .zip(&ROUTE_DISABLED)
+        .map(|(route, disabled)| format!(r#""{}":{}"#, route, !disabled.load(Ordering::Relaxed)))
+        .collect();
+    make_response(200, &format!(r#"{{"routes":{{{}}}}}"#, routes.join(",")), "application/json")
+}
+
+// Parks the worker until the next /barrier/release or the timeout. At most
+// pool size - 1 requests can wait, so a worker is always left to serve the
+// release; past that the request is turned away with 503.
+fn barrier_wait(query_string: &str) -> Response {
+    let secs = parse_query(query_string)
+        .get("timeout")
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(BARRIER_DEFAULT_SECS)
+        .min(BARRIER_MAX_SECS);
+    let start = Instant::now();
+    
+    let mut state = BARRIER.lock().unwrap();
+    if state.waiting + 1 >= config().pool_size() {
+        return make_response(503, "Service Unavailable", "text/plain");
+    }
+    state.waiting += 1;
+    let generation = state.generation;
+    
+    let (mut state, _) = BARRIER_RELEASED
+        .wait_timeout_while(state, Duration::from_secs(secs), |s| s.generation == generation)
+        .unwrap();
+    state.waiting -= 1;
+    let released = state.generation != generation;
+    // How long after the release this waiter got the lock back: the herd's tail
+    let wake_delay = match state.released_at {
+        Some(at) if released => at.elapsed().as_micros(),
+        _ => 0,
+    };
+    drop(state);
+    
+    let json = format!(
+        r#"{{"route":"/barrier/wait","released":{},"waited_ms":{},"wake_delay_us":{}}}"#,
+        released, start.elapsed().as_millis(), wake_delay
+    );
+    make_response(200, &json, "application/json")
+}
+
+// Wakes every parked /barrier/wait at once and reports how many there were
+fn barrier_release() -> Response {
+    let mut state = BARRIER.lock().unwrap();
+    state.generation += 1;
+    state.released_at = Some(Instant::now());
+    let released = state.waiting;
+    drop(state);
+    BARRIER_RELEASED.notify_all();
+    
+    let json = format!(r#"{{"route":"/barrier/release","released":{}}}"#, released);
+    make_response(200, &json, "application/json")
+}
+
+// A readable socket that yields 0 bytes means the peer closed its end
+fn client_disconnected(stream: &TcpStream) -> bool {
+    let mut buf = [0u8; 1];
+    if stream.set_nonblocking(true).is_err() {
+        return true;
+    }
+    let gone = match stream.peek(&mut buf) {
+        Ok(0) => true,
+        Ok(_) => false,
+        Err(e) => e.kind() != std::io::ErrorKind::WouldBlock,
+    };
+    let _ = stream.set_nonblocking(false);
+    gone
+}
+
+thread_local! {
+    // xorshift64 state, seeded differently in every thread
+    static RNG: Cell<u64> = Cell::new(RandomState::new().build_hasher().finish() | 1);
+}
+
+fn random_u64() -> u64 {
+    RNG.with(|rng| {
+        let mut x = rng.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        rng.set(x);
+        x
+    })
+}
+
+// Uniform in [0, 100)
+fn random_percent() -> f64 {
+    // The top 53 bits fill an f64 mantissa exactly
+    (random_u64() >> 11) as f64 / (1u64 << 53) as f64 * 100.0
+}
+
+thread_local! {
+    // Each worker formats its log lines into one reused buffer
+    static LOG_LINE: RefCell<String> = RefCell::new(String::with_capacity(256));
+}
+
+// `<time> <client> "<method> <target>" <status> <bytes> <micros>us`
+fn log_access(stream: &TcpStream, request: &Request, response: &Response, started: Instant) {
+    if !config().access_log {
+        return;
+    }
+    
+    if config().log_format != LogFormat::Default {
+        log_access_clf(stream, request, response);
+        return;
+    }
+    
+    LOG_LINE.with(|line| {
+        let mut line = line.borrow_mut();
+        line.clear();
+        
+        write_log_time(&mut line);
+        let _ = match client_ip(stream, request) {
+            Some(ip) => write!(line, "{}", ip),
+            None => write!(line, "-"),
+        };
+        let _ = writeln!(
+            line,
+            " \"{} {}\" {} {} {}us",
+            request.method(), request.target(), response.code, response.body_len(),
+            started.elapsed().as_micros()
+        );
+        
+        let _ = io::stdout().lock().write_all(line.as_bytes());
+    });
+}
+
+// Apache's common log format, `%h %l %u %t "%r" %>s %b`, and with
+// --log-format combined `"%{Referer}i" "%{User-Agent}i"` after it. Identity
+// and user are always "-", and so is a missing header or an empty body.
+// Quotes, backslashes and control bytes inside the quoted fields are
+// escaped the way Apache does it, so a hostile User-Agent can't fake a field.
+fn log_access_clf(stream: &TcpStream, request: &Request, response: &Response) {
+    LOG_LINE.with(|line| {
+        let mut line = line.borrow_mut();
+        line.clear();
+        
+        let _ = match client_ip(stream, request) {
+            Some(ip) => write!(line, "{} - - [", ip),
+            None => write!(line, "- - - ["),
+        };
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let secs = now.as_secs();
+        let (year, month, day) = civil_from_days(secs / 86400);
+        let _ = write!(
+            line,
+            "{:02}/{}/{:04}:{:02}:{:02}:{:02} +0000] \"",
+            day, MONTHS[month as usize - 1], year,
+            secs / 3600 % 24, secs / 60 % 60, secs % 60
+        );
+        let request_line = request.head.lines().next().unwrap_or("").trim_end_matches('\r');
+        write_log_escaped(&mut line, request_line);
+        let _ = match response.body_len() {
+            0 => write!(line, "\" {} -", response.code),
+            bytes => write!(line, "\" {} {}", response.code, bytes),
+        };
+        if config().log_format == LogFormat::Combined {
+            for name in ["referer", "user-agent"] {
+                line.push_str(" \"");
+                write_log_escaped(&mut line, request.header(name).unwrap_or("-"));
+                line.push('"');
+            }
+        }
+        line.push('\n');
+        
+        let _ = io::stdout().lock().write_all(line.as_bytes());
+    });
+}
+
+fn write_log_escaped(line: &mut String, value: &str) {
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                line.push('\\');
+                line.push(c);
+            }
+            c if c.is_ascii_control() => {
+                let _ = write!(line, "\\x{:02x}", c as u32);
+            }
+            c => line.push(c),
+        }
+    }
+}
+
+// One line per connection: "<time> <peer> requests=N in=BYTES out=BYTES
+// lifetime=MSms end=REASON". Bytes are what crossed the socket, heads included.
+fn log_connection(stream: &TcpStream, client: &ClientStream, opened: Instant, end: ConnectionEnd) {
+    LOG_LINE.with(|line| {
+        let mut line = line.borrow_mut();
+        line.clear();
+        
+        write_log_time(&mut line);
+        let _ = match stream.peer_addr() {
+            Ok(peer) => write!(line, "{}", peer),
+            Err(_) => write!(line, "-"),
+        };
+        let _ = writeln!(
+            line,
+            " requests={} in={} out={} lifetime={:.3}ms end={}",
+            client.requests, client.read_total, client.written,
+            opened.elapsed().as_secs_f64() * 1000.0, end.name()
+        );
+        
+        let _ = io::stdout().lock().write_all(line.as_bytes());
+    });
+}
+
+// The --log-time prefix shared by the access and connection logs
+fn write_log_time(line: &mut String) {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    match config().log_time {
+        LogTime::Iso8601 => {
+            write_iso8601(line, now);
+            line.push(' ');
+        }
+        LogTime::Epoch => {
+            let _ = write!(line, "{}.{:03} ", now.as_secs(), now.subsec_millis());
+        }
+        LogTime::None => {}
+    }
+}
+
+// The peer address, or with --trust-proxy the address our proxy says it
+// saw. Only the last hop is used: that's the one the proxy appended itself,
+// while anything before it came from the client and could be made up.
+fn client_ip(stream: &TcpStream, request: &Request) -> Option<IpAddr> {
+    let peer = stream.peer_addr().ok().map(|addr| addr.ip());
+    if !config().trust_proxy {
+        return peer;
+    }
+    
+    let forwarded = request.last_header("forwarded").and_then(|v| {
+        let hop = v.rsplit(',').next()?;
+        let value = hop
+            .split(';')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(k, _)| k.trim().eq_ignore_ascii_case("for"))?
+            .1;
+        parse_node(value.trim().trim_matches('"'))
+    });
+    let x_forwarded_for = || {
+        request
+            .last_header("x-forwarded-for")
+            .and_then(|v| v.rsplit(',').next())
+            .and_then(|hop| parse_node(hop.trim()))
+    };
+    
+    // Obfuscated or "unknown" nodes can't be used, so fall back to the peer
+    forwarded.or_else(x_forwarded_for).or(peer)
+}
+
+// An address with an optional port: 192.0.2.1, 192.0.2.1:80, [2001:db8::1]:80
+fn parse_node(node: &str) -> Option<IpAddr> {
+    node.parse::<IpAddr>()
+        .or_else(|_| node.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .or_else(|_| node.trim_start_matches('[').trim_end_matches(']').parse())
+        .ok()
+}
+
+fn write_iso8601(out: &mut String, since_epoch: Duration) {
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days(secs / 86400);
+    let _ = write!(
+        out,
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year, month, day,
+        secs / 3600 % 24, secs / 60 % 60, secs % 60,
+        since_epoch.subsec_millis()
+    );
+}
+
+// The Date header's value, shared by every response so that formatting it
+// is paid once per refresh rather than once per request
+static DATE: RwLock<String> = RwLock::new(String::new());
+
+// Refreshes DATE on every multiple of `interval` since the epoch. With whole
+// seconds that's right as the second ticks over, so the header is never
+// more than one interval behind the clock.
+fn refresh_date(interval: Duration) {
+    loop {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        *DATE.write().unwrap() = http_date(now);
+        let interval = interval.as_nanos();
+        thread::sleep(Duration::from_nanos((interval - now.as_nanos() % interval) as u64));
+    }
+}
+
+fn write_date(head: &mut String) {
+    let date = DATE.read().unwrap();
+    if !date.is_empty() {
+        let _ = write!(head, "Date: {}\r\n", date);
+        return;
+    }
+    drop(date);
+    
+    // Answering before the refresh thread's first pass, so fill it in here
+    let mut date = DATE.write().unwrap();
+    if date.is_empty() {
+        *date = http_date(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default());
+    }
+    let _ = write!(head, "Date: {}\r\n", date);
+}
+
+// IMF-fixdate, e.g. "Sun, 06 Nov 1994 08:49:37 GMT"
+fn http_date(since_epoch: Duration) -> String {
+    const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];  // 1970-01-01 was a Thursday
+    let secs = since_epoch.as_secs();
+    let days = secs / 86400;
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+        DAYS[(days % 7) as usize], day, MONTHS[month as usize - 1], year,
+        secs / 3600 % 24, secs / 60 % 60, secs % 60
+    )
+}
+
+// For http_date and the common log format's timestamp
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+// Days since 1970-01-01 to (year, month, day), after Howard Hinnant's
+// `civil_from_days`; restricted to dates on or after the epoch
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+struct Response {
+    code: u16,
+    content_type: &'static str,
+    headers: Vec<(&'static str, String)>,  // Beyond the ones every response gets
+    body: Vec<u8>,
+    file: Option<fs::File>,  // Sent chunked in place of `body`, with a Digest trailer (needs TE: trailers)
+    timing: Option<(Duration, Duration)>,  // Parse and route phases, for Server-Timing
+    keep_alive: bool,
+    version: &'static str,  // For the status line; see response_version
+}
+
+impl Response {
+    // Serializes head and body into one buffer so they go out in a single
+    // write. Returns how many bytes went out.
+    fn write_to(&self, mut stream: &TcpStream) -> io::Result<u64> {
+        record_status(self.code);
+        let head = self.head();
+        let written = if let Some(ref file) = self.file {
+            send(stream, head.as_bytes())?;
+            head.len() as u64 + write_digest_chunked(file, stream)?
+        } else {
+            let mut out = Vec::with_capacity(head.len() + self.body.len());
+            out.extend_from_slice(head.as_bytes());
+            out.extend_from_slice(&self.body);
+            let len = out.len() as u64;
+            if WRITER.get().is_some() {
+                let fit = allowed(out.len());
+                out.truncate(fit);
+                write_via_writer(stream, out)?;
+                if (fit as u64) < len {
+                    return Err(cut_off(stream));
+                }
+            } else {
+                send(stream, &out)?;
+            }
+            len
+        };
+        stream.flush()?;
+        if !config().content_length {
+            // Send the FIN right behind the body, since it's the only end marker
+            stream.shutdown(Shutdown::Write)?;
+        }
+        Ok(written)
+    }
+    
+    // Status line and headers, up to and including the blank line
+    fn head(&self) -> String {
+        let serialize_started = Instant::now();
+        let mut head = format!(
+            "{} {} {}\r\nContent-Type: {}\r\n",
+            self.version, self.code, status_text(self.code), self.content_type
+        );
+        write_date(&mut head);
+        // Keep-alive is never used without Content-Length, so then the close
+        // alone marks the end of the body
+        if self.file.is_some() {
+            head.push_str("Transfer-Encoding: chunked\r\nTrailer: Digest\r\n");
+        } else if config().content_length {
+            let _ = write!(head, "Content-Length: {}\r\n", self.body.len());
+        }
+        head.push_str(if self.keep_alive { "Connection: keep-alive\r\n" } else { "Connection: close\r\n" });
+        if config().security_headers {
+            head.push_str(SECURITY_HEADERS);
+        }
+        for (name, value) in &self.headers {
+            let _ = write!(head, "{}: {}\r\n", name, value);
+        }
+        // Serialize covers building the head up to this point; the header
+        // can't include writing itself out
+        if let Some((parse, route)) = self.timing {
+            let serialize = serialize_started.elapsed();
+            let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+            let _ = write!(
+                head,
+                "Server-Timing: parse;dur={:.3}, route;dur={:.3}, serialize;dur={:.3}\r\n",
+                ms(parse), ms(route), ms(serialize)
+            );
+        }
+        head.push_str("\r\n");
+        head
+    }
+    
+    fn body_len(&self) -> u64 {
+        match self.file {
+            Some(ref file) => file.metadata().map(|meta| meta.len()).unwrap_or(0),
+            None => self.body.len() as u64,
+        }
+    }
+}
+
+thread_local! {
+    // Response bytes sent on the connection this worker is serving, and
+    // whether --max-bytes-per-connection has cut one short. Reset as each
+    // connection starts, since a worker holds one connection at a time.
+    static CONN_SENT: Cell<u64> = const { Cell::new(0) };
+    static CONN_CUT: Cell<bool> = const { Cell::new(false) };
+}
+
+// Writes as much of `bytes` as --max-bytes-per-connection leaves room for.
+// A write that doesn't fit goes out up to the cap and fails.
+fn send(mut stream: &TcpStream, bytes: &[u8]) -> io::Result<()> {
+    let fit = allowed(bytes.len());
+    stream.write_all(&bytes[..fit])?;
+    if fit < bytes.len() {
+        return Err(cut_off(stream));
+    }
+    Ok(())
+}
+
+// How much of a `len`-byte write fits under the cap, counted as sent
+fn allowed(len: usize) -> usize {
+    let Some(cap) = config().max_bytes_per_connection else {
+        return len;
+    };
+    CONN_SENT.with(|sent| {
+        let fit = cap.saturating_sub(sent.get()).min(len as u64);
+        sent.set(sent.get() + fit);
+        fit as usize
+    })
+}
+
+// Logs the truncation and half-closes, so the client reads everything up
+// to the cap and then the end of the stream
+fn cut_off(stream: &TcpStream) -> io::Error {
+    CONN_CUT.with(|cut| cut.set(true));
+    let peer = stream.peer_addr().map_or_else(|_| "-".to_string(), |peer| peer.to_string());
+    eprintln!(
+        "Response to {} cut off at --max-bytes-per-connection ({} bytes); closing",
+        peer, config().max_bytes_per_connection.unwrap_or_default()
+    );
+    let _ = stream.shutdown(Shutdown::Write);
+    io::Error::other("--max-bytes-per-connection reached")
+}
+
+// Why a connection ended on a failed write: the cap, or `otherwise`
+fn write_end(otherwise: ConnectionEnd) -> ConnectionEnd {
+    if CONN_CUT.with(Cell::get) { ConnectionEnd::MaxBytes } else { otherwise }
+}
+
+// --centralized-writer: workers queue finished responses here and block until
+// the writer thread has sent them
+static WRITER: OnceLock<mpsc::Sender<WriteJob>> = OnceLock::new();
+static WRITER_BATCHES: AtomicU64 = AtomicU64::new(0);
+static WRITER_WRITES: AtomicU64 = AtomicU64::new(0);
+
+struct WriteJob {
+    stream: LentStream,
+    bytes: Vec<u8>,
+    done: mpsc::Sender<io::Result<()>>,
+}
+
+// A worker's stream, lent to the writer thread. The worker waits for the
+// writer's reply before going on, so the stream outlives every use of this.
+struct LentStream(*const TcpStream);
+
+unsafe impl Send for LentStream {}
+
+thread_local! {
+    // Each worker's reply channel, made once rather than per response
+    static WRITE_DONE: (mpsc::Sender<io::Result<()>>, mpsc::Receiver<io::Result<()>>) = mpsc::channel();
+}
+
+fn write_via_writer(stream: &TcpStream, bytes: Vec<u8>) -> io::Result<()> {
+    let gone = || io::Error::other("the writer thread has exited");
+    let writer = WRITER.get().ok_or_else(gone)?;
+    WRITE_DONE.with(|(done, replies)| {
+        let job = WriteJob { stream: LentStream(stream), bytes, done: done.clone() };
+        writer.send(job).map_err(|_| gone())?;
+        replies.recv().unwrap_or_else(|_| Err(gone()))
+    })
+}
+
+// Takes whatever has queued up since the last pass and writes it back to
+// back, so under load one wakeup serves many workers. Each response is still
+// its own write(2): there's no call that writes to several sockets at once.
+fn run_writer(queue: mpsc::Receiver<WriteJob>) {
+    let mut batch = Vec::new();
+    while let Ok(job) = queue.recv() {
+        batch.push(job);
+        batch.extend(queue.try_iter());
+        WRITER_BATCHES.fetch_add(1, Ordering::Relaxed);
+        WRITER_WRITES.fetch_add(batch.len() as u64, Ordering::Relaxed);
+        for job in batch.drain(..) {
+            // SAFETY: the worker that lent the stream is blocked on `done`
+            let mut stream = unsafe { &*job.stream.0 };
+            let _ = job.done.send(stream.write_all(&job.bytes));
+        }
+    }
+}
+
+// Copies `file` out as chunks while hashing it, then ends the body with the
+// hash as a trailer. A read error leaves the body without its last chunk, so
+// the client sees a truncated stream rather than a short one that looks whole.
+// Returns the length of the chunked body.
+fn write_digest_chunked(mut file: &fs::File, stream: &TcpStream) -> io::Result<u64> {
+    let mut digest = Sha256::new();
+    let mut buf = vec![0; STREAM_CHUNK];
+    let mut out = ChunkWriter::new(stream);
+    loop {
+        let n = match file.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        digest.update(&buf[..n]);
+        out.chunk(&buf[..n])?;
+    }
+    out.finish(&format!("Digest: sha-256={}\r\n", base64(&digest.finish())))
+}
+
+// Chunked body output. Each chunk is framed into a buffer, which is written
+// out once it holds --response-buffer-flush-threshold bytes, once its oldest
+// chunk is --response-buffer-flush-ms old (checked on the next chunk or
+// flush_if_due), and at the end. A chunk's size line, data and CRLF always
+// go out in one write, so Nagle never splits them.
+struct ChunkWriter<'a> {
+    stream: &'a TcpStream,
+    framed: bool,  // False writes the data bare, for a body the close ends
+    buf: Vec<u8>,
+    since: Option<Instant>,  // When the oldest chunk in `buf` was added
+    written: u64,
+}
+
+// Chunks framed and the writes that carried them, for /metrics
+static STREAM_CHUNKS: AtomicU64 = AtomicU64::new(0);
+static STREAM_WRITES: AtomicU64 = AtomicU64::new(0);
+
+impl<'a> ChunkWriter<'a> {
+    fn new(stream: &'a TcpStream) -> Self {
+        ChunkWriter { stream, framed: true, buf: Vec::new(), since: None, written: 0 }
+    }
+    
+    fn unframed(stream: &'a TcpStream) -> Self {
+        ChunkWriter { framed: false, ..ChunkWriter::new(stream) }
+    }
+    
+    fn chunk(&mut self, data: &[u8]) -> io::Result<()> {
+        if self.framed {
+            let _ = write!(self.buf, "{:x}\r\n", data.len());
+            self.buf.extend_from_slice(data);
+            self.buf.extend_from_slice(b"\r\n");
+            STREAM_CHUNKS.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.buf.extend_from_slice(data);
+        }
+        self.since.get_or_insert_with(Instant::now);
+        if self.buf.len() >= config().flush_threshold {
+            return self.flush();
+        }
+        self.flush_if_due()
+    }
+    
+    // When what's buffered has to go out by, if anything is and there's a cap
+    fn deadline(&self) -> Option<Instant> {
+        Some(self.since? + config().flush_after?)
+    }
+    
+    fn flush_if_due(&mut self) -> io::Result<()> {
+        match self.deadline() {
+            Some(due) if due <= Instant::now() => self.flush(),
+            _ => Ok(()),
+        }
+    }
+    
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        send(self.stream, &self.buf)?;
+        STREAM_WRITES.fetch_add(1, Ordering::Relaxed);
+        self.written += self.buf.len() as u64;
+        self.buf.clear();
+        self.since = None;
+        Ok(())
+    }
+    
+    // Adds the last chunk and `trailers` (CRLF-terminated lines, or nothing)
+    // and flushes. Returns the length of the whole chunked body. Unframed,
+    // there's no last chunk and the trailers are dropped.
+    fn finish(mut self, trailers: &str) -> io::Result<u64> {
+        if self.framed {
+            let _ = write!(self.buf, "0\r\n{}\r\n", trailers);
+        }
+        self.flush()?;
+        Ok(self.written)
+    }
+}
+
+// FIPS 180-4 SHA-256, just enough for the Digest trailer
+struct Sha256 {
+    state: [u32; 8],
+    block: [u8; 64],
+    filled: usize,
+    total: u64,
+}
+
+impl Sha256 {
+    const K: [u32; 64] = [
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+        0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+        0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+        0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+        0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+        0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+        0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+    ];
+    
+    fn new() -> Sha256 {
+        Sha256 {
+            state: [
+                0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+            ],
+            block: [0; 64],
+            filled: 0,
+            total: 0,
+        }
+    }
+    
+    fn update(&mut self, mut data: &[u8]) {
+        self.total += data.len() as u64;
+        while !data.is_empty() {
+            let take = (64 - self.filled).min(data.len());
+            self.block[self.filled..self.filled + take].copy_from_slice(&data[..take]);
+            self.filled += take;
+            data = &data[take..];
+            if self.filled == 64 {
+                self.compress();
+                self.filled = 0;
+            }
+        }
+    }
+    
+    fn finish(mut self) -> [u8; 32] {
+        let bits = self.total * 8;
+        self.update(&[0x80]);
+        while self.filled != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bits.to_be_bytes());
+        let mut out = [0; 32];
+        for (bytes, word) in out.chunks_mut(4).zip(self.state.iter()) {
+            bytes.copy_from_slice(&word.to_be_bytes());
+        }
+        out
+    }
+    
+    fn compress(&mut self) {
+        let mut w = [0u32; 64];
+        for (i, word) in self.block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16].wrapping_add(s0).wrapping_add(w[i - 7]).wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for (&k, &word) in Sha256::K.iter().zip(w.iter()) {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h.wrapping_add(s1).wrapping_add(ch).wrapping_add(k).wrapping_add(word);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (word, add) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *word = word.wrapping_add(add);
+        }
+    }
+}
+
+// Standard alphabet with padding (RFC 4648 §4), as Digest values use
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for group in data.chunks(3) {
+        let n = group.iter().enumerate().fold(0u32, |n, (i, &byte)| n | (byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= group.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+fn make_response(code: u16, body: &str, content_type: &'static str) -> Response {
+    Response {
+        code,
+        content_type,
+        headers: Vec::new(),
+        body: body.as_bytes().to_vec(),
+        file: None,
+        timing: None,
+        keep_alive: false,
+        version: "HTTP/1.1",
+    }
+}
+
+fn status_text(code: u16) -> &'static str {
+    match code {
+        101 => "Switching Protocols",
+        200 => "OK",
+        206 => "Partial Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        408 => "Request Timeout",
+        413 => "Payload Too Large",
+        405 => "Method Not Allowed",
+        406 => "Not Acceptable",
+        414 => "URI Too Long",
+        416 => "Range Not Satisfiable",
+        422 => "Unprocessable Entity",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        _ => "Error",
+    }
+}
+
+fn record_status(code: u16) {
+    match TRACKED_STATUSES.iter().position(|&c| c == code) {
+        Some(i) => STATUS_COUNTS[i].fetch_add(1, Ordering::Relaxed),
+        None => STATUS_CLASS_COUNTS[(code as usize / 100).clamp(1, 5) - 1].fetch_add(1, Ordering::Relaxed),
+    };
+}
+
+// Prometheus text exposition format
+fn render_metrics() -> String {
+    let mut out = String::from(
+        "# HELP responses_total Responses sent, by status code.\n# TYPE responses_total counter\n",
+    );
+    for (code, count) in TRACKED_STATUSES.iter().zip(&STATUS_COUNTS) {
+        out += &format!("responses_total{{status=\"{}\"}} {}\n", code, count.load(Ordering::Relaxed));
+    }
+    for (i, count) in STATUS_CLASS_COUNTS.iter().enumerate() {
+        let count = count.load(Ordering::Relaxed);
+        if count > 0 {
+            out += &format!("responses_total{{status=\"{}xx\"}} {}\n", i + 1, count);
+        }
+    }
+    
+    if !config().budgets.is_empty() {
+        out += "# HELP shed_total Requests answered 503 for queueing past their --budget.\n\
+                # TYPE shed_total counter\n";
+        for (budget, count) in config().budgets.iter().zip(shed_counts()) {
+            let count = count.load(Ordering::Relaxed);
+            out += &format!("shed_total{{route=\"{}\"}} {}\n", budget.path, count);
+        }
+    }
+    
+    out += "# HELP request_duration_seconds From reading a routed request to writing its response.\n\
+            # TYPE request_duration_seconds histogram\n";
+    let mut cumulative = 0;
+    for (i, count) in latency_counts().iter().enumerate() {
+        cumulative += count.load(Ordering::Relaxed);
+        match config().latency_buckets.get(i) {
+            Some(le) => out += &format!("request_duration_seconds_bucket{{le=\"{}\"}} {}\n", le, cumulative),
+            None => out += &format!("request_duration_seconds_bucket{{le=\"+Inf\"}} {}\n", cumulative),
+        }
+    }
+    out += &format!(
+        "request_duration_seconds_sum {}\nrequest_duration_seconds_count {}\n",
+        LATENCY_SUM_NANOS.load(Ordering::Relaxed) as f64 / 1e9, cumulative
+    );
+    
+    if let Some(stats) = WORKER_STATS.get() {
+        out += "# HELP worker_served_total Connections handled, by worker thread.\n\
+                # TYPE worker_served_total counter\n";
+        for (id, worker) in stats.iter().enumerate() {
+            out += &format!("worker_served_total{{worker=\"{}\"}} {}\n", id, worker.served.load(Ordering::Relaxed));
+        }
+    }
+    out += &format!(
+        "# HELP dispatch_out_of_order_total Connections started after one accepted later than them.\n\
+         # TYPE dispatch_out_of_order_total counter\n\
+         dispatch_out_of_order_total {}\n",
+        DISPATCH_OUT_OF_ORDER.load(Ordering::Relaxed)
+    );
+    
+    if config().adaptive_concurrency {
+        out += &format!(
+            "# HELP adaptive_concurrency_limit Requests --adaptive-concurrency lets run at once.\n\
+             # TYPE adaptive_concurrency_limit gauge\n\
+             adaptive_concurrency_limit {}\n\
+             # HELP adaptive_in_flight Requests holding a place under that limit.\n\
+             # TYPE adaptive_in_flight gauge\n\
+             adaptive_in_flight {}\n\
+             # HELP adaptive_rejected_total Requests answered 503 for arriving past the limit.\n\
+             # TYPE adaptive_rejected_total counter\n\
+             adaptive_rejected_total {}\n",
+            CONCURRENCY_LIMIT.load(Ordering::Relaxed),
+            IN_FLIGHT.load(Ordering::Relaxed),
+            CONCURRENCY_REJECTED.load(Ordering::Relaxed)
+        );
+    }
+    
+    if config().centralized_writer {
+        out += &format!(
+            "# HELP writer_batches_total Wakeups of the --centralized-writer thread.\n\
+             # TYPE writer_batches_total counter\n\
+             writer_batches_total {}\n\
+             # HELP writer_writes_total Responses the --centralized-writer thread has sent.\n\
+             # TYPE writer_writes_total counter\n\
+             writer_writes_total {}\n",
+            WRITER_BATCHES.load(Ordering::Relaxed),
+            WRITER_WRITES.load(Ordering::Relaxed)
+        );
+    }
+    
+    out += &format!(
+        "# HELP stream_chunks_total Chunks sent in chunked response bodies.\n\
+         # TYPE stream_chunks_total counter\n\
+         stream_chunks_total {}\n\
+         # HELP stream_writes_total Socket writes those chunks went out in.\n\
+         # TYPE stream_writes_total counter\n\
+         stream_writes_total {}\n",
+        STREAM_CHUNKS.load(Ordering::Relaxed),
+        STREAM_WRITES.load(Ordering::Relaxed)
+    );
+    
+    if let Some(total) = allocs::total() {
+        out += &format!(
+            "# HELP allocations_total Heap allocations and reallocations, all threads.\n\
+             # TYPE allocations_total counter\n\
+             allocations_total {}\n\
+             # HELP allocated_bytes_total Bytes they asked for.\n\
+             # TYPE allocated_bytes_total counter\n\
+             allocated_bytes_total {}\n",
+            total.count, total.bytes
+        );
+    }
+    
+    if config().recycle_after.is_some() {
+        out += &format!(
+            "# HELP worker_recycled_total Worker threads replaced by --recycle-after.\n\
+             # TYPE worker_recycled_total counter\n\
+             worker_recycled_total {}\n",
+            RECYCLED.load(Ordering::Relaxed)
+        );
+    }
+    
+    if config().drop_percent > 0.0 {
+        out += &format!(
+            "# HELP dropped_total Requests hung up on unanswered by --drop-percent.\n\
+             # TYPE dropped_total counter\n\
+             dropped_total {}\n",
+            DROPPED.load(Ordering::Relaxed)
+        );
+    }
+    
+    // Accept queue pressure (Linux only): if these move during a run, the
+    // backlog rather than the workers is turning connections away
+    if let Some((overflows, drops)) = sys::listen_overflows() {
+        out += &format!(
+            "# HELP listen_overflows_total Connections dropped because an accept queue was full (host-wide).\n\
+             # TYPE listen_overflows_total counter\n\
+             listen_overflows_total {}\n\
+             # HELP listen_drops_total SYNs dropped by listening sockets for any reason (host-wide).\n\
+             # TYPE listen_drops_total counter\n\
+             listen_drops_total {}\n",
+            overflows, drops
+        );
+    }
+    if let Some((depth, max)) = LISTENER.get().and_then(sys::listen_queue) {
+        out += &format!(
+            "# HELP listen_queue_depth Connections waiting in this server's accept queue.\n\
+             # TYPE listen_queue_depth gauge\n\
+             listen_queue_depth {}\n\
+             # HELP listen_queue_max Accept queue capacity (the listen backlog).\n\
+             # TYPE listen_queue_max gauge\n\
+             listen_queue_max {}\n",
+            depth, max
+        );
+    }
+    out
+}
+
+// Allocation counting, compiled in with `rustc --cfg count_allocs` since the
+// wrapper taxes every allocation. Each thread keeps its own running count,
+// so a request's allocations are the difference across it on its worker.
+#[cfg(count_allocs)]
+mod allocs {
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    
+    #[derive(Clone, Copy)]
+    pub struct Allocs {
+        pub count: u64,
+        pub bytes: u64,
+    }
+    
+    thread_local! {
+        // Const-initialised and without a destructor, so reaching it never allocates
+        static THREAD: Cell<Allocs> = const { Cell::new(Allocs { count: 0, bytes: 0 }) };
+    }
+    static COUNT: AtomicU64 = AtomicU64::new(0);
+    static BYTES: AtomicU64 = AtomicU64::new(0);
+    
+    struct Counting;
+    
+    #[global_allocator]
+    static ALLOCATOR: Counting = Counting;
+    
+    fn record(bytes: usize) {
+        COUNT.fetch_add(1, Ordering::Relaxed);
+        BYTES.fetch_add(bytes as u64, Ordering::Relaxed);
+        // Gone during thread teardown, when only the totals count it
+        let _ = THREAD.try_with(|thread| {
+            let Allocs { count, bytes: total } = thread.get();
+            thread.set(Allocs { count: count + 1, bytes: total + bytes as u64 });
+        });
+    }
+    
+    // A realloc counts as an allocation of its new size
+    unsafe impl GlobalAlloc for Counting {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            record(layout.size());
+            System.alloc(layout)
+        }
+        
+        unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+            record(layout.size());
+            System.alloc_zeroed(layout)
+        }
+        
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            record(new_size);
+            System.realloc(ptr, layout, new_size)
+        }
+        
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+    }
+    
+    pub fn snapshot() -> Option<Allocs> {
+        Some(THREAD.with(Cell::get))
+    }
+    
+    pub fn total() -> Option<Allocs> {
+        Some(Allocs { count: COUNT.load(Ordering::Relaxed), bytes: BYTES.load(Ordering::Relaxed) })
+    }
+}
+
+#[cfg(not(count_allocs))]
+mod allocs {
+    pub struct Allocs {
+        pub count: u64,
+        pub bytes: u64,
+    }
+    
+    pub fn snapshot() -> Option<Allocs> {
+        None
+    }
+    
+    pub fn total() -> Option<Allocs> {
+        None
+    }
+}
+
+// Socket options std doesn't expose yet, set through libc directly
+#[cfg(unix)]
+mod sys {
+    use super::Linger;
+    use std::io;
+    use std::net::{TcpListener, TcpStream};
+    use std::os::raw::{c_int, c_void};
+    use std::os::unix::io::AsRawFd;
+    
+    #[cfg(target_os = "linux")]
+    const SOL_SOCKET: c_int = 1;
+    #[cfg(target_os = "linux")]
+    const SO_LINGER: c_int = 13;
+    #[cfg(not(target_os = "linux"))]
+    const SOL_SOCKET: c_int = 0xffff;
+    #[cfg(target_os = "macos")]
+    const SO_LINGER: c_int = 0x1080;  // SO_LINGER_SEC; plain SO_LINGER counts ticks
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    const SO_LINGER: c_int = 0x0080;
+    
+    #[repr(C)]
+    struct RawLinger {
+        l_onoff: c_int,
+        l_linger: c_int,
+    }
+    
+    const SHUT_RD: c_int = 0;
+    #[cfg(target_os = "linux")]
+    const SIG_BLOCK: c_int = 0;
+    #[cfg(not(target_os = "linux"))]
+    const SIG_BLOCK: c_int = 1;
+    const SIGINT: c_int = 2;
+    const SIGTERM: c_int = 15;
+    
+    // At least as big as any platform's sigset_t; glibc's is the largest
+    #[repr(C)]
+    struct SigSet([u64; 16]);
+    
+    extern "C" {
+        fn setsockopt(fd: c_int, level: c_int, name: c_int, value: *const c_void, len: u32) -> c_int;
+        #[cfg(target_os = "linux")]
+        fn getsockopt(fd: c_int, level: c_int, name: c_int, value: *mut c_void, len: *mut u32) -> c_int;
+        fn shutdown(fd: c_int, how: c_int) -> c_int;
+        fn sigemptyset(set: *mut SigSet) -> c_int;
+        fn sigaddset(set: *mut SigSet, signal: c_int) -> c_int;
+        fn pthread_sigmask(how: c_int, set: *const SigSet, old: *mut SigSet) -> c_int;
+        fn sigwait(set: *const SigSet, signal: *mut c_int) -> c_int;
+    }
+    
+    fn set_option<T>(fd: c_int, level: c_int, name: c_int, value: &T) -> io::Result<()> {
+        let len = std::mem::size_of::<T>() as u32;
+        let ret = unsafe { setsockopt(fd, level, name, value as *const T as *const c_void, len) };
+        if ret == 0 { Ok(()) } else { Err(io::Error::last_os_error()) }
+    }
+    
+    pub fn set_linger(stream: &TcpStream, linger: Linger) -> io::Result<()> {
+        let raw = match linger {
+            Linger::Off => RawLinger { l_onoff: 0, l_linger: 0 },
+            Linger::Secs(secs) => RawLinger { l_onoff: 1, l_linger: secs.min(c_int::MAX as u32) as c_int },
+        };
+        set_option(stream.as_raw_fd(), SOL_SOCKET, SO_LINGER, &raw)
+    }
+    
+    // Other Unixes have no such option, so there it's quietly left alone
+    pub fn set_notsent_lowat(stream: &TcpStream, bytes: u32) -> io::Result<()> {
+        const IPPROTO_TCP: c_int = 6;
+        #[cfg(target_os = "linux")]
+        const TCP_NOTSENT_LOWAT: Option<c_int> = Some(25);
+        #[cfg(target_os = "macos")]
+        const TCP_NOTSENT_LOWAT: Option<c_int> = Some(0x201);
+        #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+        const TCP_NOTSENT_LOWAT: Option<c_int> = None;
+        
+        match TCP_NOTSENT_LOWAT {
+            Some(name) => set_option(stream.as_raw_fd(), IPPROTO_TCP, name, &(bytes.min(c_int::MAX as u32) as c_int)),
+            None => Ok(()),
+        }
+    }
+    
+    // EMFILE, ENFILE, ENOBUFS and ENOMEM: accept can't succeed until
+    // something else releases a descriptor or memory
+    pub fn out_of_resources(e: &io::Error) -> bool {
+        #[cfg(target_os = "linux")]
+        const ENOBUFS: i32 = 105;
+        #[cfg(not(target_os = "linux"))]
+        const ENOBUFS: i32 = 55;
+        matches!(e.raw_os_error(), Some(12) | Some(23) | Some(24) | Some(ENOBUFS))
+    }
+    
+    // (ListenOverflows, ListenDrops) from the TcpExt section of /proc/net/netstat,
+    // which is a header line of names followed by a line of values
+    #[cfg(target_os = "linux")]
+    pub fn listen_overflows() -> Option<(u64, u64)> {
+        let netstat = std::fs::read_to_string("/proc/net/netstat").ok()?;
+        let mut tcp_ext = netstat.lines().filter(|l| l.starts_with("TcpExt:"));
+        let (names, values) = (tcp_ext.next()?, tcp_ext.next()?);
+        
+        let field = |wanted: &str| {
+            names
+                .split_whitespace()
+                .zip(values.split_whitespace())
+                .find(|(name, _)| *name == wanted)
+                .and_then(|(_, value)| value.parse().ok())
+        };
+        Some((field("ListenOverflows")?, field("ListenDrops")?))
+    }
+    
+    // For a listening socket, TCP_INFO reports the current accept queue
+    // length in tcpi_unacked and the backlog in tcpi_sacked
+    #[cfg(target_os = "linux")]
+    pub fn listen_queue(listener: &TcpListener) -> Option<(u32, u32)> {
+        const IPPROTO_TCP: c_int = 6;
+        const TCP_INFO: c_int = 11;
+        const UNACKED_OFFSET: usize = 24;  // After 8 u8 fields and 4 u32s
+        
+        let mut info = [0u8; 104];
+        let mut len = info.len() as u32;
+        let ret = unsafe {
+            getsockopt(listener.as_raw_fd(), IPPROTO_TCP, TCP_INFO, info.as_mut_ptr() as *mut c_void, &mut len)
+        };
+        if ret != 0 || (len as usize) < UNACKED_OFFSET + 8 {
+            return None;
+        }
+        let field = |at: usize| u32::from_ne_bytes([info[at], info[at + 1], info[at + 2], info[at + 3]]);
+        Some((field(UNACKED_OFFSET), field(UNACKED_OFFSET + 4)))
+    }
+    
+    #[cfg(not(target_os = "linux"))]
+    pub fn listen_overflows() -> Option<(u64, u64)> {
+        None
+    }
+    
+    #[cfg(not(target_os = "linux"))]
+    pub fn listen_queue(_: &TcpListener) -> Option<(u32, u32)> {
+        None
+    }
+    
+    // 1, 5 and 15 minute load averages
+    #[cfg(target_os = "linux")]
+    pub fn load_average() -> Option<[f64; 3]> {
+        let loadavg = std::fs::read_to_string("/proc/loadavg").ok()?;
+        let mut fields = loadavg.split_whitespace().map(|field| field.parse().ok());
+        Some([fields.next()??, fields.next()??, fields.next()??])
+    }
+    
+    #[cfg(not(target_os = "linux"))]
+    pub fn load_average() -> Option<[f64; 3]> {
+        extern "C" {
+            fn getloadavg(loadavg: *mut f64, nelem: c_int) -> c_int;
+        }
+        let mut loads = [0.0; 3];
+        if unsafe { getloadavg(loads.as_mut_ptr(), 3) } == 3 { Some(loads) } else { None }
+    }
+    
+    // Resident set size, from VmRSS in /proc/self/status
+    #[cfg(target_os = "linux")]
+    pub fn rss_bytes() -> Option<u64> {
+        let status = std::fs::read_to_string("/proc/self/status").ok()?;
+        let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+        let kib: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+        Some(kib * 1024)
+    }
+    
+    #[cfg(not(target_os = "linux"))]
+    pub fn rss_bytes() -> Option<u64> {
+        None
+    }
+    
+    // Entries in the per-process descriptor directory, less the one that
+    // reading it opens
+    pub fn open_fds() -> Option<usize> {
+        let dir = if cfg!(target_os = "linux") { "/proc/self/fd" } else { "/dev/fd" };
+        Some(std::fs::read_dir(dir).ok()?.count().saturating_sub(1))
+    }
+    
+    fn shutdown_signals() -> SigSet {
+        let mut set = SigSet([0; 16]);
+        unsafe {
+            sigemptyset(&mut set);
+            sigaddset(&mut set, SIGINT);
+            sigaddset(&mut set, SIGTERM);
+        }
+        set
+    }
+    
+    // Blocked signals stay pending for wait_shutdown_signal instead of
+    // killing the process. New threads inherit the calling thread's mask.
+    pub fn block_shutdown_signals() {
+        unsafe { pthread_sigmask(SIG_BLOCK, &shutdown_signals(), std::ptr::null_mut()) };
+    }
+    
+    pub fn wait_shutdown_signal() -> i32 {
+        let set = shutdown_signals();
+        let mut signal = 0;
+        while unsafe { sigwait(&set, &mut signal) } != 0 {}
+        signal
+    }
+    
+    // On Linux this takes the socket out of the listening state, so new
+    // connections are refused; elsewhere they wait until the process exits
+    // Identifies the socket for shutdown_read; never 0
+    pub fn socket_handle(stream: &TcpStream) -> u64 {
+        stream.as_raw_fd() as u64 + 1
+    }
+    
+    // Reads already queued still succeed, then the socket reads as closed
+    pub fn shutdown_read(handle: u64) {
+        unsafe { shutdown(handle as c_int - 1, SHUT_RD) };
+    }
+    
+    pub fn stop_listening(listener: &TcpListener) {
+        unsafe { shutdown(listener.as_raw_fd(), SHUT_RD) };
+    }
+    
+    pub use self::prefork::*;
+    
+    // Process and socket plumbing for --prefork. Config rejects the flag
+    // elsewhere, so other platforms only get stubs.
+    #[cfg(target_os = "linux")]
+    mod prefork {
+        use super::{set_option, SOL_SOCKET};
+        use std::io;
+        use std::net::TcpListener;
+        use std::os::raw::{c_int, c_void};
+        use std::os::unix::io::FromRawFd;
+        
+        const AF_INET: c_int = 2;
+        const SOCK_STREAM: c_int = 1;
+        const SOCK_CLOEXEC: c_int = 0o2000000;
+        const SO_REUSEADDR: c_int = 2;
+        const SO_REUSEPORT: c_int = 15;
+        const LISTEN_BACKLOG: c_int = 128;  // Same as std's TcpListener::bind
+        const PR_SET_PDEATHSIG: c_int = 1;
+        const SIGTERM: c_int = 15;
+        
+        #[repr(C)]
+        struct SockaddrIn {
+            sin_family: u16,
+            sin_port: u16,  // Network byte order
+            sin_addr: u32,  // Network byte order
+            sin_zero: [u8; 8],
+        }
+        
+        extern "C" {
+            fn socket(domain: c_int, kind: c_int, protocol: c_int) -> c_int;
+            fn bind(fd: c_int, addr: *const c_void, len: u32) -> c_int;
+            fn listen(fd: c_int, backlog: c_int) -> c_int;
+            fn close(fd: c_int) -> c_int;
+            fn fork() -> c_int;
+            fn waitpid(pid: c_int, status: *mut c_int, options: c_int) -> c_int;
+            fn prctl(option: c_int, arg2: u64, arg3: u64, arg4: u64, arg5: u64) -> c_int;
+        }
+        
+        fn check(ret: c_int) -> io::Result<c_int> {
+            if ret < 0 { Err(io::Error::last_os_error()) } else { Ok(ret) }
+        }
+        
+        // Like TcpListener::bind on 0.0.0.0, but with SO_REUSEPORT set before
+        // binding so every prefork child can have its own listener on the port
+        pub fn bind_reuseport(port: u16) -> io::Result<TcpListener> {
+            let fd = check(unsafe { socket(AF_INET, SOCK_STREAM | SOCK_CLOEXEC, 0) })?;
+            let addr = SockaddrIn {
+                sin_family: AF_INET as u16,
+                sin_port: port.to_be(),
+                sin_addr: 0,
+                sin_zero: [0; 8],
+            };
+            let setup = set_option(fd, SOL_SOCKET, SO_REUSEADDR, &(1 as c_int))
+                .and_then(|_| set_option(fd, SOL_SOCKET, SO_REUSEPORT, &(1 as c_int)))
+                .and_then(|_| {
+                    let len = std::mem::size_of::<SockaddrIn>() as u32;
+                    check(unsafe { bind(fd, &addr as *const SockaddrIn as *const c_void, len) })
+                })
+                .and_then(|_| check(unsafe { listen(fd, LISTEN_BACKLOG) }));
+            
+            match setup {
+                Ok(_) => Ok(unsafe { TcpListener::from_raw_fd(fd) }),
+                Err(e) => {
+                    unsafe { close(fd) };
+                    Err(e)
+                }
+            }
+        }
+        
+        // Ok(None) in the child, Ok(Some(pid)) in the parent
+        pub fn fork_process() -> io::Result<Option<i32>> {
+            match check(unsafe { fork() })? {
+                0 => Ok(None),
+                pid => Ok(Some(pid)),
+            }
+        }
+        
+        // Blocks until any child exits; returns its pid and how it went
+        pub fn wait_child() -> io::Result<(i32, String)> {
+            let mut status: c_int = 0;
+            let pid = check(unsafe { waitpid(-1, &mut status, 0) })?;
+            let how = match status & 0x7f {
+                0 => format!("exited with status {}", (status >> 8) & 0xff),
+                signal => format!("was killed by signal {}", signal),
+            };
+            Ok((pid, how))
+        }
+        
+        // Children get SIGTERM when the supervisor dies, so stopping the
+        // parent stops the whole group
+        pub fn exit_with_parent() {
+            unsafe { prctl(PR_SET_PDEATHSIG, SIGTERM as u64, 0, 0, 0) };
+        }
+    }
+    
+    #[cfg(not(target_os = "linux"))]
+    mod prefork {
+        use std::io;
+        use std::net::TcpListener;
+        
+        fn unsupported() -> io::Error {
+            io::Error::new(io::ErrorKind::Other, "--prefork is only supported on Linux")
+        }
+        
+        pub fn bind_reuseport(_: u16) -> io::Result<TcpListener> {
+            Err(unsupported())
+        }
+        
+        pub fn fork_process() -> io::Result<Option<i32>> {
+            Err(unsupported())
+        }
+        
+        pub fn wait_child() -> io::Result<(i32, String)> {
+            Err(unsupported())
+        }
+        
+        pub fn exit_with_parent() {}
+    }
+}
+
+#[cfg(not(unix))]
+mod sys {
+    use super::Linger;
+    use std::io;
+    use std::net::{TcpListener, TcpStream};
+    
+    pub fn set_linger(_: &TcpStream, _: Linger) -> io::Result<()> {
+        Err(io::Error::new(io::ErrorKind::Other, "SO_LINGER is only supported on Unix"))
+    }
+    
+    pub fn set_notsent_lowat(_: &TcpStream, _: u32) -> io::Result<()> {
+        Ok(())
+    }
+    
+    pub fn out_of_resources(_: &io::Error) -> bool {
+        false
+    }
+    
+    pub fn listen_overflows() -> Option<(u64, u64)> {
+        None
+    }
+    
+    pub fn listen_queue(_: &TcpListener) -> Option<(u32, u32)> {
+        None
+    }
+    
+    pub fn load_average() -> Option<[f64; 3]> {
+        None
+    }
+    
+    pub fn rss_bytes() -> Option<u64> {
+        None
+    }
+    
+    pub fn open_fds() -> Option<usize> {
+        None
+    }
+    
+    // No signals to wait for, so the server runs until it's killed
+    pub fn block_shutdown_signals() {}
+    
+    pub fn wait_shutdown_signal() -> i32 {
+        loop {
+            std::thread::park();
+        }
+    }
+    
+    pub fn socket_handle(_: &TcpStream) -> u64 {
+        0
+    }
+    
+    pub fn shutdown_read(_: u64) {}
+    
+    pub fn stop_listening(_: &TcpListener) {}
+    
+    fn unsupported() -> io::Error {
+        io::Error::new(io::ErrorKind::Other, "--prefork is only supported on Linux")
+    }
+    
+    pub fn bind_reuseport(_: u16) -> io::Result<TcpListener> {
+        Err(unsupported())
+    }
+    
+    pub fn fork_process() -> io::Result<Option<i32>> {
+        Err(unsupported())
+    }
+    
+    pub fn wait_child() -> io::Result<(i32, String)> {
+        Err(unsupported())
+    }
+    
+    pub fn exit_with_parent() {}
 }
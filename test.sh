@@ -13,6 +13,7 @@ GO_BINARY=""
 RUST_BINARY=""
 CPP_BINARY=""
 declare -a PIDS=()
+declare -a FAILURES=()
 
 get_port() {
     case $1 in js) echo 3000;; py) echo 3001;; go) echo 3002;; rust) echo 3003;; cpp) echo 3004;; esac
@@ -144,6 +145,32 @@ test_server() {
         -d '{"hello":"world"}' "http://localhost:$port/something"
 }
 
+# /longpoll is shared by the JS and Rust servers, so both have to read
+# ?timeout the same way: an integer is clamped to 0-30, anything else means 5
+test_longpoll() {
+    local name=$1 port=$2 timeout expected got
+    echo -e "\n📍 $name /longpoll timeouts"
+    for timeout in 0 -3; do
+        got=$(curl -s --max-time 2 "http://localhost:$port/longpoll?timeout=$timeout")
+        check_timeout "timeout=$timeout" '"timeout":0' "$got"
+    done
+    # The default takes 5s, so it's enough to see the request still parked after 1s
+    for timeout in abc 1.5; do
+        got=$(curl -s --max-time 1 "http://localhost:$port/longpoll?timeout=$timeout")
+        check_timeout "timeout=$timeout" "" "$got"
+    done
+}
+
+check_timeout() {
+    local label=$1 expected=$2 got=$3
+    if [[ -z "$expected" && -z "$got" ]] || [[ -n "$expected" && "$got" == *"$expected"* ]]; then
+        echo "   ✓ $label"
+    else
+        echo "   ✗ $label (got: ${got:-no response})"
+        FAILURES+=("$label")
+    fi
+}
+
 main() {
     cd "$(dirname "$0")"
     
@@ -193,10 +220,13 @@ main() {
     
     for lang in "${started[@]}"; do
         test_server "$(get_name "$lang")" "$(get_port "$lang")"
+        [[ "$lang" == js || "$lang" == rust ]] && test_longpoll "$(get_name "$lang")" "$(get_port "$lang")"
     done
     
     echo -e "\n━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━"
-    if [[ ${#errors[@]} -eq 0 ]]; then
+    if [[ ${#FAILURES[@]} -gt 0 ]]; then
+        echo "❌ ${#FAILURES[@]} check(s) failed"
+    elif [[ ${#errors[@]} -eq 0 ]]; then
         echo "✅ All tests passed!"
     else
         echo "⚠️  Tests done (unavailable: ${errors[*]})"
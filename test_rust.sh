@@ -0,0 +1,1746 @@
+#!/bin/bash
+# ═══════════════════════════════════════════════════════════════════════════════
+# test_rust.sh - Feature Tests for the Rust Server (flags and edge cases)
+# ═══════════════════════════════════════════════════════════════════════════════
+
+set -o pipefail
+
+SCRIPT_DIR="$(cd "$(dirname "${BASH_SOURCE[0]}")" && pwd)"
+SERVER_DIR="$SCRIPT_DIR/servers"
+
+PORT=3003
+RUST_BINARY="/tmp/rust_features_$$"
+ALLOCS_BINARY="/tmp/rust_features_allocs_$$"  # Built with --cfg count_allocs
+SERVER_LOG="/tmp/rust_features_$$.log"
+STATIC_DIR="/tmp/rust_features_static_$$"
+CONFIG_FILE="/tmp/rust_features_$$.toml"
+DUMP_FILE="/tmp/rust_features_$$.dump"
+TEE_DIR="/tmp/rust_features_tee_$$"
+SERVER_PID=""
+PASSED=0
+FAILED=0
+
+cleanup() {
+    stop_server
+    rm -f "$RUST_BINARY" "$ALLOCS_BINARY" "$SERVER_LOG" "$CONFIG_FILE" "$DUMP_FILE"
+    rm -rf "$STATIC_DIR" "$TEE_DIR"
+}
+trap cleanup EXIT INT TERM
+
+# ─────────────────────────────────────────────────────────────────────────────
+# Server Control
+# ─────────────────────────────────────────────────────────────────────────────
+
+# Args: $1=output (default: $RUST_BINARY) $2..=extra rustc flags
+build_server() {
+    local output=${1:-$RUST_BINARY}
+    shift
+    local rustc_bin=""
+    if [[ -d "$HOME/.rustup/toolchains" ]]; then
+        rustc_bin=$(find "$HOME/.rustup/toolchains" -name "rustc" -type f 2>/dev/null | head -1)
+    fi
+    [[ -z "$rustc_bin" ]] && rustc_bin=$(which rustc 2>/dev/null)
+    [[ -z "$rustc_bin" ]] && { echo "❌ rustc not found"; exit 1; }
+
+    "$rustc_bin" -O "$@" "$SERVER_DIR/server.rs" -o "$output" || { echo "❌ Build failed"; exit 1; }
+}
+
+# Start the server with the given flags and wait until it answers
+start_server() {
+    stop_server
+    "$RUST_BINARY" "$@" >"$SERVER_LOG" 2>&1 &
+    SERVER_PID=$!
+
+    local tries=0
+    while [[ $tries -lt 30 ]]; do
+        curl -s -o /dev/null --max-time 1 "http://localhost:$PORT/healthz" 2>/dev/null && return 0
+        sleep 0.1
+        ((tries++))
+    done
+    echo "❌ Server failed to start with: $*"
+    cat "$SERVER_LOG"
+    exit 1
+}
+
+stop_server() {
+    if [[ -n "$SERVER_PID" ]]; then
+        kill "$SERVER_PID" 2>/dev/null
+        wait "$SERVER_PID" 2>/dev/null
+        SERVER_PID=""
+    fi
+}
+
+# ─────────────────────────────────────────────────────────────────────────────
+# Helpers
+# ─────────────────────────────────────────────────────────────────────────────
+
+# Assert that $3 contains $2
+# Args: $1=description $2=expected substring $3=actual output
+check() {
+    local desc=$1 expected=$2 actual=$3
+    if [[ "$actual" == *"$expected"* ]]; then
+        echo "   ✓ $desc"
+        ((PASSED++))
+    else
+        echo "   ✗ $desc"
+        echo "     expected: $expected"
+        echo "     got:      ${actual:0:200}"
+        ((FAILED++))
+    fi
+}
+
+# Assert that $3 does not contain $2
+# Args: $1=description $2=unexpected substring $3=actual output
+check_absent() {
+    local desc=$1 unexpected=$2 actual=$3
+    if [[ "$actual" != *"$unexpected"* ]]; then
+        echo "   ✓ $desc"
+        ((PASSED++))
+    else
+        echo "   ✗ $desc"
+        echo "     unexpected: $unexpected"
+        echo "     got:        ${actual:0:200}"
+        ((FAILED++))
+    fi
+}
+
+# Send raw bytes (printf escapes allowed) and print whatever comes back
+# Args: $1=payload
+raw_request() {
+    local payload=$1
+    exec 3<>"/dev/tcp/127.0.0.1/$PORT" || return 1
+    printf '%b' "$payload" >&3
+    timeout 2 cat <&3
+    exec 3<&-
+}
+
+# Send the pieces with a pause before each one after the first
+# Args: $1=delay(s) $2..=payload pieces
+slow_request() {
+    local delay=$1; shift
+    exec 3<>"/dev/tcp/127.0.0.1/$PORT" || return 1
+    printf '%b' "$1" >&3; shift
+    for piece in "$@"; do
+        sleep "$delay"
+        printf '%b' "$piece" >&3 2>/dev/null
+    done
+    timeout 5 cat <&3
+    exec 3<&-
+}
+
+# Print just the status code of a curl request
+status_of() {
+    curl -s -o /dev/null -w '%{http_code}' --max-time 5 "$@"
+}
+
+# ─────────────────────────────────────────────────────────────────────────────
+# Tests
+# ─────────────────────────────────────────────────────────────────────────────
+
+test_config_file() {
+    echo -e "\n📍 --config"
+    start_server --config "$SERVER_DIR/server.example.toml"
+    check "example config loads" "(8 workers)" "$(cat "$SERVER_LOG")"
+
+    cat > "$CONFIG_FILE" <<'EOF'
+# Comments and blank lines are skipped
+workers = 3
+metrics = false     # trailing comment
+max_uri = 16
+EOF
+    start_server --config "$CONFIG_FILE"
+    check "values applied" "(3 workers)" "$(cat "$SERVER_LOG")"
+    check "toggle applied" "404" "$(status_of "http://localhost:$PORT/metrics")"
+    check "limit applied" "414" "$(status_of "http://localhost:$PORT/something?q=0123456789")"
+
+    start_server --max-uri 4096 --config "$CONFIG_FILE" --workers-per-core 1
+    check "command line overrides the file" "200" "$(status_of "http://localhost:$PORT/something?q=0123456789")"
+    # Would fail to start as mutually exclusive if the file's workers were kept
+    check_absent "either pool flag replaces the file's" "(3 workers)" "$(cat "$SERVER_LOG")"
+
+    echo "drop_percent = 100.0" > "$CONFIG_FILE"
+    start_server --config "$CONFIG_FILE"
+    check "floats accepted" "000" "$(status_of "http://localhost:$PORT/")"
+
+    echo "max_bodyy = 10" > "$CONFIG_FILE"
+    check "unknown key reported with its line" "$CONFIG_FILE:1: unknown key \`max_bodyy\`" \
+        "$("$RUST_BINARY" --config "$CONFIG_FILE" 2>&1)"
+    echo "access_log = 1" > "$CONFIG_FILE"
+    check "wrong type rejected" "takes true or false" "$("$RUST_BINARY" --config "$CONFIG_FILE" 2>&1)"
+}
+
+test_dump_requests() {
+    echo -e "\n📍 --dump-requests"
+    rm -f "$DUMP_FILE"
+    start_server --dump-requests "$DUMP_FILE"
+    # Drop the startup probe, whose record is only written after its response
+    local i
+    for i in {1..20}; do [[ -s "$DUMP_FILE" ]] && break; sleep 0.05; done
+    : > "$DUMP_FILE"
+
+    # A body with NUL, CR/LF and high bytes must come back byte for byte
+    local body='a\0b\r\n\n\xff\xfez'
+    local head="POST /upload-rate HTTP/1.1\r\nHost: localhost\r\nContent-Length: 10\r\n\r\n"
+    raw_request "$head$body" >/dev/null
+    sleep 0.1
+
+    local header length
+    header=$(head -n1 "$DUMP_FILE")
+    length=${header#* }
+    check "record header is '<ms> <length>'" "yes" \
+        "$([[ "$header" =~ ^[0-9]+\ [0-9]+$ ]] && echo yes || echo "$header")"
+    check "length covers head and body" "$(printf '%b' "$head$body" | wc -c)" "$length"
+    check "raw bytes preserved" "same" \
+        "$(cmp -s <(tail -c +$(( ${#header} + 2 )) "$DUMP_FILE" | head -c "$length") \
+            <(printf '%b' "$head$body") && echo same || echo different)"
+
+    curl -s -o /dev/null "http://localhost:$PORT/something?q=1"
+    sleep 0.1
+    check "records are appended" "GET /something?q=1 HTTP/1.1" "$(tr -d '\0' < "$DUMP_FILE")"
+}
+
+test_tee_bodies() {
+    echo -e "\n📍 --tee-bodies"
+    rm -rf "$TEE_DIR"
+    start_server --tee-bodies "$TEE_DIR" --tee-percent 100 --tee-max-bytes 16
+    rm -f "$TEE_DIR"/*.request "$TEE_DIR"/*.response
+    : > "$TEE_DIR/index"  # Drop the startup probe
+
+    curl -s -o /dev/null -d '{"payload":"0123456789abcdef"}' "http://localhost:$PORT/something"
+    sleep 0.1
+    local index=$(cat "$TEE_DIR/index")
+    local id=${index%% *}
+    check "sample indexed with full sizes" "POST /something 200 30 60" "$index"
+    check "request body cut at the cap" '{"payload":"0123' "$(cat "$TEE_DIR/$id.request" 2>/dev/null)"
+    check "response body cut at the cap" '{"route":"/somet' "$(cat "$TEE_DIR/$id.response" 2>/dev/null)"
+    check "cap is exact" "16" "$(wc -c < "$TEE_DIR/$id.request" 2>/dev/null)"
+
+    start_server --tee-bodies "$TEE_DIR" --tee-percent 0
+    : > "$TEE_DIR/index"
+    curl -s -o /dev/null -d 'x=1' "http://localhost:$PORT/something"
+    sleep 0.1
+    check "nothing sampled at 0%" "0" "$(wc -l < "$TEE_DIR/index")"
+}
+
+test_h2_preface() {
+    echo -e "\n📍 h2c prior knowledge"
+    start_server
+
+    # The exact connection preface, then the client's empty SETTINGS frame
+    local frames
+    frames=$(raw_request 'PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n\x00\x00\x00\x04\x00\x00\x00\x00\x00' \
+        | od -An -tx1 | tr -s ' \n' ' ')
+    check "answers with an empty SETTINGS frame" " 00 00 00 04 00 00 00 00 00 " "$frames"
+    check "then GOAWAY with HTTP_1_1_REQUIRED" "00 00 08 07 00 00 00 00 00 00 00 00 00 00 00 00 0d" "$frames"
+    check_absent "no HTTP/1.1 response" "48 54 54 50" "$frames"  # "HTTP"
+
+    check "HTTP/1.1 still served afterwards" "200" "$(status_of "http://localhost:$PORT/")"
+}
+
+test_http_version() {
+    echo -e "\n📍 HTTP version"
+    start_server
+
+    check "1.0 answered as 1.0" "HTTP/1.0 200 OK" "$(raw_request "GET / HTTP/1.0\r\n\r\n" | head -n 1)"
+    check "1.1 answered as 1.1" "HTTP/1.1 200 OK" "$(raw_request "GET / HTTP/1.1\r\nConnection: close\r\n\r\n" | head -n 1)"
+    check "errors echo it too" "HTTP/1.0 404 Not Found" "$(raw_request "GET /nope HTTP/1.0\r\n\r\n" | head -n 1)"
+    check "newer 1.x gets 1.1" "HTTP/1.1 200 OK" "$(raw_request "GET / HTTP/1.2\r\nConnection: close\r\n\r\n" | head -n 1)"
+
+    # 1xx is new in 1.1, so a 1.0 client's Expect is ignored
+    local response=$(raw_request "POST /something HTTP/1.0\r\nExpect: 100-continue\r\nContent-Length: 2\r\n\r\nhi")
+    check_absent "no 100 Continue for 1.0" "100 Continue" "$response"
+    check "1.0 upload still answered" "HTTP/1.0 200 OK" "$response"
+    check "1.1 still gets 100 Continue" "HTTP/1.1 100 Continue" \
+        "$(raw_request "POST /something HTTP/1.1\r\nExpect: 100-continue\r\nContent-Length: 2\r\nConnection: close\r\n\r\nhi")"
+}
+
+test_server_timing() {
+    echo -e "\n📍 --server-timing"
+    start_server
+    check_absent "off by default" "Server-Timing" "$(curl -s -D - -o /dev/null "http://localhost:$PORT/")"
+
+    start_server --server-timing
+    local header
+    header=$(curl -s -D - -o /dev/null "http://localhost:$PORT/compute?primes=100000" | grep -i '^Server-Timing')
+    check "phases in spec syntax" "yes" \
+        "$([[ "$header" =~ ^Server-Timing:\ parse\;dur=[0-9.]+,\ route\;dur=[0-9.]+,\ serialize\;dur=[0-9.]+ ]] \
+            && echo yes || echo "$header")"
+    local route=$(sed 's/.*route;dur=\([0-9.]*\).*/\1/' <<< "$header")
+    check "route phase covers the handler" "yes" \
+        "$(awk -v r="$route" 'BEGIN { print (r > 1 ? "yes" : "route=" r "ms") }')"
+
+    # The client sits on the connection for a second before sending the head
+    header=$(slow_request 1 "" "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n" | grep -i '^Server-Timing')
+    local parse=$(sed 's/.*parse;dur=\([0-9.]*\).*/\1/' <<< "$header")
+    check "parse phase leaves out the wait for the head" "yes" \
+        "$(awk -v p="$parse" 'BEGIN { print (p != "" && p < 500 ? "yes" : "parse=" p "ms") }')"
+}
+
+# How many seconds the Date header of a fresh request is behind the clock
+date_lag() {
+    local date=$(curl -s -D - -o /dev/null "http://localhost:$PORT/" | tr -d '\r' | sed -n 's/^Date: //p')
+    echo $(( $(date +%s) - $(date -d "$date" +%s) ))
+}
+
+test_date_header() {
+    echo -e "\n📍 Date header"
+    start_server
+    check "IMF-fixdate" "yes" "$(curl -s -D - -o /dev/null "http://localhost:$PORT/" |
+        grep -qE '^Date: (Mon|Tue|Wed|Thu|Fri|Sat|Sun), [0-9]{2} [A-Z][a-z]{2} [0-9]{4} [0-9]{2}:[0-9]{2}:[0-9]{2} GMT' && echo yes)"
+    local lag=$(date_lag)
+    check "within a second of the clock" "yes" "$( (( lag >= 0 && lag <= 1 )) && echo yes || echo "${lag}s behind")"
+
+    # The second counter of `date +%s` may tick between the server's read and ours
+    start_server --date-refresh-ms 3000
+    local i worst=0
+    for i in 1 2 3 4; do
+        lag=$(date_lag)
+        (( lag > worst )) && worst=$lag
+        sleep 1
+    done
+    check "never more than the interval behind" "yes" "$( (( worst <= 4 )) && echo yes || echo "${worst}s behind")"
+    check "zero interval rejected" "at least 1" "$("$RUST_BINARY" --date-refresh-ms 0 2>&1)"
+}
+
+test_security_headers() {
+    echo -e "\n📍 --security-headers"
+    start_server
+    check_absent "off by default" "X-Content-Type-Options" "$(curl -s -D - -o /dev/null "http://localhost:$PORT/")"
+
+    start_server --security-headers
+    local headers=$(curl -s -D - -o /dev/null "http://localhost:$PORT/")
+    check "nosniff" "X-Content-Type-Options: nosniff" "$headers"
+    check "no framing" "X-Frame-Options: DENY" "$headers"
+    check "no referrer" "Referrer-Policy: no-referrer" "$headers"
+    check_absent "no HSTS without TLS" "Strict-Transport-Security" "$headers"
+    check "error responses too" "X-Content-Type-Options: nosniff" \
+        "$(curl -s -D - -o /dev/null "http://localhost:$PORT/missing")"
+}
+
+test_received_header() {
+    echo -e "\n📍 --received-header"
+    start_server
+    check_absent "off by default" "X-Request-Received" "$(curl -s -D - -o /dev/null "http://localhost:$PORT/")"
+
+    # The head is finished half a second late; the stamp is still from the first bytes
+    start_server --received-header
+    local sent=$(date +%s%N)
+    local received=$(slow_request 0.5 "GET / HTTP/1.1\r\n" "Host: localhost\r\n\r\n" |
+        tr -d '\r' | awk '/^X-Request-Received:/ { print $2 }')
+    check "stamped when the first bytes arrived" "yes" \
+        "$( (( received >= sent && received - sent < 300000000 )) && echo yes || echo "sent=$sent received=$received")"
+}
+
+test_drop_percent() {
+    echo -e "\n📍 --drop-percent"
+    start_server --drop-percent 100
+    check "request read, connection closed unanswered" "000" \
+        "$(status_of -X POST -d '{"a":1}' "http://localhost:$PORT/something")"
+    status_of "http://localhost:$PORT/" > /dev/null
+    check "health check never dropped" "200" "$(status_of "http://localhost:$PORT/healthz")"
+    check "drops counted" "dropped_total 2" "$(curl -s "http://localhost:$PORT/metrics")"
+
+    # Over a few hundred requests, half dropped lands well inside 30-70%
+    start_server --drop-percent 50
+    local i
+    for i in {1..200}; do status_of "http://localhost:$PORT/"; echo; done > /dev/null
+    local dropped=$(curl -s "http://localhost:$PORT/metrics" | awk '/^dropped_total/ { print $2 }')
+    check "roughly the requested share dropped" "yes" \
+        "$( (( dropped > 60 && dropped < 140 )) && echo yes || echo "dropped=$dropped")"
+
+    check "over 100 rejected" "between 0 and 100" "$("$RUST_BINARY" --drop-percent 101 2>&1)"
+}
+
+test_max_body() {
+    echo -e "\n📍 --max-body"
+    start_server --max-body 1024
+
+    check "small body accepted" "200" \
+        "$(status_of -X POST -d '{"a":1}' "http://localhost:$PORT/something")"
+
+    # Headers only: the declared body is never sent, so a 413 proves
+    # the server answered without waiting to read it
+    local response
+    response=$(raw_request "POST /something HTTP/1.1\r\nHost: localhost\r\nContent-Length: 10000000000\r\n\r\n")
+    check "oversized Content-Length rejected" "HTTP/1.1 413" "$response"
+    check "rejection closes the connection" "Connection: close" "$response"
+}
+
+# Print the status line a POST with the given Content-Length header(s) gets
+content_length_status() {
+    raw_request "POST /something HTTP/1.1\r\nHost: localhost\r\n$1\r\n{}" | head -n1 | tr -d '\r'
+}
+
+test_on_bad_request() {
+    echo -e "\n📍 --on-bad-request"
+    local code
+    # A bare CR inside a header is malformed whatever the header says
+    local bad=(-s -o /dev/null -w '%{http_code}' --max-time 2 -H $'X-Bad: a\rb' "http://localhost:$PORT/")
+    start_server
+    check "400 by default" "400" "$(curl "${bad[@]}")"
+
+    start_server --on-bad-request reset --connection-log
+    curl "${bad[@]}" > /dev/null; code=$?
+    check "reset: connection reset by peer" "56" "$code"
+    check "reset: nothing logged as sent" "out=0 " "$(cat "$SERVER_LOG")"
+
+    start_server --on-bad-request close
+    curl "${bad[@]}" > /dev/null; code=$?
+    check "close: empty reply" "52" "$code"
+
+    check "limits still answered" "431" \
+        "$(status_of -H "X-Big: $(head -c 9000 /dev/zero | tr '\0' a)" "http://localhost:$PORT/")"
+    check "good requests unaffected" "200" "$(status_of "http://localhost:$PORT/")"
+    check "unknown mode rejected" "Invalid or missing value for --on-bad-request" "$("$RUST_BINARY" --on-bad-request drop 2>&1)"
+}
+
+test_content_length_parsing() {
+    echo -e "\n📍 Content-Length parsing"
+    start_server
+
+    check "plain digits accepted" "HTTP/1.1 200" "$(content_length_status "Content-Length: 2\r\n")"
+    check "leading zeros accepted" "HTTP/1.1 200" "$(content_length_status "Content-Length: 002\r\n")"
+    check "overflow rejected" "HTTP/1.1 400" \
+        "$(content_length_status "Content-Length: 99999999999999999999\r\n")"
+    check "absurd leading zeros rejected" "HTTP/1.1 400" \
+        "$(content_length_status "Content-Length: 0000000000000000000002\r\n")"
+    check "non-digit rejected" "HTTP/1.1 400" "$(content_length_status "Content-Length: 2a\r\n")"
+    check "sign rejected" "HTTP/1.1 400" "$(content_length_status "Content-Length: +2\r\n")"
+    check "negative rejected" "HTTP/1.1 400" "$(content_length_status "Content-Length: -1\r\n")"
+    check "empty value rejected" "HTTP/1.1 400" "$(content_length_status "Content-Length:\r\n")"
+    check "list rejected" "HTTP/1.1 400" "$(content_length_status "Content-Length: 2, 2\r\n")"
+    check "matching duplicates accepted" "HTTP/1.1 200" \
+        "$(content_length_status "Content-Length: 2\r\nContent-Length: 2\r\n")"
+    check "conflicting duplicates rejected" "HTTP/1.1 400" \
+        "$(content_length_status "Content-Length: 2\r\nContent-Length: 3\r\n")"
+}
+
+test_no_content_length() {
+    echo -e "\n📍 --no-content-length"
+    start_server --no-content-length
+
+    local start=$(date +%s%N) response
+    response=$(raw_request "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+    local ms=$(( ($(date +%s%N) - start) / 1000000 ))
+
+    check_absent "no Content-Length header" "Content-Length" "$response"
+    check "Connection: close announced" "Connection: close" "$response"
+    check "body delivered in full" "Hello from Rust!" "$response"
+    # raw_request reads until EOF or a 2s timeout, so a quick return means the server closed
+    check "server closes the connection" "closed" "$([[ $ms -lt 1500 ]] && echo closed || echo "open after ${ms}ms")"
+    check "curl reads a close-delimited body" "Hello from Rust!" "$(curl -s "http://localhost:$PORT/")"
+}
+
+test_max_uri() {
+    echo -e "\n📍 --max-uri"
+    start_server
+
+    # The default limit is 2048 decoded bytes of path+query
+    local at_limit="/something?q=$(printf 'a%.0s' {1..2035})"
+    check "URI at the limit accepted" "200" "$(status_of "http://localhost:$PORT$at_limit")"
+    check "one byte over gets 414" "414" "$(status_of "http://localhost:$PORT${at_limit}a")"
+
+    # 3 raw bytes per escape but 1 decoded, so this is well under the limit
+    local encoded="/something?q=$(printf '%%41%.0s' {1..1000})"
+    check "percent-encoded URI measured decoded" "200" "$(status_of "http://localhost:$PORT$encoded")"
+
+    start_server --max-uri 16
+    check "configured limit applies" "414" "$(status_of "http://localhost:$PORT/something?q=1234")"
+    check "414 is counted" 'responses_total{status="414"} 1' "$(curl -s "http://localhost:$PORT/metrics")"
+}
+
+test_max_header_line() {
+    echo -e "\n📍 --max-header-line"
+    start_server
+
+    # Comfortably more than fits in one read, but under the default limit
+    local long_value=$(printf 'a%.0s' {1..8000})
+    check "long header under the limit accepted" "200" "$(status_of -H "X-Long: $long_value" "http://localhost:$PORT/")"
+
+    # The line never ends, so only a rejection mid-line gets an answer
+    local huge_value=$(printf 'a%.0s' {1..20000})
+    check "header over the limit gets 431 mid-line" "HTTP/1.1 431" \
+        "$(raw_request "GET / HTTP/1.1\r\nHost: localhost\r\nX-Huge: $huge_value")"
+
+    start_server --max-header-line 64
+    check "configured limit applies" "431" "$(status_of -H "X-Long: $(printf 'a%.0s' {1..64})" "http://localhost:$PORT/")"
+    check "long request line gets 414" "414" "$(status_of "http://localhost:$PORT/something?q=$(printf 'a%.0s' {1..64})")"
+    check "431 is counted" 'responses_total{status="431"} 1' "$(curl -s "http://localhost:$PORT/metrics")"
+}
+
+# Milliseconds the server took to close a connection after the payload was sent
+time_to_close() {
+    local start=$(date +%s%N)
+    raw_request "$1" > /dev/null
+    echo $(( ($(date +%s%N) - start) / 1000000 ))
+}
+
+# SIGTERM the server and wait for it to exit. Sets EXIT_MS and EXIT_STATUS;
+# like start_server, it can't run in a subshell.
+terminate_server() {
+    local start=$(date +%s%N)
+    kill -TERM "$SERVER_PID"
+    wait "$SERVER_PID"
+    EXIT_STATUS=$?
+    EXIT_MS=$(( ($(date +%s%N) - start) / 1000000 ))
+    SERVER_PID=""
+}
+
+test_shutdown() {
+    echo -e "\n📍 Graceful shutdown"
+    local out="/tmp/rust_features_shutdown_$$" pids=() i
+    # Two parked on the workers, two queued behind them
+    start_server --workers 2
+    for i in 1 2 3 4; do
+        curl -s -o /dev/null -w '%{http_code}\n' --max-time 5 "http://localhost:$PORT/longpoll?timeout=1" >> "$out" &
+        pids+=($!)
+    done
+    sleep 0.3
+    kill -TERM "$SERVER_PID"
+    sleep 0.2
+    local late
+    late=$(curl -s -o /dev/null -w '%{http_code}' --max-time 2 "http://localhost:$PORT/")
+    local curl_status=$?
+    wait "${pids[@]}"
+    check "in-flight and queued requests all answered" "200 200 200 200" "$(sort "$out" | tr '\n' ' ')"
+    check "new connection refused, not left hanging" "000 7" "$late $curl_status"
+    wait "$SERVER_PID"
+    check "exits cleanly once drained" "0" "$?"
+    SERVER_PID=""
+    check "drain logged" "draining 2 queued connections" "$(cat "$SERVER_LOG")"
+    rm -f "$out"
+
+    # Nobody waits out the idle timeout: not a kept-alive connection, nor one
+    # that never sent a request
+    start_server --keep-alive 30
+    exec 3<>"/dev/tcp/127.0.0.1/$PORT"
+    printf 'GET / HTTP/1.1\r\nHost: localhost\r\n\r\n' >&3
+    sleep 0.2
+    hold_connections 2
+    terminate_server
+    check "idle connections closed promptly" "yes" "$( (( EXIT_MS < 1000 )) && echo yes || echo "${EXIT_MS}ms")"
+    check "their responses still kept alive" "Connection: keep-alive" "$(timeout 1 cat <&3)"
+    exec 3<&-
+    release_connections
+
+    start_server --shutdown-timeout 1
+    curl -s -o /dev/null --max-time 5 "http://localhost:$PORT/longpoll?timeout=5" &
+    pids=($!)
+    sleep 0.3
+    terminate_server
+    wait "${pids[@]}"
+    check "gives up after --shutdown-timeout" "yes" \
+        "$( (( EXIT_MS >= 600 && EXIT_MS < 2000 )) && echo yes || echo "${EXIT_MS}ms")"
+    check "and says so in its status" "1" "$EXIT_STATUS"
+}
+
+test_count_allocs() {
+    echo -e "\n📍 --cfg count_allocs"
+    start_server
+    check_absent "not in a normal build" "X-Allocations" "$(curl -s -D - -o /dev/null "http://localhost:$PORT/")"
+    check_absent "nor in /metrics" "allocations_total" "$(curl -s "http://localhost:$PORT/metrics")"
+
+    build_server "$ALLOCS_BINARY" --cfg count_allocs
+    local RUST_BINARY=$ALLOCS_BINARY  # For start_server
+    start_server
+    local small=$(curl -s -D - -o /dev/null "http://localhost:$PORT/" | tr -d '\r' | sed -n 's/^X-Allocations: //p')
+    local big=$(curl -s -D - -o /dev/null "http://localhost:$PORT/json-tree?depth=3&breadth=4" |
+        tr -d '\r' | sed -n 's/^X-Allocations: //p')
+    check "counted per request" "yes" "$( (( ${small:-0} >= 1 )) && echo yes || echo "'$small'")"
+    check "a bigger response allocates more" "yes" "$( (( ${big:-0} > ${small:-0} )) && echo yes || echo "$big vs $small")"
+    check "bytes alongside" "X-Allocated-Bytes: " "$(curl -s -D - -o /dev/null "http://localhost:$PORT/")"
+    check "totals in /metrics" "allocated_bytes_total " "$(curl -s "http://localhost:$PORT/metrics")"
+}
+
+test_keep_alive() {
+    echo -e "\n📍 --keep-alive"
+    start_server
+    local ms=$(time_to_close "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+    check "off by default: closed after one response" "yes" "$( (( ms < 1000 )) && echo yes || echo "${ms}ms")"
+
+    start_server --keep-alive 5
+    local response
+    response=$(raw_request "POST /something HTTP/1.1\r\nHost: localhost\r\nContent-Length: 9\r\n\r\n{\"k\":\"v\"}GET /something?a=1 HTTP/1.1\r\nHost: localhost\r\n\r\nGET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+    check "next request starts right after a body" 'Query: {"a": "1"}' "$response"
+    check "all pipelined requests answered" "3" "$(grep -o 'HTTP/1.1 200' <<< "$response" | wc -l)"
+    check "persistent responses say so" "Connection: keep-alive" "$response"
+
+    # One at a time, waiting for each response, as a client without pipelining does
+    response=$(curl -s -w '|%{num_connects}' -d '{"k":"v"}' "http://localhost:$PORT/something" \
+        --next -w '|%{num_connects}' "http://localhost:$PORT/something?b=2")
+    check "POST then GET on one connection" '{"route":"/something","body":{"k":"v"}}|1Route: /something, Query: {"b": "2"}|0' "$response"
+
+    # The body's tail shares a segment with the next request
+    response=$(slow_request 0.2 "POST /something HTTP/1.1\r\nContent-Length: 9\r\n\r\n{\"k\"" \
+        ":\"v\"}GET /something?c=3 HTTP/1.1\r\nConnection: close\r\n\r\n")
+    check "body split across segments" '"body":{"k":"v"}' "$response"
+    check "request after a split body" 'Query: {"c": "3"}' "$response"
+
+    response=$(raw_request "POST /upload-rate HTTP/1.1\r\nContent-Length: 4\r\n\r\nabcdGET /something?d=4 HTTP/1.1\r\nConnection: close\r\n\r\n")
+    check "streamed body stops at Content-Length" '"bytes":4,' "$response"
+    check "request after a streamed body" 'Query: {"d": "4"}' "$response"
+
+    # An unread body is skipped, not parsed as the next request
+    check "unread body skipped" "2" \
+        "$(raw_request "GET / HTTP/1.1\r\nContent-Length: 5\r\n\r\nGET /GET / HTTP/1.1\r\nConnection: close\r\n\r\n" | grep -o 'HTTP/1.1 200' | wc -l)"
+
+    check "close wins over keep-alive" "Connection: close" \
+        "$(raw_request "GET / HTTP/1.1\r\nConnection: keep-alive, close\r\n\r\n")"
+    ms=$(time_to_close "GET / HTTP/1.1\r\nConnection: keep-alive, close\r\n\r\n")
+    check "connection closed after the response" "yes" "$( (( ms < 1000 )) && echo yes || echo "${ms}ms")"
+    check "close token found on a repeated header" "Connection: close" \
+        "$(raw_request "GET / HTTP/1.1\r\nConnection: te\r\nConnection: Close\r\n\r\n")"
+    check "HTTP/1.0 closes by default" "Connection: close" "$(raw_request "GET / HTTP/1.0\r\n\r\n")"
+
+    start_server --keep-alive 1
+    ms=$(time_to_close "GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n")
+    check "idle connection closed after the timeout" "yes" \
+        "$( (( ms >= 900 && ms < 1900 )) && echo yes || echo "${ms}ms")"
+}
+
+test_connection_log() {
+    echo -e "\n📍 --connection-log"
+    start_server --keep-alive 1 --connection-log --log-time none
+
+    local requests="GET / HTTP/1.1\r\nHost: localhost\r\n\r\nGET / HTTP/1.1\r\nConnection: close\r\n\r\n"
+    local out=$(raw_request "$requests" | wc -c) in=$(printf '%b' "$requests" | wc -c)
+    sleep 0.1
+    check "requests and bytes both ways" "requests=2 in=$in out=$out " "$(cat "$SERVER_LOG")"
+    check "ended by the response" "end=server-close" "$(tail -n 1 "$SERVER_LOG")"
+
+    raw_request "GET / HTTP/1.1\r\n\r\n" > /dev/null
+    sleep 0.1
+    check "idle past --keep-alive" "end=timeout" "$(tail -n 1 "$SERVER_LOG")"
+    check "lifetime covers the idle wait" "yes" \
+        "$(awk -F'lifetime=' 'END { split($2, a, "ms"); print (a[1] >= 900) ? "yes" : a[1] }' "$SERVER_LOG")"
+
+    curl -s -o /dev/null "http://localhost:$PORT/"
+    sleep 0.1
+    check "client hung up first" "requests=1 " "$(grep 'end=client-close' "$SERVER_LOG")"
+
+    start_server --keep-alive 5 --max-requests 2 --connection-log
+    local response=$(raw_request "GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n")
+    check "--max-requests stops at the limit" "2" "$(grep -o 'HTTP/1.1 200' <<< "$response" | wc -l)"
+    check "last allowed response closes" "Connection: close" "$response"
+    check "ended by --max-requests" "requests=2 " "$(grep 'end=max-requests' "$SERVER_LOG")"
+
+    start_server --keep-alive 5
+    curl -s -o /dev/null "http://localhost:$PORT/"
+    check_absent "off by default" "requests=" "$(cat "$SERVER_LOG")"
+}
+
+test_max_bytes_per_connection() {
+    echo -e "\n📍 --max-bytes-per-connection"
+    start_server --max-bytes-per-connection 1000 --connection-log --log-time none
+    check "stream cut at the cap" "1000" "$(raw_request "GET /stream?chunks=200&size=100 HTTP/1.1\r\n\r\n" | wc -c)"
+    sleep 0.1
+    check "truncation logged" "cut off at --max-bytes-per-connection (1000 bytes)" "$(cat "$SERVER_LOG")"
+    check "ended by the cap" "end=max-bytes" "$(tail -n 1 "$SERVER_LOG")"
+
+    # A keep-alive response is ~140 bytes, so the second one is cut short
+    local requests="GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\n" response
+    start_server --keep-alive 5 --max-bytes-per-connection 200 --connection-log
+    response=$(raw_request "$requests")
+    check "responses add up to the cap" "200" "$(printf '%s' "$response" | wc -c)"
+    check "first response whole" "1" "$(grep -o 'Hello from Rust!' <<< "$response" | wc -l)"
+    sleep 0.1
+    check "cut on the second request" "requests=2 " "$(grep 'end=max-bytes' "$SERVER_LOG")"
+
+    start_server --keep-alive 5 --max-bytes-per-connection 200 --centralized-writer
+    check "applies to the writer thread" "200" "$(raw_request "$requests" | wc -c)"
+
+    start_server --keep-alive 5
+    check "no cap by default" "3" "$(raw_request "$requests" | grep -o 'Hello from Rust!' | wc -l)"
+
+    check "zero rejected" "at least 1" "$("$RUST_BINARY" --max-bytes-per-connection 0 2>&1)"
+}
+
+test_centralized_writer() {
+    echo -e "\n📍 --centralized-writer"
+    start_server --centralized-writer --keep-alive 5
+    local out="/tmp/rust_features_writer_$$" i pids=()
+    for i in 1 2 3 4 5 6; do
+        curl -s "http://localhost:$PORT/something?n=$i" > "$out.$i" &
+        pids+=($!)
+    done
+    wait "${pids[@]}"
+    check "concurrent responses all delivered" "6" "$(cat "$out".* | grep -o 'Route: /something' | wc -l)"
+    rm -f "$out".*
+
+    local response
+    response=$(raw_request "GET /something?a=1 HTTP/1.1\r\nHost: localhost\r\n\r\nGET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+    check "pipelined responses in order" "yes" \
+        "$([[ "$response" == *'Query: {"a": "1"}'*'Hello from Rust!' ]] && echo yes || echo "$response")"
+    local metrics=$(curl -s "http://localhost:$PORT/metrics")
+    check "writer counts its writes" "writer_writes_total 9" "$metrics"
+    check "writer counts its wakeups" "writer_batches_total" "$metrics"
+
+    start_server --centralized-writer --no-content-length
+    check "body written before the closing FIN" "Hello from Rust!" "$(curl -s "http://localhost:$PORT/")"
+}
+
+test_line_endings() {
+    echo -e "\n📍 Line endings"
+    start_server
+
+    check "LF-only GET parsed" "Query: {\"a\": \"1\"}" \
+        "$(raw_request "GET /something?a=1 HTTP/1.1\nHost: localhost\n\n")"
+
+    # The body starts right after the blank line, so a wrong offset
+    # would show up as a truncated or shifted echo
+    check "LF-only POST body offset" '"body":{"k":"v"}' \
+        "$(raw_request "POST /something HTTP/1.1\nHost: localhost\nContent-Length: 9\n\n{\"k\":\"v\"}")"
+
+    check "mixed CRLF and LF" '"body":{"k":"v"}' \
+        "$(raw_request "POST /something HTTP/1.1\r\nHost: localhost\nContent-Length: 9\r\n\n{\"k\":\"v\"}")"
+
+    check "bare CR in header rejected" "HTTP/1.1 400" \
+        "$(raw_request "GET / HTTP/1.1\r\nHost: localhost\rX-Smuggle: 1\r\n\r\n")"
+
+    check "bare CR as terminator rejected" "HTTP/1.1 400" \
+        "$(raw_request "GET / HTTP/1.1\rHost: localhost\r\r")"
+}
+
+test_upload_rate() {
+    echo -e "\n📍 /upload-rate with --read-timeout"
+    start_server --read-timeout 1
+
+    local head="POST /upload-rate HTTP/1.1\r\nHost: localhost\r\nContent-Length: 12\r\n\r\n"
+
+    local response
+    response=$(slow_request 0.3 "$head" "abcd" "efgh" "ijkl")
+    check "slow but steady upload completes" '"bytes":12' "$response"
+    local ms=$(grep -o '"elapsed_ms":[0-9]*' <<< "$response" | cut -d: -f2)
+    check "span covers first to last byte" "yes" "$([[ ${ms:-0} -ge 500 ]] && echo yes || echo "no (${ms}ms)")"
+
+    # Head only: the body never comes, so the first body read times out
+    check "stalled upload gets 408" "HTTP/1.1 408" "$(raw_request "$head")"
+}
+
+test_upload_file() {
+    echo -e "\n📍 /upload-file"
+    local body="/tmp/rust_features_upload_$$" dir="/tmp/rust_features_uploads_$$"
+    head -c 5000000 /dev/urandom > "$body"
+    mkdir -p "$dir"
+    local url="http://localhost:$PORT/upload-file"
+
+    start_server --max-body 8000000 --upload-dir "$dir"
+    local response=$(curl -s --data-binary "@$body" "$url")
+    check "multi-megabyte body saved" '"bytes":5000000' "$response"
+    check "generated name returned" '"file":"rs-upload-' "$response"
+    check "deleted once answered" "0" "$(ls "$dir" | wc -l)"
+    response=$(curl -s -H "Transfer-Encoding: chunked" --data-binary "@$body" "$url")
+    check "chunked body decoded" '"bytes":5000000' "$response"
+    check "chunked over --max-body gets 413" "413" \
+        "$(head -c 9000000 /dev/zero | status_of -H "Transfer-Encoding: chunked" --data-binary @- "$url")"
+    check "nothing left behind" "0" "$(ls "$dir" | wc -l)"
+
+    local head="POST /upload-file HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n"
+    check "chunk extensions and trailers" '"bytes":11' \
+        "$(raw_request "${head}5;ext=1\r\nhello\r\n6\r\n world\r\n0\r\nX-Trailer: yes\r\n\r\n")"
+    check "bad chunk size gets 400" "HTTP/1.1 400" "$(raw_request "${head}5x\r\nhello\r\n0\r\n\r\n")"
+    check "chunk longer than declared gets 400" "HTTP/1.1 400" "$(raw_request "${head}3\r\nhello\r\n0\r\n\r\n")"
+    check "Content-Length with chunked gets 400" "HTTP/1.1 400" \
+        "$(raw_request "POST /upload-file HTTP/1.1\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n")"
+
+    start_server --keep-alive 5 --upload-dir "$dir"
+    local both=$(raw_request "${head}5\r\nhello\r\n0\r\n\r\nGET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+    check "keep-alive continues after a chunked body" "Hello from Rust!" "$both"
+    both=$(raw_request "POST /something HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\nGET / HTTP/1.1\r\n\r\n")
+    check "unread chunked body closes instead" "Connection: close" "$both"
+    check_absent "so its chunks aren't read as a request" "Hello from Rust!" "$both"
+
+    start_server --max-body 8000000 --upload-dir "$dir" --upload-ttl 2
+    response=$(curl -s --data-binary "@$body" "$url")
+    local name=$(grep -o 'rs-upload-[0-9-]*' <<< "$response")
+    check "kept with --upload-ttl" "5000000" "$(stat -c %s "$dir/$name" 2>&1)"
+    sleep 3.5
+    check "swept after the TTL" "no" "$([[ -e "$dir/$name" ]] && echo yes || echo no)"
+    rm -rf "$body" "$dir"
+}
+
+test_body_read_chunk_size() {
+    echo -e "\n📍 --body-read-chunk-size"
+    local body="/tmp/rust_features_body_$$"
+    head -c 1000000 /dev/urandom > "$body"
+    local url="http://localhost:$PORT/upload-rate"
+
+    start_server --body-read-chunk-size 1000
+    local response=$(curl -s --data-binary "@$body" "$url")
+    check "large body read in full" '"bytes":1000000' "$response"
+    local reads=$(grep -o '"reads":[0-9]*' <<< "$response" | cut -d: -f2)
+    check "no read over the chunk size" "yes" "$( (( reads >= 1000 )) && echo yes || echo "${reads} reads")"
+
+    start_server
+    reads=$(curl -s --data-binary "@$body" "$url" | grep -o '"reads":[0-9]*' | cut -d: -f2)
+    check "default chunks are bigger" "yes" "$( (( reads >= 62 && reads < 1000 )) && echo yes || echo "${reads} reads")"
+    rm -f "$body"
+    check "zero rejected" "at least 1" "$("$RUST_BINARY" --body-read-chunk-size 0 2>&1)"
+}
+
+test_compute() {
+    echo -e "\n📍 /compute"
+    start_server
+
+    check "fib(0)" '"result":0' "$(curl -s "http://localhost:$PORT/compute?fib=0")"
+    check "fib(10)" '"fib":10,"result":55' "$(curl -s "http://localhost:$PORT/compute?fib=10")"
+    check "fib(93) is exact" '"result":12200160415121876738' "$(curl -s "http://localhost:$PORT/compute?fib=93")"
+    check "primes below 2" '"result":0' "$(curl -s "http://localhost:$PORT/compute?primes=2")"
+    check "primes below 100" '"primes":100,"result":25' "$(curl -s "http://localhost:$PORT/compute?primes=100")"
+    check "both parameters rejected" "400" "$(status_of "http://localhost:$PORT/compute?fib=1&primes=1")"
+    check "over the cap rejected" "400" "$(status_of "http://localhost:$PORT/compute?primes=5000001")"
+}
+
+test_json_tree() {
+    echo -e "\n📍 /json-tree"
+    start_server
+
+    local url="http://localhost:$PORT/json-tree"
+    local tree=$(curl -s "$url?depth=1&breadth=2")
+    check "one level of members" "yes" "$([[ "$tree" =~ ^\{\"k0\":[^{}]+,\"k1\":[^{}]+\}$ ]] && echo yes || echo "$tree")"
+    check "nested shape: 3 + 9 members" "12" "$(curl -s "$url?depth=2&breadth=3" | grep -o '"k[0-9]*":' | wc -l)"
+    check "JSON content type" "application/json" "$(curl -s -D - -o /dev/null "$url?depth=1&breadth=1")"
+    check "same seed, same tree" "same" \
+        "$([[ "$(curl -s "$url?depth=3&breadth=2&seed=7")" == "$(curl -s "$url?depth=3&breadth=2&seed=7")" ]] && echo same)"
+    check "default seed is 1" "same" \
+        "$([[ "$(curl -s "$url?depth=2&breadth=2")" == "$(curl -s "$url?depth=2&breadth=2&seed=1")" ]] && echo same)"
+    check "other seed, other leaves" "different" \
+        "$([[ "$(curl -s "$url?depth=3&breadth=2&seed=1")" != "$(curl -s "$url?depth=3&breadth=2&seed=2")" ]] && echo different)"
+    check "depth over the cap rejected" "400" "$(status_of "$url?depth=9&breadth=1")"
+    check "breadth over the cap rejected" "400" "$(status_of "$url?depth=1&breadth=17")"
+    check "zero breadth rejected" "400" "$(status_of "$url?depth=1&breadth=0")"
+    check "too many nodes rejected" "400" "$(status_of "$url?depth=5&breadth=16")"
+    check "shape required" "400" "$(status_of "$url?depth=2")"
+}
+
+test_schema() {
+    echo -e "\n📍 --schema"
+    start_server --schema name:string,count:number,tags:array
+
+    local url="http://localhost:$PORT/something"
+    post() { curl -s -w ' %{http_code}' -H "Content-Type: application/json" -d "$1" "$url"; }
+    check "valid body echoed" '"body":{"name":"a","count":2,"tags":[],"extra":null}} 200' \
+        "$(post '{"name":"a","count":2,"tags":[],"extra":null}')"
+    check "missing field listed" '{"field":"count","error":"missing"}' "$(post '{"name":"a","tags":[1]}')"
+    check "wrong type listed" '{"field":"name","error":"expected string, got number"}' \
+        "$(post '{"name":1,"count":-2.5e3,"tags":[]}')"
+    check "every failure in one 422" \
+        '{"errors":[{"field":"name","error":"missing"},{"field":"count","error":"expected number, got string"},{"field":"tags","error":"expected array, got object"}]} 422' \
+        "$(post '{"count":"2","tags":{}}')"
+    check "last duplicate key wins" "200" "$(post '{"name":1,"name":"b","count":0,"tags":[]}' | cut -d' ' -f2)"
+    check "non-object body is 422" "expected an object" "$(post '[1,2]')"
+    check "malformed JSON is 400" "400" "$(status_of -d '{"name":"a",}' "$url")"
+    check "trailing garbage is 400" "400" "$(status_of -d '{"name":"a"} x' "$url")"
+    check "empty body has no fields" "422" "$(status_of -X POST "$url")"
+    check "422 counted" 'responses_total{status="422"} 5' "$(curl -s "http://localhost:$PORT/metrics")"
+
+    start_server
+    check "off by default" "200" "$(status_of -d 'not json' "$url")"
+}
+
+test_accept() {
+    echo -e "\n📍 Accept negotiation"
+    start_server
+
+    local url="http://localhost:$PORT/json-tree?depth=1&breadth=1"
+    check "no Accept: natural type" "200" "$(curl -s -o /dev/null -w '%{http_code}' -H "Accept:" "$url")"
+    check "*/*: natural type" "application/json" "$(curl -s -D - -o /dev/null -H "Accept: */*" "$url")"
+    check "exact type" "200" "$(status_of -H "Accept: application/json" "$url")"
+    check "type wildcard" "200" "$(status_of -H "Accept: application/*" "$url")"
+    check "one of several" "200" "$(status_of -H "Accept: text/html, application/json;q=0.5" "$url")"
+    check "unacceptable type is 406" "406" "$(status_of -H "Accept: application/xml" "$url")"
+    check "q=0 refuses it" "406" "$(status_of -H "Accept: application/json;q=0" "$url")"
+    check "specific q=0 beats */*" "406" "$(status_of -H "Accept: */*, application/json;q=0" "$url")"
+    check "wildcard covers what isn't refused" "200" "$(status_of -H "Accept: */*;q=0.1, text/plain;q=0" "$url")"
+    check "text route refuses JSON-only" "406" "$(status_of -H "Accept: application/json" "http://localhost:$PORT/")"
+    check "errors aren't negotiated" "404" "$(status_of -H "Accept: application/xml" "http://localhost:$PORT/missing")"
+    check "406 counted" 'responses_total{status="406"} 4' "$(curl -s "http://localhost:$PORT/metrics")"
+
+    # A refused request never reaches its route, so nothing it would do happens
+    local dir="/tmp/rust_features_accept_$$"
+    mkdir -p "$dir"
+    start_server --upload-dir "$dir" --upload-ttl 60 --admin-token s3cret
+    check "refused upload gets 406" "406" \
+        "$(status_of -H "Accept: application/xml" --data-binary "hello" "http://localhost:$PORT/upload-file")"
+    check "and leaves no file behind" "0" "$(ls "$dir" | wc -l)"
+    check "refused admin change gets 406" "406" "$(status_of -X POST -H "Authorization: Bearer s3cret" \
+        -H "Accept: application/xml" "http://localhost:$PORT/admin/routes?disable=/compute")"
+    check "and changes nothing" "200" "$(status_of "http://localhost:$PORT/compute?fib=10")"
+    rm -rf "$dir"
+}
+
+test_cookies() {
+    echo -e "\n📍 /cookies"
+    start_server
+
+    local url="http://localhost:$PORT/cookies"
+    check "no cookies" '{"cookies":{}}' "$(curl -s "$url")"
+    check "several in one header" '{"cookies":{"a":"1","b":"two","c":""}}' \
+        "$(curl -s -H "Cookie: b=two; a=1;c=" "$url")"
+    check "repeated headers and names" '{"cookies":{"a":"1","b":"2"}}' \
+        "$(curl -s -H "Cookie: a=1" -H "Cookie: b=2; a=3" "$url")"
+    check "quotes and junk" '{"cookies":{"q":"x y","z":"\\"}}' \
+        "$(curl -s -H 'Cookie: q="x y"; novalue; =empty; z=\' "$url")"
+
+    local headers=$(curl -s -D - -o /dev/null "$url/set?session=abc123&theme=dark")
+    check "one Set-Cookie each" "2" "$(grep -c '^Set-Cookie:' <<< "$headers")"
+    check "scoped to the site" "Set-Cookie: session=abc123; Path=/" "$headers"
+    local jar="/tmp/rust_features_jar_$$"
+    curl -s -c "$jar" -o /dev/null "$url/set?session=abc123&theme=dark"
+    check "round trip through a cookie jar" '{"cookies":{"session":"abc123","theme":"dark"}}' \
+        "$(curl -s -b "$jar" "$url")"
+    rm -f "$jar"
+    check "invalid value rejected" "400" "$(status_of "$url/set?a=b;c")"
+    check "invalid name rejected" "400" "$(status_of "$url/set?a@b=1")"
+    check "nothing to set rejected" "400" "$(status_of "$url/set")"
+}
+
+test_static_gzip() {
+    echo -e "\n📍 --static with .gz sidecars"
+    mkdir -p "$STATIC_DIR"
+    echo "<h1>compressed</h1>" > "$STATIC_DIR/page.html"
+    gzip -k "$STATIC_DIR/page.html"
+    echo "no sidecar" > "$STATIC_DIR/plain.txt"
+    start_server --static "$STATIC_DIR"
+
+    local url="http://localhost:$PORT/static"
+    local headers
+    headers=$(curl -s -D - -o /dev/null -H "Accept-Encoding: gzip" "$url/page.html")
+    check "sidecar sent to gzip clients" "Content-Encoding: gzip" "$headers"
+    check "sidecar length is the .gz size" "Content-Length: $(stat -c %s "$STATIC_DIR/page.html.gz")" "$headers"
+    check "responses vary on Accept-Encoding" "Vary: Accept-Encoding" "$headers"
+    check "sidecar decompresses to the file" "<h1>compressed</h1>" "$(curl -s --compressed "$url/page.html")"
+
+    headers=$(curl -s -D - -o /dev/null "$url/page.html")
+    check_absent "plain file without Accept-Encoding" "Content-Encoding" "$headers"
+    headers=$(curl -s -D - -o /dev/null -H "Accept-Encoding: gzip;q=0, *" "$url/page.html")
+    check_absent "gzip;q=0 refuses the sidecar" "Content-Encoding" "$headers"
+
+    headers=$(curl -s -D - -H "Accept-Encoding: gzip" "$url/plain.txt")
+    check "no sidecar serves the plain file" "no sidecar" "$headers"
+    check_absent "no sidecar, no Content-Encoding" "Content-Encoding" "$headers"
+
+    check "traversal rejected" "400" "$(status_of --path-as-is "$url/../server.rs")"
+    check "missing file is 404" "404" "$(status_of "$url/missing.html")"
+}
+
+test_static_ranges() {
+    echo -e "\n📍 --static ranges and If-Range"
+    mkdir -p "$STATIC_DIR"
+    printf '0123456789' > "$STATIC_DIR/digits.txt"
+    start_server --static "$STATIC_DIR"
+
+    local url="http://localhost:$PORT/static/digits.txt"
+    local headers
+    headers=$(curl -s -D - -H "Range: bytes=2-5" "$url")
+    check "range gets 206" "HTTP/1.1 206" "$headers"
+    check "range body" "2345" "$headers"
+    check "Content-Range" "Content-Range: bytes 2-5/10" "$headers"
+    check "suffix range" "789" "$(curl -s -H "Range: bytes=-3" "$url")"
+    check "open-ended range" "89" "$(curl -s -H "Range: bytes=8-" "$url")"
+    headers=$(curl -s -D - -o /dev/null -H "Range: bytes=10-" "$url")
+    check "range past the end gets 416" "HTTP/1.1 416" "$headers"
+    check "416 reports the length" "Content-Range: bytes */10" "$headers"
+    local metrics=$(curl -s "http://localhost:$PORT/metrics")
+    check "206 is counted" 'responses_total{status="206"} 3' "$metrics"
+    check "416 is counted" 'responses_total{status="416"} 1' "$metrics"
+    check "several ranges get the whole file" "0123456789" "$(curl -s -H "Range: bytes=0-1,4-5" "$url")"
+    headers=$(curl -s -D - -H "Range: items=0-9" "$url")
+    check "other units get a full 200" "HTTP/1.1 200" "$headers"
+    check "with the whole file" "0123456789" "$headers"
+    check_absent "and no Content-Range" "Content-Range" "$headers"
+    check "unit must be exactly bytes" "200" "$(status_of -H "Range: bytesx=0-1" "$url")"
+
+    headers=$(curl -s -D - -o /dev/null "$url")
+    local etag=$(tr -d '\r' <<< "$headers" | sed -n 's/^ETag: //p')
+    local modified=$(tr -d '\r' <<< "$headers" | sed -n 's/^Last-Modified: //p')
+    check "ETag sent" "yes" "$([[ "$etag" == \"*\" ]] && echo yes || echo "$etag")"
+    check "Last-Modified is an HTTP-date" "yes" \
+        "$([[ "$modified" =~ ^[A-Z][a-z]{2},\ [0-9]{2}\ [A-Z][a-z]{2}\ [0-9]{4}\ [0-9:]{8}\ GMT$ ]] && echo yes || echo "$modified")"
+
+    check "matching If-Range ETag gets the range" "206" \
+        "$(status_of -H "Range: bytes=0-1" -H "If-Range: $etag" "$url")"
+    check "matching If-Range date gets the range" "206" \
+        "$(status_of -H "Range: bytes=0-1" -H "If-Range: $modified" "$url")"
+    check "stale If-Range ETag gets the whole file" "0123456789" \
+        "$(curl -s -H "Range: bytes=0-1" -H 'If-Range: "stale"' "$url")"
+    check "weak If-Range ETag never matches" "200" \
+        "$(status_of -H "Range: bytes=0-1" -H "If-Range: W/$etag" "$url")"
+    check "other If-Range date gets the whole file" "200" \
+        "$(status_of -H "Range: bytes=0-1" -H "If-Range: Thu, 01 Jan 1970 00:00:00 GMT" "$url")"
+
+    # Rewriting the file changes its validators
+    sleep 0.01
+    printf 'abcdefghij' > "$STATIC_DIR/digits.txt"
+    check "old ETag stale after a change" "abcdefghij" \
+        "$(curl -s -H "Range: bytes=0-1" -H "If-Range: $etag" "$url")"
+}
+
+test_digest_trailer() {
+    echo -e "\n📍 --digest-trailer"
+    mkdir -p "$STATIC_DIR"
+    head -c 200000 /dev/urandom > "$STATIC_DIR/random.bin"
+    start_server --static "$STATIC_DIR" --digest-trailer
+
+    local url="http://localhost:$PORT/static/random.bin"
+    local expected=$(openssl dgst -sha256 -binary "$STATIC_DIR/random.bin" | base64)
+    local raw headers
+    raw=$(curl -s --raw -D - -H "TE: trailers" "$url" | tr -d '\r' | tail -c 200)
+    headers=$(curl -s -D - -o /dev/null -H "TE: trailers" "$url")
+    check "trailer declared" "Trailer: Digest" "$headers"
+    check "chunked" "Transfer-Encoding: chunked" "$headers"
+    check "trailer carries the SHA-256" "Digest: sha-256=$expected" "$raw"
+    check "body intact" "same" \
+        "$(curl -s -H "TE: gzip, trailers" "$url" | cmp -s - "$STATIC_DIR/random.bin" && echo same)"
+
+    # Without TE: trailers the trailer would be lost, so the file is buffered
+    headers=$(curl -s -D - -o /dev/null "$url")
+    check "no TE: buffered with Content-Length" "Content-Length: 200000" "$headers"
+    check "no TE: digest as a header" "Digest: sha-256=$expected" "$headers"
+    check_absent "no TE: not chunked" "Transfer-Encoding" "$headers"
+    check "TE without trailers: buffered" "Content-Length: 200000" \
+        "$(curl -s -D - -o /dev/null -H "TE: gzip" "$url")"
+    check "ranges aren't streamed" "Content-Length: 4" \
+        "$(curl -s -D - -o /dev/null -H "Range: bytes=0-3" "$url")"
+}
+
+test_notsent_lowat() {
+    echo -e "\n📍 --notsent-lowat"
+    mkdir -p "$STATIC_DIR"
+    head -c 1000000 /dev/urandom > "$STATIC_DIR/large.bin"
+    start_server --static "$STATIC_DIR" --digest-trailer --notsent-lowat 1
+
+    # A throttled reader keeps the socket buffer full, so writes block on the
+    # low-water mark over and over
+    local url="http://localhost:$PORT/static/large.bin"
+    check "streamed file intact to a slow reader" "same" \
+        "$(curl -s --limit-rate 4M -H "TE: trailers" "$url" | cmp -s - "$STATIC_DIR/large.bin" && echo same)"
+    check "buffered file intact" "same" "$(curl -s "$url" | cmp -s - "$STATIC_DIR/large.bin" && echo same)"
+    check "bytes required" "Invalid or missing value for --notsent-lowat" "$("$RUST_BINARY" --notsent-lowat lots 2>&1)"
+}
+
+test_connect() {
+    echo -e "\n📍 CONNECT"
+    start_server
+    local response
+    response=$(raw_request "CONNECT localhost:$PORT HTTP/1.1\r\nHost: localhost:$PORT\r\n\r\n")
+    check "refused without --enable-connect" "HTTP/1.1 405" "$response"
+    check "405 lists the methods" "Allow: GET, POST" "$response"
+    check "405 is counted" 'responses_total{status="405"} 1' "$(curl -s "http://localhost:$PORT/metrics")"
+
+    start_server --connect-echo
+    check "echo tunnel returns the client's bytes" "$(printf 'HTTP/1.1 200 Connection Established\r\n\r\nping')" \
+        "$(raw_request "CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n\r\nping")"
+
+    # Tunnel back into the server itself, all on one connection
+    start_server --enable-connect
+    response=$(raw_request "CONNECT localhost:$PORT HTTP/1.1\r\nHost: localhost:$PORT\r\n\r\nGET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+    check "tunnel established" "HTTP/1.1 200 Connection Established" "$response"
+    check "request relayed through the tunnel" "Hello from Rust!" "$response"
+    check "unreachable target gets 502" "HTTP/1.1 502" "$(raw_request "CONNECT 127.0.0.1:1 HTTP/1.1\r\n\r\n")"
+    check "502 is counted" 'responses_total{status="502"} 1' "$(curl -s "http://localhost:$PORT/metrics")"
+    check "target without a port gets 400" "HTTP/1.1 400" "$(raw_request "CONNECT localhost HTTP/1.1\r\n\r\n")"
+}
+
+test_upgrade() {
+    echo -e "\n📍 --enable-upgrade"
+    local upgrade="Upgrade: raw-echo\r\nConnection: Upgrade\r\n"
+    start_server
+    check "ignored without the flag" "Hello from Rust!" \
+        "$(raw_request "GET / HTTP/1.1\r\nUpgrade: raw-echo\r\nConnection: Upgrade, close\r\n\r\n")"
+
+    start_server --enable-upgrade
+    check "101, then the client's bytes back" \
+        "$(printf 'HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: raw-echo\r\n\r\nping')" \
+        "$(raw_request "GET / HTTP/1.1\r\n$upgrade\r\nping")"
+    check "echo keeps going after the first bytes" "one two" "$(slow_request 0.2 "GET / HTTP/1.1\r\n$upgrade\r\none " "two")"
+    check "switch counted in metrics" 'responses_total{status="1xx"} 2' "$(curl -s "http://localhost:$PORT/metrics")"
+
+    check "needs Connection: upgrade" "Hello from Rust!" \
+        "$(raw_request "GET / HTTP/1.1\r\nUpgrade: raw-echo\r\nConnection: close\r\n\r\n")"
+    check "ignored over HTTP/1.0" "HTTP/1.0 200 OK" "$(raw_request "GET / HTTP/1.0\r\n$upgrade\r\n")"
+    check "other protocols answered normally" "Hello from Rust!" \
+        "$(raw_request "GET / HTTP/1.1\r\nUpgrade: h2c\r\nConnection: Upgrade, close\r\n\r\n")"
+}
+
+test_bad_chunked() {
+    echo -e "\n📍 /debug/bad-chunked"
+    start_server
+    check "off without --debug-endpoints" "404" "$(status_of "http://localhost:$PORT/debug/bad-chunked?variant=short-chunk")"
+
+    start_server --debug-endpoints
+    check "framing sent as declared" "$(printf 'Transfer-Encoding: chunked\r\nConnection: close\r\n\r\na\r\nhello\r\n0\r\n\r\n')" \
+        "$(raw_request "GET /debug/bad-chunked?variant=short-chunk HTTP/1.1\r\n\r\n")"
+    local old=$(raw_request "GET /debug/bad-chunked?variant=short-chunk HTTP/1.0\r\n\r\n")
+    check "HTTP/1.0 answered in kind" "HTTP/1.0 200 OK" "$old"
+    check_absent "HTTP/1.0 not declared chunked" "Transfer-Encoding" "$old"
+    local variant failed=""
+    for variant in short-chunk bad-size huge-size no-data-crlf no-last-chunk; do
+        curl -s -o /dev/null --max-time 2 "http://localhost:$PORT/debug/bad-chunked?variant=$variant" || continue
+        failed+="$variant "
+    done
+    check "curl rejects each variant" "none accepted" "${failed:-none accepted}"
+    check "unknown variant lists the names" "one of: short-chunk, long-chunk" \
+        "$(curl -s "http://localhost:$PORT/debug/bad-chunked?variant=nope")"
+}
+
+test_bad_length() {
+    echo -e "\n📍 /debug/bad-length"
+    start_server
+    check "off without --debug-endpoints" "404" "$(status_of "http://localhost:$PORT/debug/bad-length?variant=too-large")"
+
+    start_server --debug-endpoints
+    check "header sent as declared" "$(printf 'Content-Length: 3\r\nConnection: close\r\n\r\nhello')" \
+        "$(raw_request "GET /debug/bad-length?variant=too-small HTTP/1.1\r\n\r\n")"
+    check "HTTP/1.0 answered in kind" "HTTP/1.0 200 OK" \
+        "$(raw_request "GET /debug/bad-length?variant=too-small HTTP/1.0\r\n\r\n")"
+    local variant failed=""
+    for variant in too-large conflicting not-a-number; do
+        curl -s -o /dev/null --max-time 2 "http://localhost:$PORT/debug/bad-length?variant=$variant" || continue
+        failed+="$variant "
+    done
+    check "curl rejects each variant" "none accepted" "${failed:-none accepted}"
+    local body=$(curl -s "http://localhost:$PORT/debug/bad-length?variant=too-small")
+    check "too-small read to its length" "hel" "$body"
+    check_absent "and no further" "hello" "$body"
+    check "unknown variant lists the names" "one of: too-large, too-small" \
+        "$(curl -s "http://localhost:$PORT/debug/bad-length?variant=nope")"
+}
+
+test_stream_flush() {
+    echo -e "\n📍 /stream and --response-buffer-flush-threshold"
+    local url="http://localhost:$PORT/stream"
+    # Each 10-byte chunk is 15 bytes framed
+    start_server
+    check "chunked" "Transfer-Encoding: chunked" "$(curl -s -D - -o /dev/null "$url?chunks=3&size=10")"
+    check "body intact" "2000" "$(curl -s "$url?chunks=200&size=10" | wc -c)"
+    local metrics=$(curl -s "http://localhost:$PORT/metrics")
+    check "a write per chunk by default" "stream_writes_total 205" "$metrics"
+    check "chunks counted" "stream_chunks_total 203" "$metrics"
+    local old=$(raw_request "GET /stream?chunks=3&size=4 HTTP/1.0\r\n\r\n")
+    check "HTTP/1.0 answered in kind" "HTTP/1.0 200 OK" "$old"
+    check "HTTP/1.0 body ends at the close" "$(printf 'Connection: close\r\n\r\n............')" "$old"
+    check_absent "HTTP/1.0 not chunked" "Transfer-Encoding" "$old"
+
+    start_server --response-buffer-flush-threshold 4096
+    check "buffered body intact" "2000" "$(curl -s "$url?chunks=200&size=10" | wc -c)"
+    check "small chunks coalesced" "stream_writes_total 1" "$(curl -s "http://localhost:$PORT/metrics")"
+    curl -s -o /dev/null "$url?chunks=1000&size=10"
+    check "flushed each time the threshold fills" "stream_writes_total 5" "$(curl -s "http://localhost:$PORT/metrics")"
+
+    # 5 chunks 200ms apart: without a time cap they all wait for the end
+    start_server --response-buffer-flush-threshold 1000000
+    curl -s -o /dev/null "$url?chunks=5&size=10&interval_ms=200"
+    check "held to the end without a time cap" "stream_writes_total 1" "$(curl -s "http://localhost:$PORT/metrics")"
+    start_server --response-buffer-flush-threshold 1000000 --response-buffer-flush-ms 50
+    local first=$(curl -s -N --max-time 0.15 "$url?chunks=5&size=10&interval_ms=200" | wc -c)
+    check "time cap sends the first chunk early" "10" "$first"
+    start_server --response-buffer-flush-threshold 1000000 --response-buffer-flush-ms 50
+    curl -s -o /dev/null "$url?chunks=5&size=10&interval_ms=200"
+    # The last chunk goes out with the end of the body
+    check "one write per chunk once overdue" "stream_writes_total 5" "$(curl -s "http://localhost:$PORT/metrics")"
+
+    check "bad size rejected" "400" "$(status_of "$url?size=0")"
+    check "time cap needs a threshold" "needs --response-buffer-flush-threshold" \
+        "$("$RUST_BINARY" --response-buffer-flush-ms 10 2>&1)"
+}
+
+test_dribble() {
+    echo -e "\n📍 /debug/dribble"
+    start_server --workers 1 --debug-endpoints
+
+    local url="http://localhost:$PORT/debug/dribble"
+    local out="/tmp/rust_features_dribble_$$"
+    curl -s -D - --max-time 1 "$url?interval_ms=100&bytes=1000" > "$out"
+    check "client read timeout fires" "28" "$?"
+    check "head sent at once" "Content-Length: 1000" "$(cat "$out")"
+    local got=$(sed '1,/^\r$/d' "$out" | wc -c)
+    check "body dribbled" "yes" "$( (( got >= 7 && got <= 10 )) && echo yes || echo "$got bytes")"
+    rm -f "$out"
+
+    # The only worker must be free again soon after the client hangs up
+    local started=$(date +%s%N)
+    check "worker freed on disconnect" "200" "$(status_of "http://localhost:$PORT/")"
+    local ms=$(( ($(date +%s%N) - started) / 1000000 ))
+    check "freed within a tick" "yes" "$( (( ms < 500 )) && echo yes || echo "${ms}ms")"
+
+    check "short dribble completes" "....." "$(curl -s "$url?interval_ms=10&bytes=5")"
+    check "HTTP/1.0 answered in kind" "HTTP/1.0 200 OK" \
+        "$(raw_request "GET /debug/dribble?interval_ms=10&bytes=5 HTTP/1.0\r\n\r\n")"
+    check "zero interval rejected" "400" "$(status_of "$url?interval_ms=0")"
+    check "too many bytes rejected" "400" "$(status_of "$url?bytes=86401")"
+    start_server
+    check "needs --debug-endpoints" "404" "$(status_of "$url")"
+}
+
+test_sysstat() {
+    echo -e "\n📍 /debug/sysstat"
+    start_server --debug-endpoints
+
+    local url="http://localhost:$PORT/debug/sysstat"
+    local stat=$(curl -s "$url")
+    check "load averages" "yes" "$([[ "$stat" =~ \"load_average\":\[[0-9.]+,[0-9.]+,[0-9.]+\] ]] && echo yes || echo "$stat")"
+    local rss=$(grep -o '"rss_bytes":[0-9]*' <<< "$stat" | cut -d: -f2)
+    local diff=$(( $(awk '/^VmRSS:/ { print $2 }' "/proc/$SERVER_PID/status") - ${rss:-0} / 1024 ))
+    check "RSS matches /proc" "yes" "$( (( ${diff#-} < 1024 )) && echo yes || echo "off by ${diff}KiB")"
+    local fds=$(grep -o '"open_fds":[0-9]*' <<< "$stat" | cut -d: -f2)
+    hold_connections 3
+    sleep 0.2
+    local held=$(curl -s "$url" | grep -o '"open_fds":[0-9]*' | cut -d: -f2)
+    release_connections
+    check "open connections counted" "3" "$(( held - fds ))"
+
+    start_server
+    check "needs --debug-endpoints" "404" "$(status_of "$url")"
+}
+
+test_admin_routes() {
+    echo -e "\n📍 /admin/routes"
+    start_server
+    check "off without --admin-token" "404" "$(status_of "http://localhost:$PORT/admin/routes")"
+
+    start_server --admin-token s3cret --keep-alive 5
+    local url="http://localhost:$PORT/admin/routes" auth="Authorization: Bearer s3cret"
+    check "no token gets 401" "401" "$(status_of "$url")"
+    check "wrong token gets 401" "401" "$(curl -s -o /dev/null -w '%{http_code}' -H "Authorization: Bearer nope" "$url")"
+    check "401 is counted" 'responses_total{status="401"} 2' "$(curl -s "http://localhost:$PORT/metrics")"
+    check "every route listed as on" '"/compute":true' "$(curl -s -H "$auth" "$url")"
+
+    local listed=$(curl -s -X POST -H "$auth" "$url?disable=/compute,/json-tree")
+    check "disabled routes listed as off" '"/compute":false,"/json-tree":false' "$listed"
+    check "disabled route gets 503" "503" "$(status_of "http://localhost:$PORT/compute?fib=10")"
+    check "other routes still work" "200" "$(status_of "http://localhost:$PORT/something?a=1")"
+    check "a 503 keeps the connection" "10" \
+        "$(curl -s -o /dev/null -o /dev/null -w '%{num_connects}' "http://localhost:$PORT/compute?fib=10" "http://localhost:$PORT/")"
+
+    check "unknown route refused" "400" "$(curl -s -o /dev/null -w '%{http_code}' -X POST -H "$auth" "$url?disable=/json-tree&enable=/nope")"
+    check "a bad change applies nothing" "503" "$(status_of "http://localhost:$PORT/json-tree")"
+    check "/admin/routes can't be disabled" "400" "$(curl -s -o /dev/null -w '%{http_code}' -X POST -H "$auth" "$url?disable=/admin/routes")"
+
+    curl -s -o /dev/null -X POST -H "$auth" "$url?enable=/compute,/json-tree"
+    check "enabled route answers again" "200" "$(status_of "http://localhost:$PORT/compute?fib=10")"
+
+    check "token must be printable" "printable ASCII" "$("$RUST_BINARY" --admin-token "" 2>&1)"
+}
+
+test_debug_workers() {
+    echo -e "\n📍 /debug/workers"
+    start_server --workers 2
+    check "off without --debug-endpoints" "404" "$(status_of "http://localhost:$PORT/debug/workers")"
+
+    start_server --workers 2 --debug-endpoints
+    curl -s -o /dev/null "http://localhost:$PORT/longpoll?timeout=1" &
+    local parked=$!
+    sleep 0.2
+    local workers=$(curl -s "http://localhost:$PORT/debug/workers")
+    check "both workers busy" '"busy":2,"total":2' "$workers"
+    check "parked worker shows its route" '"state":"busy","route":"/longpoll"' "$workers"
+    check "reporting worker shows itself" '"state":"busy","route":"/debug/workers"' "$workers"
+    wait "$parked"
+
+    workers=$(curl -s "http://localhost:$PORT/debug/workers")
+    check "finished worker back to idle" '"state":"idle"' "$workers"
+    check "one busy: the one answering" '"busy":1' "$workers"
+}
+
+test_pool_closed() {
+    echo -e "\n📍 workers exiting mid-run"
+    start_server --workers 2 --dispatch round-robin --debug-endpoints
+
+    # The probe went to worker 0, so this parks on worker 1 and the panic hits 0
+    curl -s "http://localhost:$PORT/longpoll?timeout=1" > "$SERVER_LOG.poll" &
+    local parked=$!
+    sleep 0.2
+    check "panicking handler gets no response" "000" "$(status_of "http://localhost:$PORT/debug/panic")"
+    local codes="" i
+    for i in 1 2 3; do
+        codes+=$(status_of "http://localhost:$PORT/")
+    done
+    check "connections routed around the dead worker" "200200200" "$codes"
+    wait "$parked"
+    check "in-flight request unaffected" "waited_ms" "$(cat "$SERVER_LOG.poll")"
+    rm -f "$SERVER_LOG.poll"
+    check "dead worker reported" '"state":"exited"' "$(curl -s "http://localhost:$PORT/debug/workers")"
+
+    status_of "http://localhost:$PORT/debug/panic" >/dev/null
+    check "last connection answered 503" "503" "$(status_of "http://localhost:$PORT/")"
+    sleep 0.2
+    check "server exits once no worker is left" "exited" \
+        "$(kill -0 "$SERVER_PID" 2>/dev/null && echo running || echo exited)"
+    check "reason logged" "Every worker has exited" "$(cat "$SERVER_LOG")"
+}
+
+test_readiness_threshold() {
+    echo -e "\n📍 --readiness-threshold"
+    start_server --workers 2 --readiness-threshold 1
+    check "healthy while idle" "200" "$(status_of "http://localhost:$PORT/healthz")"
+
+    # Park both workers, then queue the check with two connections behind it
+    local out="/tmp/rust_features_ready_$$" pids=()
+    curl -s -o /dev/null "http://localhost:$PORT/longpoll?timeout=1" & pids+=($!)
+    curl -s -o /dev/null "http://localhost:$PORT/longpoll?timeout=1" & pids+=($!)
+    sleep 0.2
+    curl -s -D - "http://localhost:$PORT/healthz" > "$out" & pids+=($!)
+    sleep 0.1
+    curl -s -o /dev/null "http://localhost:$PORT/" & pids+=($!)
+    curl -s -o /dev/null "http://localhost:$PORT/" & pids+=($!)
+    wait "${pids[@]}"
+    check "503 past the threshold" "HTTP/1.1 503" "$(cat "$out")"
+    check "reports the backlog" "overloaded: 2 connections waiting" "$(cat "$out")"
+    check "asks to retry" "Retry-After: 1" "$(cat "$out")"
+    rm -f "$out"
+    check "healthy again once drained" "200" "$(status_of "http://localhost:$PORT/healthz")"
+
+    start_server --workers 1
+    curl -s -o /dev/null "http://localhost:$PORT/longpoll?timeout=1" & pids=($!)
+    sleep 0.2
+    curl -s -o "$out" -w '%{http_code}' "http://localhost:$PORT/healthz" > "$out.status" & pids+=($!)
+    curl -s -o /dev/null "http://localhost:$PORT/" & pids+=($!)
+    curl -s -o /dev/null "http://localhost:$PORT/" & pids+=($!)
+    wait "${pids[@]}"
+    check "always 200 without the flag" "200" "$(cat "$out.status")"
+    rm -f "$out" "$out.status"
+}
+
+test_warmup() {
+    echo -e "\n📍 --warmup"
+    check "zero rounds rejected" "--warmup must be at least 1" "$("$RUST_BINARY" --warmup 0 2>&1)"
+
+    start_server --warmup 3 --access-log --connection-log --log-time none
+    check "reported before serving" "Warmed up 10 routes, 3 requests each" "$(head -n 1 "$SERVER_LOG")"
+    check_absent "no failures" "Warmup:" "$(cat "$SERVER_LOG")"
+    check_absent "warmup requests not logged" "/json-tree" "$(cat "$SERVER_LOG")"
+    # start_server's /healthz probe is the only request so far
+    check "only the probe connected" "1" "$(grep -c "requests=" "$SERVER_LOG")"
+    check "warmup requests not counted" 'responses_total{status="200"} 1' "$(curl -s "http://localhost:$PORT/metrics")"
+
+    # A route that can't answer 2xx under these flags is named
+    start_server --warmup 2 --max-body 4
+    check "failing route reported" "Warmup: POST /something failed 2 of 2 times (413)" "$(cat "$SERVER_LOG")"
+    check "still serves afterwards" "200" "$(status_of "http://localhost:$PORT/")"
+}
+
+test_recycle_after() {
+    echo -e "\n📍 --recycle-after"
+    start_server --workers 1 --recycle-after 2 --debug-endpoints
+    local threads_before=$(ls "/proc/$SERVER_PID/task" | sort | tr '\n' ' ')
+
+    # start_server's health check was the first request
+    local codes="" i
+    for i in 1 2 3 4 5; do
+        codes+=$(status_of "http://localhost:$PORT/")
+    done
+    check "every request served across recycles" "200200200200200" "$codes"
+    check "recycles counted" "worker_recycled_total 3" "$(curl -s "http://localhost:$PORT/metrics")"
+    check "replaced by a new thread" "yes" \
+        "$([[ "$(ls "/proc/$SERVER_PID/task" | sort | tr '\n' ' ')" != "$threads_before" ]] && echo yes)"
+    check "slot keeps its counters" '"requests":8' "$(curl -s "http://localhost:$PORT/debug/workers")"
+    check "one worker left" '"total":1' "$(curl -s "http://localhost:$PORT/debug/workers")"
+
+    # A kept-alive connection isn't cut short; the limit is checked when it ends
+    start_server --workers 1 --recycle-after 2 --keep-alive 5
+    check "not while the connection is open" "worker_recycled_total 0" \
+        "$(raw_request "GET / HTTP/1.1\r\n\r\nGET / HTTP/1.1\r\n\r\nGET /metrics HTTP/1.1\r\nConnection: close\r\n\r\n")"
+    check "recycled once it closed" "worker_recycled_total 1" "$(curl -s "http://localhost:$PORT/metrics")"
+
+    start_server
+    check_absent "off by default" "worker_recycled_total" "$(curl -s "http://localhost:$PORT/metrics")"
+}
+
+test_barrier() {
+    echo -e "\n📍 /barrier"
+    start_server --workers 4
+
+    # Three of the four workers may park; the last one stays free for the release
+    local out="/tmp/rust_features_barrier_$$" i waiters=()
+    for i in 1 2 3; do
+        curl -s --max-time 10 "http://localhost:$PORT/barrier/wait" >"$out.$i" &
+        waiters+=($!)
+    done
+    sleep 0.5
+    check "waiter beyond the cap gets 503" "503" "$(status_of "http://localhost:$PORT/barrier/wait")"
+    check "503 is counted" 'responses_total{status="503"} 1' "$(curl -s "http://localhost:$PORT/metrics")"
+
+    check "release reports the parked waiters" '"released":3' \
+        "$(curl -s -X POST "http://localhost:$PORT/barrier/release")"
+    wait "${waiters[@]}"
+    check "all waiters released" "3" "$(cat "$out".* | grep -o '"released":true' | wc -l)"
+    rm -f "$out".*
+
+    check "unreleased wait times out" '"released":false' \
+        "$(curl -s --max-time 5 "http://localhost:$PORT/barrier/wait?timeout=1")"
+}
+
+test_dispatch() {
+    echo -e "\n📍 --dispatch"
+    start_server --workers 2
+    check "served counts per worker" 'worker_served_total{worker="1"}' "$(curl -s "http://localhost:$PORT/metrics")"
+
+    # Park one worker for two seconds, then see where the next requests land
+    start_server --workers 2 --dispatch round-robin
+    curl -s -o /dev/null "http://localhost:$PORT/longpoll?timeout=2" &
+    local parked=$!
+    sleep 0.2
+    check "round-robin queues behind a busy worker" "200 000" \
+        "$(status_of --max-time 1 "http://localhost:$PORT/") $(status_of --max-time 1 "http://localhost:$PORT/")"
+    wait "$parked"
+
+    start_server --workers 2 --dispatch least-loaded
+    curl -s -o /dev/null "http://localhost:$PORT/longpoll?timeout=2" &
+    parked=$!
+    sleep 0.2
+    local i statuses=()
+    for i in 1 2 3; do statuses+=("$(status_of --max-time 1 "http://localhost:$PORT/")"); done
+    check "least-loaded steers around it" "200 200 200" "${statuses[*]}"
+    wait "$parked"
+
+    start_server --workers 2 --dispatch random
+    for i in {1..20}; do status_of "http://localhost:$PORT/"; echo; done > /dev/null
+    local served=$(curl -s "http://localhost:$PORT/metrics" | awk '/^worker_served_total/ { printf "%s ", ($2 > 0) }')
+    check "random spreads over both workers" "1 1 " "$served"
+
+    check "unknown mode rejected" "Invalid or missing value for --dispatch" "$("$RUST_BINARY" --dispatch fastest 2>&1)"
+
+    # Connections queued behind a busy worker are served in the order they arrived
+    start_server --workers 1 --access-log --log-time none
+    curl -s -o /dev/null "http://localhost:$PORT/longpoll?timeout=1" &
+    parked=$!
+    sleep 0.2
+    local -a queued=()
+    for i in 1 2 3 4 5; do
+        curl -s -o /dev/null "http://localhost:$PORT/?n=$i" &
+        queued+=($!)
+        sleep 0.05
+    done
+    wait "$parked" "${queued[@]}"
+    check "queue is first in, first out" "n=1 n=2 n=3 n=4 n=5 " "$(grep -o 'n=[0-9]*' "$SERVER_LOG" | tr '\n' ' ')"
+
+    # Per-worker queues let a later connection overtake one behind a busy
+    # worker: round-robin puts n=1 behind the longpoll, and n=2 on the free
+    # worker, where it starts first
+    start_server --workers 2 --dispatch round-robin
+    curl -s -o /dev/null "http://localhost:$PORT/longpoll?timeout=1" &
+    parked=$!
+    sleep 0.2
+    curl -s -o /dev/null "http://localhost:$PORT/?n=0"
+    curl -s -o /dev/null "http://localhost:$PORT/?n=1" &
+    queued=($!)
+    sleep 0.1
+    curl -s -o /dev/null "http://localhost:$PORT/?n=2"
+    wait "$parked" "${queued[@]}"
+    check "overtaking counted" "dispatch_out_of_order_total 1" "$(curl -s "http://localhost:$PORT/metrics")"
+}
+
+test_budget() {
+    echo -e "\n📍 --budget"
+    start_server --workers 1 --budget /=100,/compute=5000
+
+    # Park the only worker so the next requests queue for about a second
+    curl -s -o /dev/null "http://localhost:$PORT/longpoll?timeout=1" &
+    local parked=$!
+    sleep 0.2
+    check "queued past its budget gets 503" "503" "$(status_of "http://localhost:$PORT/")"
+    wait "$parked"
+
+    curl -s -o /dev/null "http://localhost:$PORT/longpoll?timeout=1" &
+    parked=$!
+    sleep 0.2
+    check "within a generous budget is served" "200" "$(status_of "http://localhost:$PORT/compute?fib=1")"
+    wait "$parked"
+
+    curl -s -o /dev/null "http://localhost:$PORT/longpoll?timeout=1" &
+    parked=$!
+    sleep 0.2
+    check "routes without a budget are never shed" "200" "$(status_of "http://localhost:$PORT/something")"
+    wait "$parked"
+
+    check "no queue, no shedding" "200" "$(status_of "http://localhost:$PORT/")"
+    local metrics=$(curl -s "http://localhost:$PORT/metrics")
+    check "shed count exposed" 'shed_total{route="/"} 1' "$metrics"
+    check "unshed routes report zero" 'shed_total{route="/compute"} 0' "$metrics"
+}
+
+test_adaptive_concurrency() {
+    echo -e "\n📍 --adaptive-concurrency"
+    check "target without the controller rejected" "--latency-target needs --adaptive-concurrency" \
+        "$("$RUST_BINARY" --latency-target 20 2>&1)"
+    check "zero target rejected" "--latency-target must be at least 1" \
+        "$("$RUST_BINARY" --adaptive-concurrency --latency-target 0 2>&1)"
+
+    start_server --workers 4 --adaptive-concurrency --latency-target 20
+    check "cap starts at the pool size" "adaptive_concurrency_limit 4" "$(curl -s "http://localhost:$PORT/metrics")"
+
+    # A second per request is far over the target: the cap halves
+    local -a parked=()
+    for i in 1 2 3 4; do
+        curl -s -o /dev/null "http://localhost:$PORT/longpoll?timeout=1" &
+        parked+=($!)
+    done
+    wait "${parked[@]}"
+    check "slow window shrinks the cap" "adaptive_concurrency_limit 2" "$(curl -s "http://localhost:$PORT/metrics")"
+
+    parked=()
+    for i in 1 2; do
+        curl -s -o /dev/null "http://localhost:$PORT/longpoll?timeout=1" &
+        parked+=($!)
+    done
+    sleep 0.2
+    check "past the cap gets 503" "503" "$(status_of "http://localhost:$PORT/")"
+    check "metrics are never shed" "200" "$(status_of "http://localhost:$PORT/metrics")"
+    wait "${parked[@]}"
+    local metrics=$(curl -s "http://localhost:$PORT/metrics")
+    check "cap never drops below one" "adaptive_concurrency_limit 1" "$metrics"
+    check "rejections counted" "adaptive_rejected_total 1" "$metrics"
+
+    # Fast requests that fill the cap raise it again, one window at a time
+    for i in 1 2 3 4; do
+        sleep 0.15
+        curl -s -o /dev/null "http://localhost:$PORT/"
+    done
+    check "fast windows grow the cap" "adaptive_concurrency_limit 2" "$(curl -s "http://localhost:$PORT/metrics")"
+}
+
+# Open $1 idle connections to the server, leaving their fds in HELD_FDS
+hold_connections() {
+    HELD_FDS=()
+    local i fd
+    for ((i = 0; i < $1; i++)); do
+        exec {fd}<>"/dev/tcp/localhost/$PORT"
+        HELD_FDS+=("$fd")
+    done
+}
+
+release_connections() {
+    local fd
+    for fd in "${HELD_FDS[@]}"; do
+        exec {fd}>&-
+    done
+    HELD_FDS=()
+}
+
+test_accept_backoff() {
+    echo -e "\n📍 --accept-backoff-ms"
+    start_server --accept-backoff-ms 5 --accept-backoff-max-ms 40
+    # Few enough descriptors that idle connections use them all up
+    prlimit --pid "$SERVER_PID" --nofile=16:16
+
+    hold_connections 20
+    sleep 0.5
+    local log=$(cat "$SERVER_LOG")
+    check "EMFILE reported" "Too many open files" "$log"
+    check "first pause is the minimum" "retrying in 5ms" "$log"
+    check "pause doubles" "retrying in 10ms" "$log"
+    check "pause capped" "yes" \
+        "$(grep -q 'retrying in 40ms' <<< "$log" && ! grep -q 'retrying in 80ms' <<< "$log" && echo yes)"
+    release_connections
+    check "serves again once descriptors free up" "200" "$(status_of "http://localhost:$PORT/healthz")"
+    check "recovery logged" "accept recovered after" "$(cat "$SERVER_LOG")"
+
+    # A second bout starts from the minimum again
+    hold_connections 20
+    sleep 0.2
+    check "backoff reset after a successful accept" "retrying in 5ms" \
+        "$(sed -n '/accept recovered/,$p' "$SERVER_LOG")"
+    release_connections
+    check "invalid cap rejected" "can't be below" \
+        "$("$RUST_BINARY" --accept-backoff-ms 50 --accept-backoff-max-ms 10 2>&1)"
+}
+
+test_latency_buckets() {
+    echo -e "\n📍 --latency-buckets"
+    start_server --latency-buckets 0.001,500,2000
+
+    curl -s -o /dev/null "http://localhost:$PORT/"
+    curl -s -o /dev/null "http://localhost:$PORT/longpoll?timeout=1"
+    local metrics=$(curl -s "http://localhost:$PORT/metrics")
+    check "declared as a histogram" "# TYPE request_duration_seconds histogram" "$metrics"
+    check "nothing under a microsecond" 'request_duration_seconds_bucket{le="0.000001"} 0' "$metrics"
+    # The readiness probe and / are quick; the long-poll takes a second
+    check "buckets are cumulative" 'request_duration_seconds_bucket{le="0.5"} 2' "$metrics"
+    check "slow request in a higher bucket" 'request_duration_seconds_bucket{le="2"} 3' "$metrics"
+    check "+Inf holds everything" 'request_duration_seconds_bucket{le="+Inf"} 3' "$metrics"
+    check "count matches" "request_duration_seconds_count 3" "$metrics"
+
+    # Idle time before a request, on a new or a kept-alive connection, isn't part of it
+    start_server --latency-buckets 500 --keep-alive 5
+    slow_request 0.6 "" "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n" \
+        "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n" >/dev/null
+    check "idle time not counted" 'request_duration_seconds_bucket{le="0.5"} 3' "$(curl -s "http://localhost:$PORT/metrics")"
+    check "unsorted bounds rejected" "ascending order" "$("$RUST_BINARY" --latency-buckets 5,1 2>&1)"
+}
+
+# Print the Content-Type of a response
+content_type_of() {
+    curl -s -o /dev/null -w '%{content_type}' --max-time 5 "$@"
+}
+
+test_content_types() {
+    echo -e "\n📍 Static content types"
+    mkdir -p "$STATIC_DIR"
+    for name in index.html style.css app.js logo.png LOGO.PNG data.unknownext; do
+        echo "x" > "$STATIC_DIR/$name"
+    done
+    start_server --static "$STATIC_DIR"
+
+    local url="http://localhost:$PORT/static"
+    check ".html" "text/html" "$(content_type_of "$url/index.html")"
+    check ".css" "text/css" "$(content_type_of "$url/style.css")"
+    check ".js" "application/javascript" "$(content_type_of "$url/app.js")"
+    check ".png" "image/png" "$(content_type_of "$url/logo.png")"
+    check "extension match ignores case" "image/png" "$(content_type_of "$url/LOGO.PNG")"
+    check "unknown extension gets the default" "application/octet-stream" \
+        "$(content_type_of "$url/data.unknownext")"
+
+    start_server --static "$STATIC_DIR" --default-content-type text/plain
+    check "--default-content-type applies" "text/plain" "$(content_type_of "$url/data.unknownext")"
+    check "known types unaffected" "image/png" "$(content_type_of "$url/logo.png")"
+}
+
+# Print the client column of the access log line for the given path
+logged_ip() {
+    sleep 0.1
+    grep -F "\"GET $1\"" "$SERVER_LOG" | tail -1 | cut -d' ' -f1
+}
+
+test_trust_proxy() {
+    echo -e "\n📍 --trust-proxy"
+    start_server --access-log --log-time none --trust-proxy
+
+    curl -s -o /dev/null -H "X-Forwarded-For: 203.0.113.9, 198.51.100.7" "http://localhost:$PORT/?xff"
+    check "X-Forwarded-For uses the last hop" "198.51.100.7" "$(logged_ip "/?xff")"
+
+    curl -s -o /dev/null -H 'Forwarded: for=203.0.113.9, for="[2001:db8::1]:4711";proto=http' \
+        "http://localhost:$PORT/?fwd"
+    check "Forwarded with quoted IPv6 and port" "2001:db8::1" "$(logged_ip "/?fwd")"
+
+    curl -s -o /dev/null -H "Forwarded: for=192.0.2.60" -H "X-Forwarded-For: 198.51.100.7" \
+        "http://localhost:$PORT/?both"
+    check "Forwarded preferred over X-Forwarded-For" "192.0.2.60" "$(logged_ip "/?both")"
+
+    curl -s -o /dev/null -H "Forwarded: for=unknown" "http://localhost:$PORT/?unknown"
+    check "unusable node falls back to the peer" "127.0.0.1" "$(logged_ip "/?unknown")"
+
+    # Without the flag the headers are client-controlled and must be ignored
+    start_server --access-log --log-time none
+    curl -s -o /dev/null -H "X-Forwarded-For: 198.51.100.7" -H "Forwarded: for=192.0.2.60" \
+        "http://localhost:$PORT/?spoof"
+    check "headers ignored without --trust-proxy" "127.0.0.1" "$(logged_ip "/?spoof")"
+}
+
+test_log_format() {
+    echo -e "\n📍 --log-format"
+    start_server --access-log --log-format combined
+    curl -s -o /dev/null -H "Referer: http://example.com/page" -A 'bench "quoted" agent' "http://localhost:$PORT/?combined"
+    curl -s -o /dev/null -A '' "http://localhost:$PORT/?bare"
+    sleep 0.1
+    local line=$(grep '?combined' "$SERVER_LOG")
+    check "client, identity and user first" "127.0.0.1 - - [" "$line"
+    check "CLF timestamp" "yes" \
+        "$([[ "$line" =~ \[[0-9]{2}/[A-Z][a-z]{2}/[0-9]{4}:[0-9]{2}:[0-9]{2}:[0-9]{2}\ \+0000\] ]] && echo yes || echo "$line")"
+    check "request line, status, bytes, referer and agent" \
+        '"GET /?combined HTTP/1.1" 200 16 "http://example.com/page" "bench \"quoted\" agent"' "$line"
+    check "missing headers logged as -" '"GET /?bare HTTP/1.1" 200 16 "-" "-"' "$(grep '?bare' "$SERVER_LOG")"
+
+    start_server --access-log --log-format common
+    curl -s -o /dev/null -H "Referer: http://example.com/page" "http://localhost:$PORT/?common"
+    sleep 0.1
+    line=$(grep '?common' "$SERVER_LOG")
+    check "common stops after the bytes" "yes" "$([[ "$line" == *'"GET /?common HTTP/1.1" 200 16' ]] && echo yes || echo "$line")"
+
+    check "unknown format rejected" "Invalid or missing value for --log-format" "$("$RUST_BINARY" --log-format json 2>&1)"
+}
+
+test_prefork() {
+    echo -e "\n📍 --prefork"
+    start_server --prefork 2
+
+    local children
+    children=$(pgrep -P "$SERVER_PID" | wc -l)
+    check "supervisor forks two children" "2" "$children"
+
+    # Kill one child; the supervisor should notice and fork a replacement
+    kill -9 "$(pgrep -P "$SERVER_PID" | head -1)"
+    sleep 1.5
+    check "dead child is reported" "restarting" "$(cat "$SERVER_LOG")"
+    check "replacement child forked" "2" "$(pgrep -P "$SERVER_PID" | wc -l)"
+    check "still serving after restart" "200" "$(status_of "http://localhost:$PORT/")"
+
+    check "zero processes rejected" "at least one process" "$("$RUST_BINARY" --prefork 0 2>&1)"
+}
+
+main() {
+    cd "$SCRIPT_DIR"
+
+    echo "🔨 Building Rust server..."
+    build_server
+
+    test_config_file
+    test_dump_requests
+    test_tee_bodies
+    test_h2_preface
+    test_http_version
+    test_server_timing
+    test_count_allocs
+    test_date_header
+    test_security_headers
+    test_received_header
+    test_drop_percent
+    test_accept_backoff
+    test_max_body
+    test_on_bad_request
+    test_content_length_parsing
+    test_no_content_length
+    test_max_uri
+    test_max_header_line
+    test_keep_alive
+    test_shutdown
+    test_connection_log
+    test_max_bytes_per_connection
+    test_centralized_writer
+    test_line_endings
+    test_upload_rate
+    test_body_read_chunk_size
+    test_upload_file
+    test_compute
+    test_json_tree
+    test_schema
+    test_accept
+    test_cookies
+    test_static_gzip
+    test_content_types
+    test_static_ranges
+    test_digest_trailer
+    test_notsent_lowat
+    test_connect
+    test_upgrade
+    test_bad_chunked
+    test_bad_length
+    test_stream_flush
+    test_dribble
+    test_sysstat
+    test_debug_workers
+    test_admin_routes
+    test_pool_closed
+    test_readiness_threshold
+    test_recycle_after
+    test_warmup
+    test_barrier
+    test_dispatch
+    test_budget
+    test_adaptive_concurrency
+    test_latency_buckets
+    test_trust_proxy
+    test_log_format
+    test_prefork
+
+    echo -e "\n━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━"
+    if [[ $FAILED -eq 0 ]]; then
+        echo "✅ All $PASSED checks passed!"
+    else
+        echo "❌ $FAILED of $((PASSED + FAILED)) checks failed"
+    fi
+    echo "━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━"
+    [[ $FAILED -eq 0 ]]
+}
+
+main "$@"